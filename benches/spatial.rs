@@ -0,0 +1,102 @@
+//! Compares finding the walls the player collides with by checking every wall, as
+//! `ControlSystem` used to, with querying a `SpatialIndex`, on a 500×500 maze.
+
+#![feature(test)]
+
+#[macro_use]
+extern crate frunk;
+extern crate game;
+extern crate test;
+
+use game::{
+    components::{CollisionComponent, LocationComponent},
+    spatial::SpatialIndex,
+    World,
+};
+use test::{black_box, Bencher};
+
+/// The width and depth of the maze.
+const SIZE: usize = 500;
+
+/// Creates a world with a wall on every even row and column of a 500×500 grid, except for
+/// scattered gaps, along with an index of the walls.
+fn maze() -> (World, SpatialIndex) {
+    let mut world = World::default();
+    let mut index = SpatialIndex::default();
+    for x in 0..SIZE {
+        for z in 0..SIZE {
+            if (x % 2 == 0 || z % 2 == 0) && (x * 7 + z * 13) % 5 != 0 {
+                let loc = LocationComponent::pos(x as f32 + 0.5, 0.5, z as f32 + 0.5);
+                let wall = world.new_entity(hlist![CollisionComponent, loc]);
+                index.insert(wall, loc.xyz, loc.radius());
+            }
+        }
+    }
+    (world, index)
+}
+
+/// Returns where the player is on each step of the benchmark: a diagonal walk across the maze.
+fn player_locations() -> Vec<LocationComponent> {
+    (0..16)
+        .map(|i| {
+            let t = (i * SIZE / 16) as f32 + 1.5;
+            let mut loc = LocationComponent::pos(t, 0.25, t);
+            loc.scale = 0.2;
+            loc
+        }).collect()
+}
+
+#[bench]
+fn collisions_by_iteration(b: &mut Bencher) {
+    let (world, _) = maze();
+    let players = player_locations();
+    b.iter(|| {
+        for player in &players {
+            let collides = world
+                .iter::<Hlist![&CollisionComponent, &LocationComponent]>()
+                .any(|(_, hlist_pat![_, loc])| player.collides(loc));
+            black_box(collides);
+        }
+    });
+}
+
+#[bench]
+fn collisions_by_index(b: &mut Bencher) {
+    let (world, index) = maze();
+    let players = player_locations();
+    b.iter(|| {
+        for player in &players {
+            let collides = index
+                .query_radius(player.xyz, player.radius())
+                .into_iter()
+                .filter_map(|wall| world.get_one::<LocationComponent>(wall))
+                .any(|loc| player.collides(loc));
+            black_box(collides);
+        }
+    });
+}
+
+#[bench]
+fn nearest_wall(b: &mut Bencher) {
+    let (world, index) = maze();
+    let players = player_locations();
+    b.iter(|| {
+        for player in &players {
+            black_box(index.nearest::<CollisionComponent>(&world, player.xyz));
+        }
+    });
+}
+
+#[bench]
+fn index_build(b: &mut Bencher) {
+    let (world, _) = maze();
+    b.iter(|| {
+        let mut index = SpatialIndex::default();
+        for (wall, hlist_pat![_, loc]) in
+            world.iter::<Hlist![&CollisionComponent, &LocationComponent]>()
+        {
+            index.insert(wall, loc.xyz, loc.radius());
+        }
+        black_box(index.len());
+    });
+}
@@ -1,3 +1,4 @@
+#[macro_use]
 extern crate failure;
 extern crate game;
 #[macro_use]
@@ -8,10 +9,19 @@ extern crate structopt;
 
 use failure::Fallible;
 use game::{
+    generate::{Algorithm, Generator},
+    lint::{lint, Severity},
+    preview::Preview,
+    solve::{solve, Verdict},
     util::{log_err, read_file_and_parse_to},
     Map,
 };
-use std::{fs::File, io::stdout, path::PathBuf, process::exit};
+use std::{
+    fs::File,
+    io::{stdout, Write},
+    path::PathBuf,
+    process::exit,
+};
 use structopt::StructOpt;
 
 fn main() {
@@ -35,10 +45,6 @@ pub struct Options {
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     pub verbose: usize,
 
-    /// The path of the non-JSON map file to load.
-    #[structopt(parse(from_os_str))]
-    pub input_path: PathBuf,
-
     /// The subcommand to run.
     #[structopt(subcommand)]
     pub command: Command,
@@ -49,6 +55,10 @@ pub enum Command {
     /// "Upgrades" from the assignment's format to the JSON format.
     #[structopt(name = "upgrade")]
     Upgrade {
+        /// The path of the non-JSON map file to load.
+        #[structopt(parse(from_os_str))]
+        input_path: PathBuf,
+
         /// The path of the JSON map file to save to.
         #[structopt(parse(from_os_str))]
         output_path: Option<PathBuf>,
@@ -57,6 +67,96 @@ pub enum Command {
         #[structopt(short = "p", long = "pretty")]
         pretty: bool,
     },
+
+    /// "Downgrades" from the JSON format to the assignment's format, reporting anything that
+    /// the assignment's format can't hold.
+    #[structopt(name = "downgrade")]
+    Downgrade {
+        /// The path of the map file to load.
+        #[structopt(parse(from_os_str))]
+        input_path: PathBuf,
+
+        /// The path of the non-JSON map file to save to.
+        #[structopt(parse(from_os_str))]
+        output_path: Option<PathBuf>,
+    },
+
+    /// Checks the map for issues, exiting with an error if any would stop it from loading.
+    #[structopt(name = "lint")]
+    Lint {
+        /// The path of the map file to load.
+        #[structopt(parse(from_os_str))]
+        input_path: PathBuf,
+    },
+
+    /// Finds the shortest way to complete the map, exiting with an error if there's none.
+    #[structopt(name = "solve")]
+    Solve {
+        /// The path of the map file to load.
+        #[structopt(parse(from_os_str))]
+        input_path: PathBuf,
+
+        /// Whether to output the solution or the reason there's none as JSON.
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Draws the map from above, as a PNG or SVG image depending on the output's extension.
+    #[structopt(name = "preview")]
+    Preview {
+        /// The path of the map file to load.
+        #[structopt(parse(from_os_str))]
+        input_path: PathBuf,
+
+        /// The path of the image file to save to, ending in .png or .svg.
+        #[structopt(parse(from_os_str))]
+        output_path: PathBuf,
+
+        /// The number of pixels per tile.
+        #[structopt(long = "scale", default_value = "16")]
+        scale: u32,
+
+        /// Whether to draw the shortest way through the map.
+        #[structopt(long = "solution")]
+        solution: bool,
+    },
+
+    /// Generates a maze, with keys and doors placed so that it can always be completed.
+    #[structopt(name = "generate")]
+    Generate {
+        /// The path of the map file to save to.
+        #[structopt(parse(from_os_str))]
+        output_path: Option<PathBuf>,
+
+        /// The width of the map, in tiles.
+        #[structopt(long = "width", default_value = "15")]
+        width: usize,
+
+        /// The height of the map, in tiles.
+        #[structopt(long = "height", default_value = "15")]
+        height: usize,
+
+        /// The seed for the random number generator. The same seed always generates the same
+        /// map.
+        #[structopt(short = "s", long = "seed", default_value = "0")]
+        seed: u64,
+
+        /// The algorithm to carve the maze with: backtracker, prim, kruskal, or wilson.
+        #[structopt(short = "a", long = "algorithm", default_value = "backtracker")]
+        algorithm: Algorithm,
+
+        /// The number of key and door pairs to place.
+        #[structopt(short = "k", long = "keys", default_value = "1")]
+        keys: usize,
+
+        /// Whether to write the assignment's format rather than JSON.
+        #[structopt(long = "legacy")]
+        legacy: bool,
+
+        /// Whether to pretty-print the JSON.
+        #[structopt(short = "p", long = "pretty")]
+        pretty: bool,
+    },
 }
 
 impl Options {
@@ -72,14 +172,15 @@ impl Options {
 }
 
 fn run(options: Options) -> Fallible<()> {
-    let map: Map = read_file_and_parse_to(options.input_path)?;
     match options.command {
         Command::Upgrade {
+            input_path,
             output_path,
             pretty,
         } => {
+            let map: Map = read_file_and_parse_to(input_path)?;
             if let Some(output_path) = output_path {
-                let file = File::open(output_path)?;
+                let file = File::create(output_path)?;
                 if pretty {
                     serde_json::to_writer_pretty(file, &map)?;
                 } else {
@@ -95,5 +196,126 @@ fn run(options: Options) -> Fallible<()> {
 
             Ok(())
         }
+        Command::Downgrade {
+            input_path,
+            output_path,
+        } => {
+            let map = Map::load(input_path)?;
+            for loss in map.legacy_losses() {
+                eprintln!("Dropped: {}", loss);
+            }
+
+            if let Some(output_path) = output_path {
+                write!(File::create(output_path)?, "{}", map)?;
+            } else {
+                print!("{}", map);
+            }
+            Ok(())
+        }
+        Command::Lint { input_path } => {
+            let issues = lint(&Map::load(input_path)?);
+            for issue in &issues {
+                println!("{}", issue);
+            }
+
+            let errors = issues
+                .iter()
+                .filter(|issue| issue.severity == Severity::Error)
+                .count();
+            if errors > 0 {
+                bail!("Found {} errors and {} warnings", errors, issues.len() - errors);
+            }
+            Ok(())
+        }
+        Command::Solve { input_path, json } => {
+            let verdict = solve(&Map::load(input_path)?)?;
+            if json {
+                serde_json::to_writer(stdout(), &verdict)?;
+                println!();
+            } else {
+                match verdict {
+                    Verdict::Solvable(ref solution) => {
+                        let moves = solution
+                            .moves
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<String>();
+                        println!("Solvable in {} moves: {}", solution.moves.len(), moves);
+                        for &(x, y, letter) in &solution.keys {
+                            println!("Pick up the key {} at ({}, {})", letter, x, y);
+                        }
+                    }
+                    Verdict::Unsolvable(ref why) => println!("Unsolvable: {}", why),
+                }
+            }
+
+            match verdict {
+                Verdict::Solvable(_) => Ok(()),
+                Verdict::Unsolvable(_) => exit(1),
+            }
+        }
+        Command::Preview {
+            input_path,
+            output_path,
+            scale,
+            solution,
+        } => {
+            let map = Map::load(input_path)?;
+            let solution = if solution {
+                match solve(&map)? {
+                    Verdict::Solvable(solution) => Some(solution),
+                    Verdict::Unsolvable(why) => {
+                        warn!("Not drawing a solution: {}", why);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let preview = Preview::new(&map, solution.as_ref());
+            match output_path.extension().and_then(|ext| ext.to_str()) {
+                Some("png") => preview.to_png(scale).save(&output_path)?,
+                Some("svg") => write!(File::create(&output_path)?, "{}", preview.to_svg(scale))?,
+                _ => bail!(
+                    "Can't tell what format to save {} in; it should end in .png or .svg",
+                    output_path.display()
+                ),
+            }
+            Ok(())
+        }
+        Command::Generate {
+            output_path,
+            width,
+            height,
+            seed,
+            algorithm,
+            keys,
+            legacy,
+            pretty,
+        } => {
+            let generator = Generator {
+                dims: (width, height),
+                algorithm,
+                keys,
+                seed,
+            };
+            let map = generator.generate()?;
+
+            let mut out: Box<dyn Write> = match output_path {
+                Some(output_path) => Box::new(File::create(output_path)?),
+                None => Box::new(stdout()),
+            };
+            if legacy {
+                write!(out, "{}", map)?;
+            } else if pretty {
+                serde_json::to_writer_pretty(&mut out, &map)?;
+                writeln!(out)?;
+            } else {
+                serde_json::to_writer(&mut out, &map)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
     }
 }
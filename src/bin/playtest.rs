@@ -0,0 +1,74 @@
+extern crate failure;
+extern crate game;
+#[macro_use]
+extern crate log;
+extern crate stderrlog;
+extern crate structopt;
+
+use failure::Fallible;
+use game::{
+    playtest::{script_path, Playtest},
+    util::log_err,
+};
+use std::{path::PathBuf, process::exit};
+use structopt::StructOpt;
+
+fn main() {
+    let options = Options::from_args();
+    options.start_logger();
+
+    if let Err(err) = run(options) {
+        log_err(err);
+        exit(1);
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(raw(setting = "::structopt::clap::AppSettings::ColoredHelp"))]
+pub struct Options {
+    /// Turns off message output.
+    #[structopt(short = "q", long = "quiet")]
+    pub quiet: bool,
+
+    /// Increases the verbosity. Default verbosity is errors only.
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    pub verbose: usize,
+
+    /// The path of the map file to play.
+    #[structopt(parse(from_os_str))]
+    pub map_path: PathBuf,
+
+    /// The path of the script to play the map with. Defaults to the map's path with `.playtest`
+    /// appended.
+    #[structopt(short = "s", long = "script", parse(from_os_str))]
+    pub script_path: Option<PathBuf>,
+}
+
+impl Options {
+    /// Sets up logging as specified by the `-q` and `-v` flags.
+    pub fn start_logger(&self) {
+        if !self.quiet {
+            let r = stderrlog::new().verbosity(self.verbose).init();
+            if let Err(err) = r {
+                error!("Logging couldn't start: {}", err);
+            }
+        }
+    }
+}
+
+fn run(options: Options) -> Fallible<()> {
+    let script_path = match options.script_path {
+        Some(path) => path,
+        None => script_path(&options.map_path),
+    };
+    let playtest = Playtest::run_files(&options.map_path, &script_path)?;
+    if playtest.state().is_done() {
+        println!("Reached the goal after {} ticks", playtest.ticks());
+    } else {
+        println!(
+            "Finished the script after {} ticks, without reaching the goal",
+            playtest.ticks()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Moving a round body around obstacles on the floor, sliding along them rather than stopping
+//! dead when it runs into them.
+//!
+//! Everything happens in the x-z plane, in which the body is a circle and each obstacle is an
+//! axis-aligned box. A move is made in small steps, after each of which the body is pushed out of
+//! the boxes it has ended up overlapping, deepest first. Since it's pushed straight out from the
+//! nearest point of each box, the part of the move along the box's face is kept, so the body
+//! slides along walls, and comes to rest in corners where the pushes from two walls cancel its
+//! move out.
+
+use cgmath::{InnerSpace, Point2, Vector2};
+
+/// How far a body may move in one step, as a fraction of its radius. Keeping steps shorter than
+/// the body is wide stops it from passing through thin obstacles.
+const MAX_STEP: f32 = 0.5;
+
+/// How many times a body is pushed out of a box it overlaps after each step. Pushing it out of one
+/// box may leave it overlapping another, but a few rounds are enough to settle it in any corner.
+const ITERATIONS: usize = 4;
+
+/// An obstacle, as an axis-aligned box in the x-z plane. The `y` coordinates of its corners are
+/// world `z` coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    /// The corner with the smallest coordinates.
+    pub min: Point2<f32>,
+
+    /// The corner with the largest coordinates.
+    pub max: Point2<f32>,
+}
+
+impl Aabb {
+    /// Creates a box with the given center and width, like the cube a `LocationComponent` with
+    /// that position and scale is rendered as.
+    pub fn around(center: Point2<f32>, size: f32) -> Aabb {
+        let half = Vector2::new(size / 2.0, size / 2.0);
+        Aabb {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    /// Returns how far a circle has to move to stop overlapping the box, or `None` if it doesn't
+    /// overlap it. A circle whose center is outside the box is pushed straight away from the
+    /// nearest point of the box, and one whose center is inside is pushed out through the nearest
+    /// side.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate cgmath;
+    /// # extern crate game;
+    /// # use cgmath::{Point2, Vector2};
+    /// # use game::collision::Aabb;
+    /// # fn main() {
+    /// let wall = Aabb::around(Point2::new(0.5, 0.5), 1.0);
+    /// assert_eq!(wall.push_out(Point2::new(1.5, 0.5), 0.25), None);
+    /// assert_eq!(
+    ///     wall.push_out(Point2::new(1.125, 0.5), 0.25),
+    ///     Some(Vector2::new(0.125, 0.0))
+    /// );
+    /// assert_eq!(
+    ///     wall.push_out(Point2::new(0.5, 0.875), 0.25),
+    ///     Some(Vector2::new(0.0, 0.375))
+    /// );
+    /// # }
+    /// ```
+    pub fn push_out(&self, center: Point2<f32>, radius: f32) -> Option<Vector2<f32>> {
+        let nearest = Point2::new(
+            center.x.max(self.min.x).min(self.max.x),
+            center.y.max(self.min.y).min(self.max.y),
+        );
+        let offset = center - nearest;
+        let distance = offset.magnitude();
+        if distance >= radius {
+            None
+        } else if distance > 0.0 {
+            Some(offset * ((radius - distance) / distance))
+        } else {
+            let pushes = [
+                Vector2::new(self.min.x - radius - center.x, 0.0),
+                Vector2::new(self.max.x + radius - center.x, 0.0),
+                Vector2::new(0.0, self.min.y - radius - center.y),
+                Vector2::new(0.0, self.max.y + radius - center.y),
+            ];
+            let mut shortest = pushes[0];
+            for &push in &pushes[1..] {
+                if push.magnitude() < shortest.magnitude() {
+                    shortest = push;
+                }
+            }
+            Some(shortest)
+        }
+    }
+}
+
+/// Moves a circle from a point by the given amount, sliding along the boxes in its way. Returns
+/// where it ends up.
+///
+/// # Example
+///
+/// ```
+/// # extern crate cgmath;
+/// # extern crate game;
+/// # use cgmath::{Point2, Vector2};
+/// # use game::collision::{slide, Aabb};
+/// # fn main() {
+/// // A wall along the x axis, made of two tiles.
+/// let walls = [
+///     Aabb::around(Point2::new(0.5, -0.5), 1.0),
+///     Aabb::around(Point2::new(1.5, -0.5), 1.0),
+/// ];
+///
+/// // Moving diagonally into the wall slides along it, across the seam between the tiles.
+/// let end = slide(Point2::new(0.5, 0.5), Vector2::new(1.0, -1.0), 0.25, &walls);
+/// assert!((end.x - 1.5).abs() < 1e-5);
+/// assert!((end.y - 0.25).abs() < 1e-5);
+/// # }
+/// ```
+pub fn slide(start: Point2<f32>, motion: Vector2<f32>, radius: f32, boxes: &[Aabb]) -> Point2<f32> {
+    let steps = if radius > 0.0 {
+        (motion.magnitude() / (radius * MAX_STEP)).ceil().max(1.0)
+    } else {
+        1.0
+    };
+    let step = motion / steps;
+
+    let mut pos = start;
+    for _ in 0..steps as usize {
+        pos += step;
+        for _ in 0..ITERATIONS {
+            // Push the body out of the box it overlaps most first. Where two boxes meet to make a
+            // flat wall, that's the one it's moving into, so the corner of the other can't nudge
+            // it along the wall.
+            let mut deepest: Option<Vector2<f32>> = None;
+            for aabb in boxes {
+                if let Some(push) = aabb.push_out(pos, radius) {
+                    if deepest.map_or(true, |deepest| push.magnitude() > deepest.magnitude()) {
+                        deepest = Some(push);
+                    }
+                }
+            }
+            match deepest {
+                Some(push) => pos += push,
+                None => break,
+            }
+        }
+    }
+    pos
+}
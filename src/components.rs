@@ -1,10 +1,14 @@
 //! Common components.
 
-use cgmath::{Deg, InnerSpace, Matrix3, Matrix4, Point3, Vector3, Vector4};
+use cgmath::{Deg, InnerSpace, Matrix3, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
 pub use crate::gui::{DecalComponent, RenderComponent};
+use crate::Entity;
+use std::borrow::Cow;
 
-/// A component for an object having a location.
-#[derive(Copy, Clone, Debug)]
+/// A component for an object having a location. When deserializing, missing fields are taken
+/// from `LocationComponent::default()`.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct LocationComponent {
     /// The location of the object.
     pub xyz: Point3<f32>,
@@ -71,11 +75,50 @@ impl LocationComponent {
         Matrix4::look_at_dir(self.xyz, self.forward(), Vector3::new(0.0, 1.0, 0.0))
     }
 
+    /// Returns the radius of the object, for collisions.
+    pub fn radius(&self) -> f32 {
+        self.scale * 2f32.sqrt() / 2.0
+    }
+
     /// Returns whether the two objects collide.
     pub fn collides(&self, other: &LocationComponent) -> bool {
         let distance = (self.xyz - other.xyz).magnitude();
-        let min_distance = (self.scale + other.scale) * 2f32.sqrt() / 2.0;
-        distance < min_distance
+        distance < self.radius() + other.radius()
+    }
+
+    /// Interpolates between two locations, where `t = 0.0` gives this location and `t = 1.0`
+    /// gives the other. Each angle turns the short way around.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate game;
+    /// # use game::components::LocationComponent;
+    /// # fn main() {
+    /// let a = LocationComponent::pos(0.0, 0.0, 0.0).rotate_by(0.0, 350.0);
+    /// let b = LocationComponent::pos(2.0, 0.0, 0.0).rotate_by(0.0, 10.0);
+    /// let mid = a.lerp(&b, 0.5);
+    /// assert_eq!(mid.xyz.x, 1.0);
+    /// assert_eq!(mid.rotation[1] % 360.0, 0.0);
+    /// # }
+    /// ```
+    pub fn lerp(&self, other: &LocationComponent, t: f32) -> LocationComponent {
+        let mut rotation = self.rotation;
+        for i in 0..3 {
+            let mut diff = (other.rotation[i] - self.rotation[i]) % 360.0;
+            if diff > 180.0 {
+                diff -= 360.0;
+            } else if diff < -180.0 {
+                diff += 360.0;
+            }
+            rotation[i] += diff * t;
+        }
+
+        LocationComponent {
+            xyz: self.xyz + (other.xyz - self.xyz) * t,
+            rotation,
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
     }
 }
 
@@ -92,38 +135,146 @@ impl Default for LocationComponent {
 impl_Component!(LocationComponent);
 
 /// A component for the camera.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CameraComponent;
 
 impl_Component!(CameraComponent);
 
 /// A component for a door.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct DoorComponent(pub char);
 
 impl_Component!(DoorComponent);
 
+/// A component for an enemy, which patrols between tiles until it sees the player, then chases
+/// them. It's moved by `EnemySystem`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct EnemyComponent {
+    /// The tiles the enemy patrols between, in order, returning to the first after the last.
+    /// The enemy starts on the first.
+    pub patrol: Vec<(usize, usize)>,
+
+    /// The index of the waypoint in `patrol` the enemy is heading to.
+    #[serde(default)]
+    pub next: usize,
+
+    /// The tile the player was last seen on, if the enemy is chasing them.
+    #[serde(default)]
+    pub last_seen: Option<(usize, usize)>,
+
+    /// The tiles on the way to where the enemy is heading, from the tile it was on when the way
+    /// was found. It's only found again when the enemy heads somewhere else, such as the player
+    /// moving to another tile while being chased, or when it strays from it. This isn't saved.
+    #[serde(skip)]
+    pub path: Vec<(usize, usize)>,
+}
+
+impl_Component!(EnemyComponent);
+
 /// A component for the goal location.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct GoalComponent;
 
 impl_Component!(GoalComponent);
 
 /// A component for a key.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct KeyComponent {
     /// The letter of the key.
     pub letter: char,
-
-    /// Whether the key is held by the player.
-    pub held: bool,
 }
 
 impl_Component!(KeyComponent);
 
-/// A component to allow checking for collisions, based on the radius of the object's
-/// LocationComponent. The boolean is whether the object obstructs movement.
-#[derive(Copy, Clone, Debug, Default)]
-pub struct CollisionComponent(pub bool);
+/// A component for an object held by the player.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HeldComponent;
+
+impl_Component!(HeldComponent);
+
+/// A component for objects that obstruct movement. Each is an axis-aligned box around its
+/// LocationComponent, as wide as its scale, like the cube it's rendered as.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CollisionComponent;
 
 impl_Component!(CollisionComponent);
+
+/// A component for an object moved by `MovementSystem`, which slides along the objects with a
+/// `CollisionComponent` in its way rather than going through them.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ControllerComponent {
+    /// The radius of the object, for collisions. It's a circle on the floor, so its height
+    /// doesn't matter.
+    pub radius: f32,
+}
+
+impl_Component!(ControllerComponent);
+
+/// A component giving an entity a human-readable name.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NameComponent(pub Cow<'static, str>);
+
+impl NameComponent {
+    /// Creates a name component.
+    pub fn new(name: impl Into<Cow<'static, str>>) -> NameComponent {
+        NameComponent(name.into())
+    }
+}
+
+impl_Component!(NameComponent);
+
+/// A component making an entity's `LocationComponent` relative to another entity's. When the
+/// parent is deleted, so is the child. Use `World::set_parent` to add this, since it checks that
+/// no cycles are created.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ParentComponent(pub Entity);
+
+impl_Component!(ParentComponent);
+
+/// A component holding an entity's `LocationComponent` as it was before the last simulation
+/// step, which rendering interpolates from. It's kept up to date by `PreviousLocationSystem`.
+#[derive(Copy, Clone, Debug)]
+pub struct PreviousLocationComponent(pub LocationComponent);
+
+impl_Component!(PreviousLocationComponent);
+
+/// A component holding an entity's model matrix in world space, as computed from its
+/// `LocationComponent` and those of its ancestors by `TransformSystem`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransformComponent {
+    /// The model matrix.
+    pub matrix: Matrix4<f32>,
+}
+
+impl TransformComponent {
+    /// Returns the position of the entity in world space.
+    pub fn position(&self) -> Point3<f32> {
+        Point3::new(self.matrix.w.x, self.matrix.w.y, self.matrix.w.z)
+    }
+
+    /// Returns the (uniform) scale of the entity in world space.
+    pub fn scale(&self) -> f32 {
+        self.matrix.x.truncate().magnitude()
+    }
+
+    /// Returns the radius of the entity in world space, for collisions.
+    pub fn radius(&self) -> f32 {
+        self.scale() * 2f32.sqrt() / 2.0
+    }
+
+    /// Returns whether the two objects collide, as with `LocationComponent::collides`.
+    pub fn collides(&self, other: &TransformComponent) -> bool {
+        let distance = (self.position() - other.position()).magnitude();
+        distance < self.radius() + other.radius()
+    }
+}
+
+impl Default for TransformComponent {
+    fn default() -> TransformComponent {
+        TransformComponent {
+            matrix: Matrix4::identity(),
+        }
+    }
+}
+
+impl_Component!(TransformComponent);
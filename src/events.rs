@@ -0,0 +1,37 @@
+//! Common gameplay events.
+
+use crate::Entity;
+
+/// An event sent when the player picks up a key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyPickedUp {
+    /// The key that was picked up.
+    pub key: Entity,
+
+    /// The letter of the key.
+    pub letter: char,
+}
+
+/// An event sent when a door is unlocked. The key used to unlock it is deleted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DoorUnlocked {
+    /// The door that was unlocked.
+    pub door: Entity,
+
+    /// The letter of the door.
+    pub letter: char,
+}
+
+/// An event sent when the player reaches the goal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GoalReached {
+    /// The goal that was reached. It is deleted when it is reached.
+    pub goal: Entity,
+}
+
+/// An event sent when an enemy catches the player, who is sent back to the start.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PlayerCaught {
+    /// The enemy that caught the player.
+    pub enemy: Entity,
+}
@@ -0,0 +1,492 @@
+//! Generating mazes, with keys and doors placed so that they can always be completed.
+//!
+//! The maze is built on a grid of cells, which are the tiles with even coordinates. The tiles
+//! between two neighbouring cells are passages or walls, and the rest are walls. Each algorithm
+//! carves a spanning tree of the cells, so there's exactly one way between any two of them.
+//!
+//! The goal is put on the cell farthest from the start, and doors on the way between them. The
+//! key for each door is put somewhere that can be reached through only the doors before it, so
+//! taking the keys in the order of their doors always works, even though opening a door uses up
+//! its key.
+
+use crate::{solve::Move, Map, Tile};
+use failure::{Error, Fallible};
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/// An algorithm to carve a maze with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    /// A randomized depth-first search, which makes long, winding passages.
+    Backtracker,
+
+    /// Randomized Prim's algorithm, which makes many short dead ends.
+    Prim,
+
+    /// Randomized Kruskal's algorithm, which joins random passages until they all connect.
+    Kruskal,
+
+    /// Wilson's algorithm, which chooses uniformly among every possible maze.
+    Wilson,
+}
+
+impl Algorithm {
+    /// All the algorithms.
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::Backtracker,
+        Algorithm::Prim,
+        Algorithm::Kruskal,
+        Algorithm::Wilson,
+    ];
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let s = match *self {
+            Algorithm::Backtracker => "backtracker",
+            Algorithm::Prim => "prim",
+            Algorithm::Kruskal => "kruskal",
+            Algorithm::Wilson => "wilson",
+        };
+        fmt.write_str(s)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Algorithm> {
+        match Algorithm::ALL.iter().find(|algorithm| algorithm.to_string() == s) {
+            Some(&algorithm) => Ok(algorithm),
+            None => bail!(
+                "Unknown algorithm {:?}; the known algorithms are backtracker, prim, kruskal, and \
+                 wilson",
+                s
+            ),
+        }
+    }
+}
+
+/// The settings for generating a maze.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{generate::{Algorithm, Generator}, solve::{solve, Verdict}};
+/// # fn main() {
+/// for &algorithm in &Algorithm::ALL {
+///     let generator = Generator {
+///         dims: (21, 15),
+///         algorithm,
+///         keys: 7,
+///         seed: 5607,
+///     };
+///     let map = generator.generate().unwrap();
+///     assert_eq!(map.keys.len(), 7);
+///     match solve(&map).unwrap() {
+///         Verdict::Solvable(solution) => assert_eq!(solution.doors.len(), 7),
+///         Verdict::Unsolvable(why) => panic!("{} made an unsolvable map: {}", algorithm, why),
+///     }
+///
+///     // The same seed always makes the same map.
+///     assert_eq!(generator.generate().unwrap().to_string(), map.to_string());
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Generator {
+    /// The dimensions of the map, in tiles. With even dimensions, the last column or row is
+    /// left as wall.
+    pub dims: (usize, usize),
+
+    /// The algorithm to carve the maze with.
+    pub algorithm: Algorithm,
+
+    /// The number of key and door pairs to place.
+    pub keys: usize,
+
+    /// The seed for the random number generator.
+    pub seed: u64,
+}
+
+impl Default for Generator {
+    fn default() -> Generator {
+        Generator {
+            dims: (15, 15),
+            algorithm: Algorithm::Backtracker,
+            keys: 1,
+            seed: 0,
+        }
+    }
+}
+
+impl Generator {
+    /// Generates a map. Returns an error if the map is too small for the number of keys.
+    pub fn generate(&self) -> Fallible<Map> {
+        let (width, height) = self.dims;
+        let cells = Cells {
+            dims: ((width + 1) / 2, (height + 1) / 2),
+        };
+        if width == 0 || height == 0 || cells.len() < 2 {
+            bail!("A {}x{} map is too small for a maze", width, height);
+        }
+
+        let mut rng = Rng::new(self.seed);
+        let passages = match self.algorithm {
+            Algorithm::Backtracker => backtracker(&cells, &mut rng),
+            Algorithm::Prim => prim(&cells, &mut rng),
+            Algorithm::Kruskal => kruskal(&cells, &mut rng),
+            Algorithm::Wilson => wilson(&cells, &mut rng),
+        };
+        let mut neighbours = vec![Vec::new(); cells.len()];
+        for &(a, b) in &passages {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+        }
+
+        // Put the goal as far from the start as possible.
+        let start = rng.below(cells.len());
+        let (order, parents) = breadth_first(&neighbours, start);
+        let goal = *order.last().unwrap();
+        let mut path = vec![goal];
+        while let Some(parent) = parents[*path.last().unwrap()] {
+            path.push(parent);
+        }
+        path.reverse();
+
+        // Find how far along the path each cell branches off from it, i.e. how many of the doors
+        // need to be unlocked to reach it.
+        let mut branches_at = vec![0; cells.len()];
+        let mut queue = path.iter().cloned().collect::<VecDeque<_>>();
+        let mut seen = vec![false; cells.len()];
+        for (i, &cell) in path.iter().enumerate() {
+            branches_at[cell] = i;
+            seen[cell] = true;
+        }
+        while let Some(cell) = queue.pop_front() {
+            for &next in &neighbours[cell] {
+                if !seen[next] {
+                    seen[next] = true;
+                    branches_at[next] = branches_at[cell];
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let key_cells = (0..cells.len())
+            .filter(|&cell| cell != start && cell != goal)
+            .collect::<Vec<_>>();
+        let (doors, keys) = place_keys(self.keys, &path, &branches_at, &key_cells, &mut rng)
+            .ok_or_else(|| {
+                format_err!(
+                    "Can't fit {} keys in a {}x{} map; try a bigger map or fewer keys",
+                    self.keys,
+                    width,
+                    height
+                )
+            })?;
+
+        let mut tiles = vec![Tile::Wall; width * height];
+        for cell in 0..cells.len() {
+            let (x, y) = cells.tile(cell);
+            tiles[x + y * width] = Tile::Empty;
+        }
+        for &(a, b) in &passages {
+            let (x, y) = cells.passage(a, b);
+            tiles[x + y * width] = Tile::Empty;
+        }
+        let mut map_keys = Vec::new();
+        for (i, (&edge, &key)) in doors.iter().zip(&keys).enumerate() {
+            let letter = (b'a' + (i % 5) as u8) as char;
+            let (x, y) = cells.passage(path[edge], path[edge + 1]);
+            tiles[x + y * width] = Tile::Door(letter.to_ascii_uppercase());
+            let (x, y) = cells.tile(key);
+            map_keys.push((x, y, letter));
+        }
+
+        Ok(Map {
+            dims: self.dims,
+            tiles,
+            start: cells.tile(start),
+            goal: cells.tile(goal),
+            keys: map_keys,
+            ..Map::default()
+        })
+    }
+}
+
+/// Chooses which edges of the path get doors, and which cells get their keys. Returns `None` if
+/// there isn't room for that many keys.
+fn place_keys(
+    count: usize,
+    path: &[usize],
+    branches_at: &[usize],
+    key_cells: &[usize],
+    rng: &mut Rng,
+) -> Option<(Vec<usize>, Vec<usize>)> {
+    // The number of cells a key can go on which can be reached through the first `i` edges of
+    // the path.
+    let mut reachable = vec![0; path.len()];
+    for &cell in key_cells {
+        reachable[branches_at[cell]] += 1;
+    }
+    for i in 1..reachable.len() {
+        reachable[i] += reachable[i - 1];
+    }
+
+    // Spread the doors along the path, one in each of `count` equal stretches where possible.
+    // Door `i` needs at least `i + 1` cells before it for its own and the earlier doors' keys.
+    let edges = path.len() - 1;
+    let mut doors = Vec::with_capacity(count);
+    for i in 0..count {
+        let earliest = (0..edges).find(|&edge| reachable[edge] > i)?;
+        let earliest = earliest.max(doors.last().map_or(0, |&edge| edge + 1));
+        let latest = (edges + i).checked_sub(count)?;
+        if earliest > latest {
+            return None;
+        }
+        let lo = (i * edges / count).max(earliest).min(latest);
+        let hi = (((i + 1) * edges / count).max(1) - 1).max(lo).min(latest);
+        doors.push(lo + rng.below(hi - lo + 1));
+    }
+
+    // Put each key past the door before its own if possible, so the keys are spread out too.
+    let mut keys = Vec::with_capacity(count);
+    let mut used = vec![false; branches_at.len()];
+    for (i, &door) in doors.iter().enumerate() {
+        let after = if i == 0 { 0 } else { doors[i - 1] + 1 };
+        let mut choices = key_cells
+            .iter()
+            .cloned()
+            .filter(|&cell| !used[cell] && after <= branches_at[cell] && branches_at[cell] <= door)
+            .collect::<Vec<_>>();
+        if choices.is_empty() {
+            choices = key_cells
+                .iter()
+                .cloned()
+                .filter(|&cell| !used[cell] && branches_at[cell] <= door)
+                .collect();
+        }
+        let key = choices[rng.below(choices.len())];
+        used[key] = true;
+        keys.push(key);
+    }
+    Some((doors, keys))
+}
+
+/// The grid of cells, numbered in row-major order.
+struct Cells {
+    dims: (usize, usize),
+}
+
+impl Cells {
+    fn len(&self) -> usize {
+        self.dims.0 * self.dims.1
+    }
+
+    /// Returns the tile a cell is on.
+    fn tile(&self, cell: usize) -> (usize, usize) {
+        (cell % self.dims.0 * 2, cell / self.dims.0 * 2)
+    }
+
+    /// Returns the tile between two neighbouring cells.
+    fn passage(&self, a: usize, b: usize) -> (usize, usize) {
+        let (a, b) = (self.tile(a), self.tile(b));
+        ((a.0 + b.0) / 2, (a.1 + b.1) / 2)
+    }
+
+    /// Returns the neighbours of a cell.
+    fn neighbours(&self, cell: usize) -> impl Iterator<Item = usize> {
+        let dims = self.dims;
+        let pos = (cell % dims.0, cell / dims.0);
+        Move::ALL
+            .iter()
+            .filter_map(move |m| m.from(pos, dims))
+            .map(move |(x, y)| x + y * dims.0)
+    }
+}
+
+/// Carves a maze with a randomized depth-first search.
+fn backtracker(cells: &Cells, rng: &mut Rng) -> Vec<(usize, usize)> {
+    let mut passages = Vec::new();
+    let mut visited = vec![false; cells.len()];
+    let start = rng.below(cells.len());
+    visited[start] = true;
+    let mut stack = vec![start];
+    while let Some(&cell) = stack.last() {
+        let unvisited = cells
+            .neighbours(cell)
+            .filter(|&next| !visited[next])
+            .collect::<Vec<_>>();
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+        let next = unvisited[rng.below(unvisited.len())];
+        visited[next] = true;
+        passages.push((cell, next));
+        stack.push(next);
+    }
+    passages
+}
+
+/// Carves a maze by growing it from a random passage out of it each time.
+fn prim(cells: &Cells, rng: &mut Rng) -> Vec<(usize, usize)> {
+    let mut passages = Vec::new();
+    let mut visited = vec![false; cells.len()];
+    let start = rng.below(cells.len());
+    visited[start] = true;
+    let mut frontier = cells.neighbours(start).map(|next| (start, next)).collect::<Vec<_>>();
+    while !frontier.is_empty() {
+        let i = rng.below(frontier.len());
+        let (cell, next) = frontier.swap_remove(i);
+        if visited[next] {
+            continue;
+        }
+        visited[next] = true;
+        passages.push((cell, next));
+        frontier.extend(
+            cells
+                .neighbours(next)
+                .filter(|&after| !visited[after])
+                .map(|after| (next, after)),
+        );
+    }
+    passages
+}
+
+/// Carves a maze by joining the cells on either side of walls in a random order, unless they're
+/// already connected.
+fn kruskal(cells: &Cells, rng: &mut Rng) -> Vec<(usize, usize)> {
+    let mut walls = (0..cells.len())
+        .flat_map(|cell| {
+            cells
+                .neighbours(cell)
+                .filter(move |&next| next > cell)
+                .map(move |next| (cell, next))
+        }).collect::<Vec<_>>();
+    rng.shuffle(&mut walls);
+
+    // A union-find forest of the connected cells.
+    let mut parents = (0..cells.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], mut cell: usize) -> usize {
+        while parents[cell] != cell {
+            parents[cell] = parents[parents[cell]];
+            cell = parents[cell];
+        }
+        cell
+    }
+
+    let mut passages = Vec::new();
+    for (a, b) in walls {
+        let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+        if root_a != root_b {
+            parents[root_a] = root_b;
+            passages.push((a, b));
+        }
+    }
+    passages
+}
+
+/// Carves a maze with loop-erased random walks from each cell not yet in it.
+fn wilson(cells: &Cells, rng: &mut Rng) -> Vec<(usize, usize)> {
+    let mut passages = Vec::new();
+    let mut in_maze = vec![false; cells.len()];
+    in_maze[rng.below(cells.len())] = true;
+
+    // Where the walk last left each cell for; overwriting this erases loops.
+    let mut next = vec![0; cells.len()];
+    for first in 0..cells.len() {
+        let mut cell = first;
+        while !in_maze[cell] {
+            let neighbours = cells.neighbours(cell).collect::<Vec<_>>();
+            next[cell] = neighbours[rng.below(neighbours.len())];
+            cell = next[cell];
+        }
+
+        let mut cell = first;
+        while !in_maze[cell] {
+            in_maze[cell] = true;
+            passages.push((cell, next[cell]));
+            cell = next[cell];
+        }
+    }
+    passages
+}
+
+/// Returns the cells in breadth-first order from the start, so the last is the farthest from it,
+/// along with the cell before each on the way from the start.
+fn breadth_first(neighbours: &[Vec<usize>], start: usize) -> (Vec<usize>, Vec<Option<usize>>) {
+    let mut order = vec![start];
+    let mut parents = vec![None; neighbours.len()];
+    let mut seen = vec![false; neighbours.len()];
+    seen[start] = true;
+    let mut i = 0;
+    while i < order.len() {
+        let cell = order[i];
+        for &next in &neighbours[cell] {
+            if !seen[next] {
+                seen[next] = true;
+                parents[next] = Some(cell);
+                order.push(next);
+            }
+        }
+        i += 1;
+    }
+    (order, parents)
+}
+
+/// A SplitMix64 random number generator. Unlike the `rand` crate's generators, its output is
+/// guaranteed to stay the same, so a seed always gives the same map.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::generate::Rng;
+/// # fn main() {
+/// let mut rng = Rng::new(7);
+/// let mut items = (0..10).collect::<Vec<_>>();
+/// rng.shuffle(&mut items);
+/// assert!(rng.below(3) < 3);
+///
+/// let mut again = Rng::new(7);
+/// let mut shuffled = (0..10).collect::<Vec<_>>();
+/// again.shuffle(&mut shuffled);
+/// assert_eq!(items, shuffled);
+/// # }
+/// ```
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a generator from a seed.
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// Returns the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number less than `n`, which must be positive.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Shuffles the items into a random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
@@ -1,16 +1,24 @@
 use crate::{
-    components::{CameraComponent, CollisionComponent, LocationComponent},
-    State, System,
+    replay::Recorder,
+    resources::{Input, InputState, Time},
+    snapshot::ComponentRegistry,
+    systems::seed_transforms,
+    util::log_err,
+    State, System, World,
 };
 use glium::glutin::{DeviceEvent, ElementState, Event, EventsLoop, WindowEvent};
 use smallvec::SmallVec;
+use std::path::PathBuf;
 
-/// The control system.
+/// The control system. This stores the user's input in the `InputState` resource, which
+/// `MovementSystem` moves the camera according to. Mouse motion is added up until a simulation
+/// step uses it. F5 quicksaves the world, and F9 quickloads it.
 pub struct ControlSystem {
     event_loop: EventsLoop,
-
-    move_forward: f32,
-    move_strafe: f32,
+    quicksave_path: PathBuf,
+    recorder: Option<Recorder>,
+    registry: ComponentRegistry,
+    replaying: bool,
 }
 
 impl ControlSystem {
@@ -18,87 +26,121 @@ impl ControlSystem {
     pub fn new(event_loop: EventsLoop) -> ControlSystem {
         ControlSystem {
             event_loop,
-
-            move_forward: 0.0,
-            move_strafe: 0.0,
+            quicksave_path: PathBuf::from("quicksave.json"),
+            recorder: None,
+            registry: ComponentRegistry::default(),
+            replaying: false,
         }
     }
+
+    /// Records every input the system consumes with the given recorder.
+    pub fn with_recorder(mut self, recorder: Recorder) -> ControlSystem {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Makes the system ignore every input except those closing the game, for watching a replay.
+    /// The replay's inputs should be fed to the simulation by a `ReplaySystem` instead.
+    pub fn replaying(mut self) -> ControlSystem {
+        self.replaying = true;
+        self
+    }
+
+    /// Sets the file that quicksaves are written to and quickloaded from. Defaults to
+    /// `quicksave.json` in the current directory.
+    pub fn with_quicksave_path(mut self, path: impl Into<PathBuf>) -> ControlSystem {
+        self.quicksave_path = path.into();
+        self
+    }
 }
 
 impl System for ControlSystem {
     fn step(&mut self, state: &mut State, _dt: u64) {
-        let mut move_pitch = 0.0;
-        let mut move_yaw = 0.0;
+        let mut input_state = state
+            .world_mut()
+            .and_then(|world| world.resource::<InputState>().cloned())
+            .unwrap_or_default();
+
+        let tick = state
+            .world_mut()
+            .and_then(|world| world.resource::<Time>())
+            .map_or(0, |time| time.frame);
 
         // Handle input events.
+        let mut quicksave = false;
+        let mut quickload = false;
         let mut events = SmallVec::<[_; 4]>::new();
         self.event_loop.poll_events(|event| events.push(event));
         for event in events {
-            match event {
+            let input = match event {
                 Event::DeviceEvent { event, .. } => match event {
-                    DeviceEvent::Key(event) => match event.state {
-                        ElementState::Pressed => match event.scancode {
-                            1 => *state = State::Close,     // Escape
-                            17 => self.move_forward = 1.0,  // W
-                            30 => self.move_strafe = -1.0,  // A
-                            31 => self.move_forward = -1.0, // S
-                            32 => self.move_strafe = 1.0,   // D
-                            _ => {}
-                        },
-                        ElementState::Released => match event.scancode {
-                            17 | 31 => self.move_forward = 0.0, // W, S
-                            30 | 32 => self.move_strafe = 0.0,  // A, D
-                            _ => {}
-                        },
+                    DeviceEvent::Key(event) => Input::Key {
+                        scancode: event.scancode,
+                        pressed: event.state == ElementState::Pressed,
                     },
-                    DeviceEvent::MouseMotion { delta: (x, y) } => {
-                        move_yaw -= x as f32;
-                        move_pitch += y as f32;
-                    }
-                    _ => {}
+                    DeviceEvent::MouseMotion { delta: (x, y) } => Input::MouseMotion {
+                        x: x as f32,
+                        y: y as f32,
+                    },
+                    _ => continue,
                 },
                 Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::CloseRequested => *state = State::Close,
+                    WindowEvent::CloseRequested => Input::Close,
                     WindowEvent::Resized(_) => {
                         // TODO: self.recompute_proj
+                        continue;
                     }
-                    _ => {}
+                    _ => continue,
                 },
-                _ => {}
+                _ => continue,
+            };
+            if self.replaying && !input.is_close() {
+                continue;
             }
-        }
-
-        // Get the world.
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
-        };
-
-        // Get the camera.
-        let camera = match world.iter().next() {
-            Some((ent, hlist_pat![CameraComponent])) => ent,
-            None => {
-                warn!("No camera?");
-                return;
+            if let Some(ref recorder) = self.recorder {
+                recorder.record_input(tick, input);
             }
-        };
 
-        // Apply motion to the camera, if no collisions occur.
-        let old_loc = *world
-            .get_one::<LocationComponent>(camera)
-            .expect("Camera didn't have a location?");
-        let mut new_loc = old_loc.move_by(self.move_forward / 40.0, self.move_strafe / 40.0);
+            match input {
+                _ if input.is_close() => *state = State::Close,
+                Input::Key {
+                    scancode: 63,
+                    pressed: true,
+                } => quicksave = true, // F5
+                Input::Key {
+                    scancode: 67,
+                    pressed: true,
+                } => quickload = true, // F9
+                _ => input_state.apply(input),
+            }
+        }
 
-        for (_, hlist_pat![&CollisionComponent(c), loc]) in world.iter() {
-            if c && new_loc.collides(loc) {
-                new_loc = old_loc;
-                break;
+        // Quicksave or quickload, if requested.
+        if quicksave {
+            if let Some(world) = state.world_mut() {
+                match world.save(&self.registry, &self.quicksave_path) {
+                    Ok(()) => info!("Saved to {}", self.quicksave_path.display()),
+                    Err(err) => log_err(err),
+                }
+            }
+        }
+        if quickload && !state.should_close() {
+            match World::load(&self.registry, &self.quicksave_path) {
+                Ok(mut world) => {
+                    info!("Loaded {}", self.quicksave_path.display());
+                    if self.recorder.is_some() {
+                        warn!("Quickloading while recording; the recording won't replay");
+                    }
+                    // Neither the transforms nor the index are saved, so they're rebuilt.
+                    seed_transforms(&mut world);
+                    *state = State::Playing(world);
+                }
+                Err(err) => log_err(err),
             }
         }
 
-        let camera_loc = world
-            .get_mut::<LocationComponent>(camera)
-            .expect("Camera didn't have a location?");
-        *camera_loc = new_loc.rotate_by(move_pitch / 10.0, move_yaw / 10.0);
+        if let Some(world) = state.world_mut() {
+            world.insert_resource(input_state);
+        }
     }
 }
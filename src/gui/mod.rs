@@ -4,8 +4,8 @@ mod render;
 
 pub use crate::gui::{
     controls::ControlSystem,
-    model::{Material, Model, Vertex},
-    render::{DecalComponent, RenderComponent, RenderData},
+    model::{Material, MaterialSource, Model, ModelSource, Vertex},
+    render::{DecalComponent, RenderAssets, RenderComponent, RenderData},
 };
 use crate::{State, System};
 use failure::{Fallible, SyncFailure};
@@ -25,6 +25,7 @@ pub struct GuiSystem<T> {
     display: Display,
     grab_mouse: bool,
     params: DrawParameters<'static>,
+    showing_win: bool,
     data: T,
 }
 
@@ -114,6 +115,7 @@ impl GuiSystem<()> {
                 display,
                 grab_mouse,
                 params,
+                showing_win: false,
                 data: (),
             },
         ))
@@ -127,6 +129,7 @@ impl GuiSystem<()> {
             display: self.display,
             grab_mouse: self.grab_mouse,
             params: self.params,
+            showing_win: self.showing_win,
             data,
         };
         system.recompute_proj();
@@ -137,11 +140,23 @@ impl GuiSystem<()> {
 impl System for GuiSystem<RenderData> {
     fn step(&mut self, state: &mut State, _dt: u64) {
         // Get the world.
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
+        let (world, won) = match state {
+            State::Playing(ref mut world) | State::Lost(ref mut world, _) => (world, false),
+            State::Done(ref mut world, _) => (world, true),
             _ => return,
         };
 
+        // Show the win decal once the goal is reached. This goes by the state rather than the
+        // `GoalReached` event, since several steps may run between frames, and events only last
+        // for two.
+        if won && !self.showing_win {
+            match world.find_by_name("win") {
+                Some(decal) => world.get_mut::<DecalComponent>(decal).unwrap().enabled = true,
+                None => warn!("No win decal?"),
+            }
+        }
+        self.showing_win = won;
+
         // Render the frame.
         let mut frame = self.display.draw();
         frame.clear_color_and_depth(
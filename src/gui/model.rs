@@ -45,6 +45,72 @@ lazy_static! {
     static ref DEFAULT_MATERIAL: Arc<Material> = Arc::new(Material::flat([1.0, 0.0, 1.0]));
     static ref MATERIAL_CACHE: Mutex<HashMap<PathBuf, Weak<Material>>> = Mutex::new(HashMap::new());
     static ref MODEL_CACHE: Mutex<HashMap<PathBuf, Weak<Model>>> = Mutex::new(HashMap::new());
+    static ref SOURCE_CACHE: Mutex<Vec<(ModelSource, Weak<Model>)>> = Mutex::new(Vec::new());
+}
+
+/// A description of how to build a model, which is stored in place of the model itself when a
+/// world is saved.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ModelSource {
+    /// A quad with the given vertices, as with `Model::quad`.
+    #[serde(rename = "quad")]
+    Quad([(f32, f32, f32); 4], MaterialSource),
+
+    /// A unit cube, as with `Model::cube`.
+    #[serde(rename = "cube")]
+    Cube(MaterialSource),
+}
+
+impl ModelSource {
+    /// Builds the model, loading its material if necessary. Models are cached, so loading the
+    /// same source twice returns the same model.
+    pub fn load(&self) -> Fallible<Arc<Model>> {
+        let mut cache = SOURCE_CACHE.lock().unwrap();
+        cache.retain(|(_, model)| model.upgrade().is_some());
+        if let Some(model) = cache
+            .iter()
+            .find(|(source, _)| source == self)
+            .and_then(|(_, model)| model.upgrade())
+        {
+            return Ok(model);
+        }
+
+        let model = Arc::new(match *self {
+            ModelSource::Quad([v1, v2, v3, v4], ref material) => {
+                Model::quad(v1, v2, v3, v4, material.load()?)
+            }
+            ModelSource::Cube(ref material) => Model::cube(material.load()?),
+        });
+        cache.push((self.clone(), Arc::downgrade(&model)));
+        Ok(model)
+    }
+}
+
+/// A description of how to build a material.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MaterialSource {
+    /// The default material, an eye-searing magenta.
+    #[serde(rename = "default")]
+    Default,
+
+    /// A material of the given color, as with `Material::flat`.
+    #[serde(rename = "flat")]
+    Flat([f32; 3]),
+
+    /// A material loaded from a `.mtl` file, as with `Material::load_mtl`.
+    #[serde(rename = "mtl")]
+    Mtl(PathBuf),
+}
+
+impl MaterialSource {
+    /// Loads the material, returning `None` for the default material.
+    pub fn load(&self) -> Fallible<Option<Arc<Material>>> {
+        match *self {
+            MaterialSource::Default => Ok(None),
+            MaterialSource::Flat(color) => Ok(Some(Arc::new(Material::flat(color)))),
+            MaterialSource::Mtl(ref path) => Material::load_mtl(path).map(Some),
+        }
+    }
 }
 
 /// A model.
@@ -2,18 +2,25 @@
 
 use cgmath::{Deg, Matrix4};
 use crate::{
-    components::{CameraComponent, LocationComponent},
-    systems::GuiSystem,
-    Model, Vertex, World,
+    components::{
+        CameraComponent, LocationComponent, ParentComponent, PreviousLocationComponent,
+    },
+    resources::Interpolation,
+    systems::{world_matrix, GuiSystem},
+    util::load_texture,
+    Entity, Material, Model, ModelSource, Vertex, With, World,
 };
+use failure::Fallible;
 use glium::{
+    backend::Facade,
     glutin::dpi::LogicalSize,
     index::{NoIndices, PrimitiveType},
     texture::RawImage2d,
     uniforms::{Sampler, SamplerWrapFunction},
     Program, Surface, Texture2d, VertexBuffer,
 };
-use std::{cell::RefCell, collections::HashMap, ptr::null, rc::Rc, sync::Arc};
+use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, ptr::null, rc::Rc, sync::Arc};
 
 /// A graphical component for a 2d decal, drawn over the screen.
 pub struct DecalComponent {
@@ -22,6 +29,44 @@ pub struct DecalComponent {
 
     /// The image for the decal.
     pub image: Arc<RawImage2d<'static, u8>>,
+
+    /// The path the image was loaded from, which is what gets saved.
+    pub path: PathBuf,
+}
+
+impl DecalComponent {
+    /// Loads a decal from the given image file.
+    pub fn load(path: impl Into<PathBuf>, enabled: bool) -> Fallible<DecalComponent> {
+        let path = path.into();
+        Ok(DecalComponent {
+            enabled,
+            image: load_texture("", &path)?,
+            path,
+        })
+    }
+}
+
+/// The saved form of a `DecalComponent`.
+#[derive(Deserialize, Serialize)]
+struct SavedDecal {
+    enabled: bool,
+    path: PathBuf,
+}
+
+impl Serialize for DecalComponent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedDecal {
+            enabled: self.enabled,
+            path: self.path.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DecalComponent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DecalComponent, D::Error> {
+        let saved = SavedDecal::deserialize(deserializer)?;
+        DecalComponent::load(saved.path, saved.enabled).map_err(D::Error::custom)
+    }
 }
 
 impl_Component!(DecalComponent);
@@ -30,10 +75,96 @@ impl_Component!(DecalComponent);
 pub struct RenderComponent {
     /// The model for the component.
     pub model: Arc<Model>,
+
+    /// The description the model was built from, which is what gets saved.
+    pub source: ModelSource,
+}
+
+impl RenderComponent {
+    /// Builds a render component from the given source.
+    pub fn load(source: ModelSource) -> Fallible<RenderComponent> {
+        Ok(RenderComponent {
+            model: source.load()?,
+            source,
+        })
+    }
+}
+
+impl Serialize for RenderComponent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RenderComponent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RenderComponent, D::Error> {
+        let source = ModelSource::deserialize(deserializer)?;
+        RenderComponent::load(source).map_err(D::Error::custom)
+    }
 }
 
 impl_Component!(RenderComponent);
 
+/// The assets needed to render a world, which can be loaded without a GL context.
+/// `RenderData::upload` sends them to the GPU.
+#[derive(Clone)]
+pub struct RenderAssets {
+    /// The clear color.
+    pub clear_color: [f32; 4],
+
+    /// The source of the vertex shader.
+    pub vertex_shader: String,
+
+    /// The source of the fragment shader.
+    pub fragment_shader: String,
+
+    /// The models of the world's `RenderComponent`s.
+    pub models: Vec<Arc<Model>>,
+
+    /// The materials of the models.
+    pub materials: Vec<Arc<Material>>,
+
+    /// The images of the world's `DecalComponent`s.
+    pub decals: Vec<Arc<RawImage2d<'static, u8>>>,
+}
+
+impl RenderAssets {
+    /// Creates a set of assets with the given clear color and shader sources, and no models,
+    /// materials or decals.
+    pub fn new(
+        clear_color: [f32; 4],
+        vertex_shader: impl Into<String>,
+        fragment_shader: impl Into<String>,
+    ) -> RenderAssets {
+        RenderAssets {
+            clear_color,
+            vertex_shader: vertex_shader.into(),
+            fragment_shader: fragment_shader.into(),
+            models: Vec::new(),
+            materials: Vec::new(),
+            decals: Vec::new(),
+        }
+    }
+
+    /// Adds the models, materials and decal images used by the world's entities.
+    pub fn add_world(&mut self, world: &World) {
+        for (_, hlist_pat![render]) in world.iter::<Hlist![&RenderComponent]>() {
+            push_new(&mut self.models, &render.model);
+            push_new(&mut self.materials, &render.model.material);
+        }
+        for (_, hlist_pat![decal]) in world.iter::<Hlist![&DecalComponent]>() {
+            push_new(&mut self.decals, &decal.image);
+        }
+    }
+}
+
+/// Adds a value to a list, unless the same value (by address) is already in it.
+fn push_new<T>(list: &mut Vec<Arc<T>>, value: &Arc<T>) {
+    if !list.iter().any(|other| Arc::ptr_eq(other, value)) {
+        list.push(value.clone());
+    }
+}
+
 /// The data required to render a world.
 pub struct RenderData {
     /// The clear color.
@@ -42,6 +173,10 @@ pub struct RenderData {
     /// The GLSL program.
     pub program: Program,
 
+    /// The assets that were uploaded, which are kept alive so that the addresses the caches are
+    /// keyed by aren't reused.
+    assets: RenderAssets,
+
     /// The dimensions of the window.
     pub(super) dims: LogicalSize,
 
@@ -56,16 +191,46 @@ pub struct RenderData {
 }
 
 impl RenderData {
-    /// Creates a RenderData with the given clear color and GLSL program.
-    pub fn new(clear_color: [f32; 4], program: Program) -> RenderData {
-        RenderData {
-            clear_color,
+    /// Compiles the shaders and uploads the models and images in the assets.
+    pub fn upload(assets: RenderAssets, facade: &impl Facade) -> Fallible<RenderData> {
+        let program = Program::from_source(
+            facade,
+            &assets.vertex_shader,
+            &assets.fragment_shader,
+            None,
+        )?;
+
+        let mut vbos = HashMap::new();
+        for model in &assets.models {
+            let vbo = VertexBuffer::new(facade, &model.vertices)?;
+            vbos.insert(&**model as *const Model, Rc::new(vbo));
+        }
+
+        let images = assets
+            .materials
+            .iter()
+            .flat_map(|material| material.texture.iter().chain(&material.bump))
+            .chain(&assets.decals);
+        let mut textures = HashMap::new();
+        for image in images {
+            let texture = upload_texture(facade, image)?;
+            textures.insert(&**image as *const _, Rc::new(texture));
+        }
+
+        Ok(RenderData {
+            clear_color: assets.clear_color,
             program,
+            assets,
             dims: LogicalSize::new(0.0, 0.0),
             proj: Matrix4::from_scale(0.0),
-            textures: RefCell::new(HashMap::new()),
-            vbos: RefCell::new(HashMap::new()),
-        }
+            textures: RefCell::new(textures),
+            vbos: RefCell::new(vbos),
+        })
+    }
+
+    /// Returns the assets that were uploaded.
+    pub fn assets(&self) -> &RenderAssets {
+        &self.assets
     }
 }
 
@@ -74,22 +239,37 @@ impl GuiSystem<RenderData> {
     pub(super) fn render(&mut self, world: &mut World, frame: &mut impl Surface) {
         let indices = NoIndices(PrimitiveType::TrianglesList);
 
-        let view_mat = match world.iter().next() {
-            Some((_, hlist_pat![camera, loc])) => {
-                let _: &CameraComponent = camera;
-                let loc: &LocationComponent = loc;
-                loc.view()
-            }
-            None => return,
+        // Draw everything between where it was before the last step and where it is now.
+        let alpha = world.resource::<Interpolation>().map_or(1.0, |alpha| alpha.0);
+        let location = |entity: Entity| {
+            world
+                .get::<Hlist![&LocationComponent, Option<&PreviousLocationComponent>]>(entity)
+                .map(|hlist_pat![&loc, prev]| match prev {
+                    Some(&PreviousLocationComponent(prev)) => prev.lerp(&loc, alpha),
+                    None => loc,
+                })
+        };
+        let parent = |entity: Entity| {
+            world
+                .get::<Hlist![&ParentComponent]>(entity)
+                .map(|hlist_pat![&ParentComponent(parent)]| parent)
         };
 
-        for (_entity, hlist_pat![decal]) in world.iter() {
+        let view_mat = match world.single::<Hlist![With<CameraComponent>]>() {
+            Ok((camera, _)) => match location(camera) {
+                Some(loc) => loc.view(),
+                None => return,
+            },
+            Err(_) => return,
+        };
+
+        for (entity, hlist_pat![decal]) in world.iter() {
             let decal: &DecalComponent = decal;
             if !decal.enabled {
                 continue;
             }
 
-            info!("Rendering decal {}", _entity);
+            info!("Rendering decal {}", world.named(entity));
             let texture = self.get_texture(Some(&decal.image));
             let uniforms = uniform!{ decal: &*texture };
             frame
@@ -102,9 +282,12 @@ impl GuiSystem<RenderData> {
                 ).unwrap()
         }
 
-        for (_entity, hlist_pat![render, loc]) in world.iter() {
-            let render: &RenderComponent = render;
-            let loc: LocationComponent = *loc;
+        let mut matrices = HashMap::new();
+        for (entity, hlist_pat![render, _]) in
+            world.iter::<Hlist![&RenderComponent, With<LocationComponent>]>()
+        {
+            world_matrix(entity, &mut matrices, &parent, &location);
+            let model = matrices[&entity];
 
             let (bump, texture, vbo) = self.get_model_parts(&render.model);
 
@@ -113,7 +296,7 @@ impl GuiSystem<RenderData> {
                 bump: Sampler::new(&*bump).wrap_function(SamplerWrapFunction::Repeat),
                 bumped: render.model.material.bump.is_some(),
                 diffuse: render.model.material.diffuse,
-                model: Into::<[[f32; 4]; 4]>::into(loc.model()),
+                model: Into::<[[f32; 4]; 4]>::into(model),
                 proj: Into::<[[f32; 4]; 4]>::into(self.data.proj),
                 tex: Sampler::new(&*texture).wrap_function(SamplerWrapFunction::Repeat),
                 textured: render.model.material.texture.is_some(),
@@ -151,14 +334,7 @@ impl GuiSystem<RenderData> {
                     .unwrap()
                     .clone()
             } else {
-                // TODO: The fact that this is necessary feels bug-report-worthy...
-                let texture_clone = RawImage2d {
-                    data: texture.data.clone(),
-                    format: texture.format,
-                    height: texture.height,
-                    width: texture.width,
-                };
-                let texture = Rc::new(Texture2d::new(&self.display, texture_clone).unwrap());
+                let texture = Rc::new(upload_texture(&self.display, texture).unwrap());
                 self.data
                     .textures
                     .borrow_mut()
@@ -195,3 +371,15 @@ impl GuiSystem<RenderData> {
         (bump, texture, vbo)
     }
 }
+
+/// Uploads an image to a new texture.
+fn upload_texture(facade: &impl Facade, image: &RawImage2d<'static, u8>) -> Fallible<Texture2d> {
+    // TODO: The fact that this is necessary feels bug-report-worthy...
+    let image = RawImage2d {
+        data: image.data.clone(),
+        format: image.format,
+        height: image.height,
+        width: image.width,
+    };
+    Ok(Texture2d::new(facade, image)?)
+}
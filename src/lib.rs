@@ -15,42 +15,81 @@ extern crate rayon;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate smallvec;
-extern crate symbol;
 extern crate typemap;
 
 #[macro_use]
 pub mod util;
 
+pub mod collision;
 pub mod components;
+pub mod events;
+pub mod generate;
 mod gui;
+pub mod lint;
 mod map;
+pub mod pathfind;
+pub mod playtest;
+pub mod prefab;
+pub mod preview;
+pub mod replay;
+pub mod resources;
+pub mod schedule;
+pub mod snapshot;
+pub mod solve;
+pub mod spatial;
 mod state;
 pub mod systems;
+pub mod timestep;
+mod world;
 
 pub use crate::{
-    gui::{Material, Model, RenderData, Vertex},
-    map::{Map, Tile},
-    state::{State, World},
+    gui::{Material, MaterialSource, Model, ModelSource, RenderAssets, RenderData, Vertex},
+    map::{Enemy, Map, Tile},
+    state::State,
+    world::{
+        Changed, Component, ComponentBundle, ComponentRemoved, EntityDeleted, EventReader, Events,
+        Resource, SystemAccess, With, Without, World, WorldView,
+    },
 };
-use frunk::{FuncMut, PolyMut};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
-use symbol::Symbol;
 
-/// An entity.
-#[derive(Clone, Copy, Eq, Hash, PartialEq)]
-pub struct Entity(Symbol);
+/// An entity. The index may be reused once the entity is deleted, but the generation is bumped
+/// each time, so a handle to a deleted entity is never mistaken for a live one. An index whose
+/// generation has run out is never reused.
+///
+/// An entity displays as its index and generation, like `3v0`, since its name is a `NameComponent`
+/// which only the world can look up. To show an entity to people, such as in logs and errors,
+/// format `World::named(entity)` instead, which displays like `3v0:player`.
+#[derive(Clone, Copy, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    /// Returns the index of the entity.
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    /// Returns the generation of the entity.
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
 
 impl Debug for Entity {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "Entity({:?})", self.0)
+        write!(fmt, "Entity({})", self)
     }
 }
 
 impl Display for Entity {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        write!(fmt, "{:?}", self)
+        write!(fmt, "{}v{}", self.index, self.generation)
     }
 }
 
@@ -60,20 +99,12 @@ pub trait System {
     fn step(&mut self, state: &mut State, dt: u64);
 }
 
-/// A helper for stepping through a system with Frunk.
-pub struct SystemStepper<'a>(pub &'a mut State, pub u64);
-
-impl<'a> SystemStepper<'a> {
-    /// Creates a `PolyMut` function for calling `System::step` with the given arguments.
-    pub fn with_args(state: &'a mut State, dt: u64) -> PolyMut<SystemStepper<'a>> {
-        PolyMut(SystemStepper(state, dt))
-    }
-}
-
-impl<'a, 'b, S: System> FuncMut<&'b mut S> for SystemStepper<'a> {
-    type Output = ();
+/// The trait for a system that only accesses the components and resources it declares, which
+/// lets a `Schedule` run it at the same time as other systems it doesn't conflict with.
+pub trait ParallelSystem: Send {
+    /// Declares the components and resources the system reads and writes.
+    fn access(&self, access: &mut SystemAccess);
 
-    fn call(&mut self, system: &'b mut S) {
-        system.step(self.0, self.1)
-    }
+    /// Runs a single step. The system isn't run while the state is `State::Close`.
+    fn step(&mut self, world: &mut WorldView, dt: u64);
 }
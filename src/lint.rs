@@ -0,0 +1,238 @@
+//! Checking that a map makes sense, beyond it having parsed.
+
+use crate::{pathfind::find_path, Map, Tile};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// How bad an issue with a map is.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Severity {
+    /// The map loads, but probably doesn't play as intended.
+    #[serde(rename = "warning")]
+    Warning,
+
+    /// The map can't be loaded.
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Severity::Warning => fmt.write_str("warning"),
+            Severity::Error => fmt.write_str("error"),
+        }
+    }
+}
+
+/// An issue with a map.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Issue {
+    /// How bad the issue is.
+    pub severity: Severity,
+
+    /// The tile the issue is at.
+    pub at: (usize, usize),
+
+    /// A description of the issue.
+    pub message: String,
+}
+
+impl Issue {
+    fn new(severity: Severity, at: (usize, usize), message: String) -> Issue {
+        Issue {
+            severity,
+            at,
+            message,
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "{} at ({}, {}): {}",
+            self.severity, self.at.0, self.at.1, self.message
+        )
+    }
+}
+
+/// Checks a map for issues. Errors are issues that would stop `World::from_map` from loading the
+/// map; warnings are for maps that load, but can't be played as they look like they should be.
+/// Each enemy's waypoints are checked too, including whether each can be reached from the one
+/// before it.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{lint::{lint, Severity}, Map};
+/// # fn main() {
+/// let map: Map = "5 5\n0000G\nWW0W0\n0WAW0\n0W0WW\nS000a\n".parse().unwrap();
+/// assert!(lint(&map).is_empty());
+///
+/// let map: Map = "3 2\nSBW\n0SG\n".parse().unwrap();
+/// let issues = lint(&map)
+///     .into_iter()
+///     .map(|issue| issue.to_string())
+///     .collect::<Vec<_>>();
+/// assert_eq!(
+///     issues,
+///     vec![
+///         "warning at (0, 0): This start is replaced by the one at (1, 1)",
+///         "warning at (1, 0): There's no key for the door B",
+///     ]
+/// );
+/// # }
+/// ```
+pub fn lint(map: &Map) -> Vec<Issue> {
+    use self::Severity::{Error, Warning};
+
+    let mut issues = Vec::new();
+    let (width, height) = map.dims;
+    if map.tiles.len() != width * height {
+        let i = map.tiles.len().min(width * height);
+        let at = if width == 0 { (0, 0) } else { (i % width, i / width) };
+        let message = format!(
+            "The map is {}x{}, so it should have {} tiles, but it has {}",
+            width,
+            height,
+            width * height,
+            map.tiles.len()
+        );
+        issues.push(Issue::new(Error, at, message));
+    }
+
+    for &(name, at) in &[("start", map.start), ("goal", map.goal)] {
+        match map.tile(at.0, at.1) {
+            Some(Tile::Wall) => {
+                let message = format!("The {} is on a wall", name);
+                issues.push(Issue::new(Error, at, message));
+            }
+            Some(_) => {}
+            None => {
+                // If the tile is inside the map, the map is missing tiles, which is reported
+                // above.
+                if at.0 >= width || at.1 >= height {
+                    let message = format!("The {} is outside the map", name);
+                    issues.push(Issue::new(Error, at, message));
+                }
+            }
+        }
+    }
+    for &(x, y, ch) in &map.replaced {
+        let (name, replacement) = if ch == 'G' {
+            ("goal", map.goal)
+        } else {
+            ("start", map.start)
+        };
+        let message = format!(
+            "This {} is replaced by the one at ({}, {})",
+            name, replacement.0, replacement.1
+        );
+        issues.push(Issue::new(Warning, (x, y), message));
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let letter = match map.tile(x, y) {
+                Some(Tile::Door(letter)) => letter,
+                _ => continue,
+            };
+            match letter {
+                'A'..='E' => {
+                    let key = letter.to_ascii_lowercase();
+                    if !map.keys.iter().any(|&(_, _, letter)| letter == key) {
+                        let message = format!("There's no key for the door {}", letter);
+                        issues.push(Issue::new(Warning, (x, y), message));
+                    }
+                }
+                _ => {
+                    let message = format!("The door {:?} should be lettered A through E", letter);
+                    issues.push(Issue::new(Error, (x, y), message));
+                }
+            }
+        }
+    }
+
+    for &(x, y, letter) in &map.keys {
+        match letter {
+            'a'..='e' => {}
+            _ => {
+                let message = format!("The key {:?} should be lettered a through e", letter);
+                issues.push(Issue::new(Error, (x, y), message));
+                continue;
+            }
+        }
+        match map.tile(x, y) {
+            Some(Tile::Empty) => {}
+            Some(Tile::Wall) => {
+                let message = format!("The key {} is on a wall", letter);
+                issues.push(Issue::new(Warning, (x, y), message));
+            }
+            Some(Tile::Door(_)) => {
+                let message = format!("The key {} is on a door", letter);
+                issues.push(Issue::new(Warning, (x, y), message));
+            }
+            None => {
+                if x >= width || y >= height {
+                    let message = format!("The key {} is outside the map", letter);
+                    issues.push(Issue::new(Warning, (x, y), message));
+                }
+            }
+        }
+        let door = letter.to_ascii_uppercase();
+        if !map.tiles.contains(&Tile::Door(door)) {
+            let message = format!("There's no door for the key {}", letter);
+            issues.push(Issue::new(Warning, (x, y), message));
+        }
+    }
+
+    for enemy in &map.enemies {
+        if enemy.patrol.is_empty() {
+            let message = "An enemy has no waypoints to patrol".to_string();
+            issues.push(Issue::new(Error, (0, 0), message));
+            continue;
+        }
+        let mut all_empty = true;
+        for &(x, y) in &enemy.patrol {
+            match map.tile(x, y) {
+                Some(Tile::Empty) => continue,
+                Some(Tile::Wall) => {
+                    let message = "An enemy's waypoint is on a wall".to_string();
+                    issues.push(Issue::new(Error, (x, y), message));
+                }
+                Some(Tile::Door(letter)) => {
+                    let message = format!("An enemy's waypoint is on the door {}", letter);
+                    issues.push(Issue::new(Warning, (x, y), message));
+                }
+                None => {
+                    if x >= width || y >= height {
+                        let message = "An enemy's waypoint is outside the map".to_string();
+                        issues.push(Issue::new(Error, (x, y), message));
+                    }
+                }
+            }
+            all_empty = false;
+        }
+
+        // Enemies can't go through locked doors, so check that each waypoint can be reached from
+        // the one before without going through any doors.
+        if !all_empty {
+            continue;
+        }
+        let passable = |(x, y)| map.tile(x, y) == Some(Tile::Empty);
+        for i in 0..enemy.patrol.len() {
+            let (from, to) = (enemy.patrol[i], enemy.patrol[(i + 1) % enemy.patrol.len()]);
+            if find_path(map.dims, from, to, passable).is_none() {
+                let message = format!(
+                    "An enemy can't get to its waypoint at ({}, {}) from here",
+                    to.0, to.1
+                );
+                issues.push(Issue::new(Warning, from, message));
+            }
+        }
+    }
+
+    issues
+}
@@ -1,7 +1,5 @@
 #[macro_use]
 extern crate failure;
-#[macro_use]
-extern crate frunk;
 extern crate game;
 #[macro_use]
 extern crate log;
@@ -10,12 +8,13 @@ extern crate structopt;
 
 use failure::{Fallible, ResultExt};
 use game::{
-    systems::{
-        GuiSystem, HoldSystem, SinkingDoorSystem, SnagSystem, SpinningKeySystem,
-        TheFloorIsLavaSystem, UnlockSystem, WinSystem,
-    },
+    replay::{Outcome, Recorder, Recording, ReplaySystem},
+    resources::{Interpolation, Time},
+    schedule::Schedule,
+    systems::{add_simulation_systems, GuiSystem},
+    timestep::FixedTimestep,
     util::log_err,
-    State, SystemStepper, World,
+    RenderData, State, World,
 };
 use std::{path::PathBuf, process::exit, time::Instant};
 use structopt::StructOpt;
@@ -48,6 +47,23 @@ pub struct Options {
     /// Disable mouse grabbing.
     #[structopt(long = "no-grab-mouse")]
     pub no_grab_mouse: bool,
+
+    /// The file that F5 quicksaves to, and F9 quickloads from.
+    #[structopt(long = "quicksave", default_value = "quicksave.json", parse(from_os_str))]
+    pub quicksave_path: PathBuf,
+
+    /// Records every input to the given file, so the game can be replayed.
+    #[structopt(long = "record", parse(from_os_str), conflicts_with = "replay_path")]
+    pub record_path: Option<PathBuf>,
+
+    /// Replays the inputs recorded in the given file, instead of taking input from the user.
+    #[structopt(long = "replay", parse(from_os_str))]
+    pub replay_path: Option<PathBuf>,
+
+    /// Runs the replay without a window, as fast as possible, checking that it ends the same way
+    /// the recording did.
+    #[structopt(long = "headless", requires = "replay_path")]
+    pub headless: bool,
 }
 
 impl Options {
@@ -63,33 +79,91 @@ impl Options {
 }
 
 fn run(options: Options) -> Fallible<()> {
+    let (assets, world) = World::from_map_file(options.map_path)?;
+    let replay = match options.replay_path {
+        Some(ref path) => Some(Recording::load(path)?),
+        None => None,
+    };
+
+    if options.headless {
+        let state = replay.unwrap().replay(world)?;
+        info!("The replay matched the recording, ending {:?}", Outcome::of(&state));
+        return Ok(());
+    }
+
+    let mut state = State::Playing(world);
     let (controls, gui) = GuiSystem::new(!options.no_grab_mouse)
         .with_context(|err| format_err!("Failed to create GUI system: {}", err))?;
+    let render_data = RenderData::upload(assets, gui.facade())?;
 
-    let (render_data, world) = World::from_map_file(options.map_path, gui.facade())?;
-    let mut state = State::Playing(world);
+    let mut timestep = FixedTimestep::default();
+    let dt = timestep.step_millis();
+
+    // Input is polled and the world is rendered once per frame, but the simulation runs in
+    // fixed steps, as many as the time since the last frame makes up.
+    let mut controls = controls.with_quicksave_path(options.quicksave_path);
+    let mut simulation = Schedule::builder();
+    let recorder = options.record_path.as_ref().map(|_| Recorder::new(dt));
+    if let Some(ref recorder) = recorder {
+        controls = controls.with_recorder(recorder.clone());
+    }
+    if let Some(ref recording) = replay {
+        if recording.step_millis != dt {
+            bail!(
+                "The replay's steps are {}ms long, but the game's are {}ms long",
+                recording.step_millis,
+                dt
+            );
+        }
+        controls = controls.replaying();
+        simulation = simulation.system("replay", ReplaySystem::new(recording));
+    }
+    let mut input = Schedule::builder().system("controls", controls).build()?;
+    let mut simulation = add_simulation_systems(simulation).build()?;
+    let mut render = Schedule::builder()
+        .system("gui", gui.add_render_data(render_data))
+        .build()?;
+    info!("Simulation execution plan:\n{}", simulation);
 
-    let mut systems = hlist![
-        controls,
-        gui.add_render_data(render_data),
-        HoldSystem,
-        SinkingDoorSystem,
-        SnagSystem,
-        SpinningKeySystem,
-        TheFloorIsLavaSystem,
-        UnlockSystem,
-        WinSystem,
-    ];
     let mut last = Instant::now();
     while !state.should_close() {
-        let dt = last.elapsed();
-        last = Instant::now();
-        let dt = dt.subsec_millis() as u64 + 1_000_000 * dt.as_secs();
+        let now = Instant::now();
+        let steps = timestep.advance(now - last);
+        last = now;
+
+        input.run(&mut state, dt);
+
+        for _ in 0..steps {
+            if state.should_close() {
+                break;
+            }
+            if let Some(world) = state.world_mut() {
+                if let Some(time) = world.resource_mut::<Time>() {
+                    time.advance(dt);
+                }
+                world.update_events();
+            }
+            simulation.run(&mut state, dt);
 
-        systems
-            .to_mut()
-            .map(SystemStepper::with_args(&mut state, dt));
+            if let Some(world) = state.world_mut() {
+                if let Some(ref recorder) = recorder {
+                    recorder.record_step(world);
+                }
+                if let Some(ref recording) = replay {
+                    recording.check_step(world)?;
+                }
+            }
+        }
+
+        if let Some(world) = state.world_mut() {
+            world.insert_resource(Interpolation(timestep.alpha()));
+        }
+        render.run(&mut state, dt);
     }
 
+    if let (Some(recorder), Some(path)) = (recorder, options.record_path) {
+        recorder.finish(&state).save(&path)?;
+        info!("Recorded to {}", path.display());
+    }
     Ok(())
 }
@@ -1,8 +1,17 @@
-use failure::{Error, Fallible};
-use std::{path::PathBuf, str::FromStr};
+use crate::{
+    prefab::Placement,
+    util::{read_file_and_parse_to, read_file_and_unjson},
+};
+use failure::{Error, Fallible, ResultExt};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 /// The map as parsed.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Map {
     /// The dimensions of the map.
     pub dims: (usize, usize),
@@ -19,6 +28,10 @@ pub struct Map {
     /// The location of keys.
     pub keys: Vec<(usize, usize, char)>,
 
+    /// The enemies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enemies: Vec<Enemy>,
+
     /// The color to clear with.
     pub clear_color: [f32; 4],
 
@@ -39,23 +52,214 @@ pub struct Map {
 
     /// The decal to display on victory.
     pub win_decal: PathBuf,
+
+    /// The filenames of files of additional prefabs. Their prefabs replace any built-in prefabs
+    /// with the same names.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefabs: Vec<PathBuf>,
+
+    /// The prefabs to spawn for tile characters, overriding the defaults, which are `player` for
+    /// `S`, `goal` for `G`, `wall` for `W`, `door` for `A` through `E`, and `key` for `a` through
+    /// `e`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub legend: BTreeMap<char, String>,
+
+    /// Prefabs placed on specific tiles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub placements: Vec<Placement>,
+
+    /// The starts and goals which were replaced by a later `S` or `G` tile, when parsed from the
+    /// assignment's format. The JSON format can't have more than one of each.
+    #[serde(skip)]
+    pub replaced: Vec<(usize, usize, char)>,
 }
 
-impl FromStr for Map {
-    type Err = Error;
+impl Map {
+    /// Loads a map from a file, which may be in either the JSON format or the assignment's
+    /// format.
+    pub fn load(path: impl AsRef<Path>) -> Fallible<Map> {
+        match read_file_and_unjson(path.as_ref()) {
+            Ok(map) => Ok(map),
+            Err(err) => {
+                warn!("While loading map: {}", err);
+                warn!("Falling back to old-style map loading...");
+                let map = read_file_and_parse_to(path.as_ref())
+                    .with_context(|err| format_err!("While loading old-style map: {}", err))?;
+                info!("Successfully loaded old-style map.");
+                Ok(map)
+            }
+        }
+    }
 
-    fn from_str(s: &str) -> Fallible<Map> {
-        let w_end_idx = s.find(' ').unwrap();
-        let h_end_idx = s[w_end_idx..].find('\n').unwrap() + w_end_idx;
+    /// Returns the tile at the given location, or `None` if it's out of bounds.
+    pub fn tile(&self, x: usize, y: usize) -> Option<Tile> {
+        if x < self.dims.0 && y < self.dims.1 {
+            self.tiles.get(x + y * self.dims.0).cloned()
+        } else {
+            None
+        }
+    }
 
-        let w = &s[..w_end_idx];
-        let h = &s[w_end_idx + 1..h_end_idx];
-        let mut map = Map {
-            dims: (w.parse()?, h.parse()?),
+    /// Describes each part of the map that writing it in the assignment's format, with its
+    /// `Display` implementation, would lose. If there are none, parsing what's written gives back
+    /// the same map.
+    pub fn legacy_losses(&self) -> Vec<String> {
+        let mut losses = Vec::new();
+        let default = Map::default();
+        if self.clear_color != default.clear_color {
+            losses.push(format!(
+                "The clear color {:?} isn't the default of {:?}",
+                self.clear_color, default.clear_color
+            ));
+        }
+        if self.door_colors != default.door_colors {
+            losses.push(format!(
+                "The door colors {:?} aren't the defaults of {:?}",
+                self.door_colors, default.door_colors
+            ));
+        }
+        for &(name, ref path) in &[
+            ("floor material", &self.material_floor),
+            ("wall material", &self.material_wall),
+        ] {
+            if let Some(path) = path {
+                losses.push(format!("The {} {} can't be set", name, path.display()));
+            }
+        }
+        for &(name, path, default) in &[
+            ("fragment shader", &self.shader_frag, &default.shader_frag),
+            ("vertex shader", &self.shader_vert, &default.shader_vert),
+            ("win decal", &self.win_decal, &default.win_decal),
+        ] {
+            if path != default {
+                losses.push(format!(
+                    "The {} {} isn't the default of {}",
+                    name,
+                    path.display(),
+                    default.display()
+                ));
+            }
+        }
+        for path in &self.prefabs {
+            losses.push(format!("The prefabs in {} can't be used", path.display()));
+        }
+        for (ch, prefab) in &self.legend {
+            losses.push(format!("The legend entry {:?} => {:?} can't be set", ch, prefab));
+        }
+        for placement in &self.placements {
+            losses.push(format!(
+                "The {} prefab placed at ({}, {}) can't be placed",
+                placement.prefab, placement.at.0, placement.at.1
+            ));
+        }
+        for enemy in &self.enemies {
+            match enemy.patrol.first() {
+                Some(&(x, y)) => {
+                    losses.push(format!("The enemy at ({}, {}) can't be written", x, y))
+                }
+                None => losses.push("An enemy with no patrol can't be written".to_string()),
+            }
+        }
+
+        if self.tiles.len() != self.dims.0 * self.dims.1 {
+            losses.push(format!(
+                "The map has {} tiles rather than {}",
+                self.tiles.len(),
+                self.dims.0 * self.dims.1
+            ));
+        }
+        for y in 0..self.dims.1 {
+            for x in 0..self.dims.0 {
+                if let Some(Tile::Door(letter)) = self.tile(x, y) {
+                    match letter {
+                        'A'..='E' => {}
+                        _ => losses.push(format!(
+                            "The door {:?} at ({}, {}) can't be written",
+                            letter, x, y
+                        )),
+                    }
+                }
+            }
+        }
+
+        // Everything after the first of the start, goal, and keys on a tile is lost, as is the
+        // tile under them if it's not empty.
+        let things = Some((self.start.0, self.start.1, "start".to_string()))
+            .into_iter()
+            .chain(Some((self.goal.0, self.goal.1, "goal".to_string())))
+            .chain(
+                self.keys
+                    .iter()
+                    .map(|&(x, y, letter)| (x, y, format!("key {:?}", letter))),
+            );
+        let mut covered = Vec::new();
+        for (x, y, name) in things {
+            match self.tile(x, y) {
+                None => losses.push(format!("The {} at ({}, {}) is outside the map", name, x, y)),
+                Some(_) if covered.contains(&(x, y)) => losses.push(format!(
+                    "The {} at ({}, {}) shares its tile with something else",
+                    name, x, y
+                )),
+                Some(tile) => {
+                    match tile {
+                        Tile::Empty => {}
+                        Tile::Wall => {
+                            losses.push(format!("The {} at ({}, {}) is on a wall", name, x, y))
+                        }
+                        Tile::Door(letter) => losses.push(format!(
+                            "The {} at ({}, {}) is on the door {}",
+                            name, x, y, letter
+                        )),
+                    }
+                    covered.push((x, y));
+                }
+            }
+        }
+        for &(x, y, letter) in &self.keys {
+            match letter {
+                'a'..='e' => {}
+                _ => losses.push(format!(
+                    "The key {:?} at ({}, {}) can't be written",
+                    letter, x, y
+                )),
+            }
+        }
+        if !self.keys.windows(2).all(|w| (w[0].1, w[0].0) <= (w[1].1, w[1].0)) {
+            losses.push("The keys aren't in the order they're on the map".to_string());
+        }
+        if !self.replaced.is_empty() {
+            losses.push("The map has more than one start or goal".to_string());
+        }
+
+        losses
+    }
+
+    /// Returns the name of the prefab to spawn for a tile character.
+    pub fn prefab_for(&self, ch: char) -> Option<&str> {
+        if let Some(prefab) = self.legend.get(&ch) {
+            return Some(prefab);
+        }
+        match ch {
+            'S' => Some("player"),
+            'G' => Some("goal"),
+            'W' => Some("wall"),
+            'A'..='E' => Some("door"),
+            'a'..='e' => Some("key"),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Map {
+    /// Creates an empty map, with the settings maps in the assignment's format have.
+    fn default() -> Map {
+        Map {
+            dims: (0, 0),
             tiles: Vec::new(),
             start: (0, 0),
             goal: (0, 0),
             keys: Vec::new(),
+            enemies: Vec::new(),
 
             clear_color: [0.0; 4],
             door_colors: [
@@ -70,17 +274,95 @@ impl FromStr for Map {
             shader_frag: PathBuf::from("main.frag"),
             shader_vert: PathBuf::from("main.vert"),
             win_decal: PathBuf::from("win.png"),
+            prefabs: Vec::new(),
+            legend: BTreeMap::new(),
+            placements: Vec::new(),
+            replaced: Vec::new(),
+        }
+    }
+}
+
+impl Display for Map {
+    /// Writes the map in the assignment's format. Only the tiles, start, goal, and keys can be
+    /// written; where several are on the same tile, the start comes first, then the goal, then
+    /// keys. `Map::legacy_losses` describes what's left out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate game;
+    /// # use game::Map;
+    /// # fn main() {
+    /// let map = Map::load("maps/textured.json").unwrap();
+    /// assert_eq!(map.to_string(), "5 5\n0000G\nWW0W0\n0WAW0\n0W0WW\nS000a\n");
+    /// assert_eq!(
+    ///     map.legacy_losses(),
+    ///     vec![
+    ///         "The clear color [0.0, 0.0, 0.01, 1.0] isn't the default of [0.0, 0.0, 0.0, 0.0]",
+    ///         "The floor material floor.mtl can't be set",
+    ///         "The wall material wall.mtl can't be set",
+    ///     ]
+    /// );
+    ///
+    /// let legacy: Map = map.to_string().parse().unwrap();
+    /// assert_eq!(legacy.tiles, map.tiles);
+    /// assert!(legacy.legacy_losses().is_empty());
+    /// # }
+    /// ```
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        writeln!(fmt, "{} {}", self.dims.0, self.dims.1)?;
+        for y in 0..self.dims.1 {
+            for x in 0..self.dims.0 {
+                let key = self
+                    .keys
+                    .iter()
+                    .find(|&&(kx, ky, _)| (kx, ky) == (x, y))
+                    .map(|&(_, _, letter)| letter);
+                let ch = if (x, y) == self.start {
+                    'S'
+                } else if (x, y) == self.goal {
+                    'G'
+                } else if let Some(letter) = key {
+                    letter
+                } else {
+                    match self.tile(x, y) {
+                        Some(Tile::Empty) | None => '0',
+                        Some(Tile::Wall) => 'W',
+                        Some(Tile::Door(letter)) => letter,
+                    }
+                };
+                write!(fmt, "{}", ch)?;
+            }
+            writeln!(fmt)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Map {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Map> {
+        let w_end_idx = s.find(' ').unwrap();
+        let h_end_idx = s[w_end_idx..].find('\n').unwrap() + w_end_idx;
+
+        let w = &s[..w_end_idx];
+        let h = &s[w_end_idx + 1..h_end_idx];
+        let mut map = Map {
+            dims: (w.parse()?, h.parse()?),
+            ..Map::default()
         };
 
         let mut rest = &s[h_end_idx + 1..];
         let mut x = 0;
         let mut y = 0;
+        let mut seen = (false, false);
         while map.tiles.len() != map.dims.0 * map.dims.1 {
             let ch = rest
                 .chars()
                 .next()
                 .ok_or_else(|| format_err!("Unexpected EOF while parsing map body"))?;
-            parse_tile(&mut map, ch, x, y)?;
+            parse_tile(&mut map, &mut seen, ch, x, y)?;
             x += 1;
             if x > map.dims.0 {
                 x = 0;
@@ -98,6 +380,14 @@ impl FromStr for Map {
     }
 }
 
+/// An enemy placed on a map.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Enemy {
+    /// The tiles the enemy patrols between, in order. It starts on the first, and after reaching
+    /// the last, heads back to the first.
+    pub patrol: Vec<(usize, usize)>,
+}
+
 /// The floor map tile.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Tile {
@@ -114,14 +404,29 @@ pub enum Tile {
     Door(char),
 }
 
-fn parse_tile(map: &mut Map, ch: char, x: usize, y: usize) -> Fallible<()> {
+/// Parses a tile, where `seen` is whether a start and a goal have been parsed yet.
+fn parse_tile(
+    map: &mut Map,
+    seen: &mut (bool, bool),
+    ch: char,
+    x: usize,
+    y: usize,
+) -> Fallible<()> {
     let tile = match ch {
         '0' => Tile::Empty,
         'G' => {
+            if seen.1 {
+                map.replaced.push((map.goal.0, map.goal.1, 'G'));
+            }
+            seen.1 = true;
             map.goal = (x, y);
             Tile::Empty
         }
         'S' => {
+            if seen.0 {
+                map.replaced.push((map.start.0, map.start.1, 'S'));
+            }
+            seen.0 = true;
             map.start = (x, y);
             Tile::Empty
         }
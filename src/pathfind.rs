@@ -0,0 +1,152 @@
+//! Finding paths and lines of sight on a grid of tiles, for enemies to get around the map.
+//!
+//! Both functions take a closure saying which tiles can be passed through, so that callers can
+//! decide what blocks the way; `EnemySystem` treats walls and locked doors as blocking.
+
+use crate::solve::Move;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap},
+};
+
+/// Finds a shortest path between two tiles with A*, moving one tile at a time. The path starts
+/// with `from` and ends with `to`, and is `None` if `to` can't be reached. The tile `from` is
+/// assumed to be passable. Ties between equally short paths are always broken the same way.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{pathfind::find_path, Map, Tile};
+/// # fn main() {
+/// let map: Map = "3 3\nS00\nWW0\nG00\n".parse().unwrap();
+/// let passable = |(x, y)| map.tile(x, y) == Some(Tile::Empty);
+/// let path = find_path(map.dims, (0, 0), (0, 2), passable).unwrap();
+/// assert_eq!(path, vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)]);
+///
+/// assert_eq!(find_path(map.dims, (0, 0), (0, 1), passable), None);
+/// # }
+/// ```
+pub fn find_path(
+    dims: (usize, usize),
+    from: (usize, usize),
+    to: (usize, usize),
+    passable: impl Fn((usize, usize)) -> bool,
+) -> Option<Vec<(usize, usize)>> {
+    let distance = |a: (usize, usize), b: (usize, usize)| {
+        let dx = if a.0 > b.0 { a.0 - b.0 } else { b.0 - a.0 };
+        let dy = if a.1 > b.1 { a.1 - b.1 } else { b.1 - a.1 };
+        dx + dy
+    };
+
+    // The queue is ordered by the estimated length of the whole path, then by the distance left,
+    // then by the tile, so that which path is found doesn't depend on hashing.
+    let mut parents = HashMap::new();
+    let mut costs = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    parents.insert(from, None);
+    costs.insert(from, 0);
+    queue.push(Reverse((distance(from, to), distance(from, to), from)));
+    while let Some(Reverse((_, _, pos))) = queue.pop() {
+        if pos == to {
+            let mut path = vec![pos];
+            let mut current = pos;
+            while let Some(parent) = parents[&current] {
+                path.push(parent);
+                current = parent;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cost = costs[&pos] + 1;
+        for m in &Move::ALL {
+            let next = match m.from(pos, dims) {
+                Some(next) if passable(next) => next,
+                _ => continue,
+            };
+            let better = match costs.entry(next) {
+                Entry::Occupied(ref entry) if *entry.get() <= cost => false,
+                Entry::Occupied(mut entry) => {
+                    entry.insert(cost);
+                    true
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(cost);
+                    true
+                }
+            };
+            if better {
+                parents.insert(next, Some(pos));
+                let left = distance(next, to);
+                queue.push(Reverse((cost + left, left, next)));
+            }
+        }
+    }
+    None
+}
+
+/// Returns whether a straight line between two points, in tile units, only crosses passable
+/// tiles. A line passing exactly through the corner between tiles is blocked if either of the
+/// tiles beside the corner is.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{pathfind::line_of_sight, Map, Tile};
+/// # fn main() {
+/// let map: Map = "3 3\nS00\n0W0\n00G\n".parse().unwrap();
+/// let passable = |(x, y)| map.tile(x, y) == Some(Tile::Empty);
+/// assert!(line_of_sight((0.5, 0.5), (2.5, 0.5), passable));
+/// assert!(!line_of_sight((0.5, 0.5), (2.5, 2.5), passable));
+/// assert!(!line_of_sight((0.5, 1.5), (1.5, 0.5), passable));
+/// # }
+/// ```
+pub fn line_of_sight(
+    from: (f32, f32),
+    to: (f32, f32),
+    passable: impl Fn((usize, usize)) -> bool,
+) -> bool {
+    let passable = |x: isize, y: isize| x >= 0 && y >= 0 && passable((x as usize, y as usize));
+
+    // Walk through the tiles the line crosses, in order, stepping to whichever tile's edge the
+    // line reaches first.
+    let (mut x, mut y) = (from.0.floor() as isize, from.1.floor() as isize);
+    let end = (to.0.floor() as isize, to.1.floor() as isize);
+    let axis = |start: f32, delta: f32, tile: isize| {
+        if delta > 0.0 {
+            (1, (tile as f32 + 1.0 - start) / delta, 1.0 / delta)
+        } else if delta < 0.0 {
+            (-1, (tile as f32 - start) / delta, -1.0 / delta)
+        } else {
+            (0, ::std::f32::INFINITY, ::std::f32::INFINITY)
+        }
+    };
+    let (step_x, mut next_x, delta_x) = axis(from.0, to.0 - from.0, x);
+    let (step_y, mut next_y, delta_y) = axis(from.1, to.1 - from.1, y);
+
+    loop {
+        if !passable(x, y) {
+            return false;
+        }
+        if (x, y) == end || (next_x > 1.0 && next_y > 1.0) {
+            return true;
+        }
+        if next_x < next_y {
+            x += step_x;
+            next_x += delta_x;
+        } else if next_y < next_x {
+            y += step_y;
+            next_y += delta_y;
+        } else {
+            if !passable(x + step_x, y) || !passable(x, y + step_y) {
+                return false;
+            }
+            x += step_x;
+            y += step_y;
+            next_x += delta_x;
+            next_y += delta_y;
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Playing a map headlessly from a script, to check that it can be completed.
+//!
+//! A script has one command per line, and `#` starts a comment. The commands are:
+//!
+//! - `walk_to(x, y)`, which walks the player in a straight line to the center of a tile.
+//! - `turn(degrees)`, which turns the player, from +z towards +x.
+//! - `wait(ms)`, which lets the simulation run without any input.
+//! - `expect_held(a)`, which checks that the player is holding the key `a`.
+//! - `expect_unlocked(A)`, which checks that the door `A` has been unlocked.
+//! - `expect_done(ticks)`, which waits at most the given number of ticks for the goal to be
+//!   reached.
+//! - `expect_caught(ticks)`, which waits at most the given number of ticks for an enemy to have
+//!   caught the player.
+//!
+//! Each map in the `maps` directory has a script next to it, named by appending `.playtest` to
+//! the map's filename, which the tests check completes the map.
+
+use crate::{
+    components::{CameraComponent, HeldComponent, KeyComponent, LocationComponent},
+    events::{DoorUnlocked, PlayerCaught},
+    resources::{InputState, Time},
+    schedule::Schedule,
+    systems::{add_simulation_systems, MOVE_SPEED, TURN_SPEED},
+    timestep::STEP_MILLIS,
+    util::read_file_and_parse_to,
+    EventReader, State, With, World,
+};
+use failure::{Error, Fallible, ResultExt};
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter, Result as FmtResult},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// A command in a playtest script.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Walks to the center of the tile with the given coordinates.
+    WalkTo(usize, usize),
+
+    /// Turns by the given number of degrees.
+    Turn(f32),
+
+    /// Waits for the given number of milliseconds.
+    Wait(u64),
+
+    /// Checks that the key with the given letter is held.
+    ExpectHeld(char),
+
+    /// Checks that the door with the given letter has been unlocked.
+    ExpectUnlocked(char),
+
+    /// Waits at most the given number of ticks for the game to be won.
+    ExpectDone(u64),
+
+    /// Waits at most the given number of ticks for an enemy to have caught the player.
+    ExpectCaught(u64),
+}
+
+impl Display for Command {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Command::WalkTo(x, y) => write!(fmt, "walk_to({}, {})", x, y),
+            Command::Turn(degrees) => write!(fmt, "turn({})", degrees),
+            Command::Wait(ms) => write!(fmt, "wait({})", ms),
+            Command::ExpectHeld(letter) => write!(fmt, "expect_held({})", letter),
+            Command::ExpectUnlocked(letter) => write!(fmt, "expect_unlocked({})", letter),
+            Command::ExpectDone(ticks) => write!(fmt, "expect_done({})", ticks),
+            Command::ExpectCaught(ticks) => write!(fmt, "expect_caught({})", ticks),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Command> {
+        let s = s.trim();
+        let (name, args) = match (s.find('('), s.ends_with(')')) {
+            (Some(open), true) => (&s[..open], &s[open + 1..s.len() - 1]),
+            _ => bail!("Expected a command of the form name(args), found {:?}", s),
+        };
+        let args = args.split(',').map(str::trim).collect::<Vec<_>>();
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format_err!("{} takes {} arguments, not {}", name, n, args.len()))
+            }
+        };
+        let letter = |arg: &str| {
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(letter), None) => Ok(letter),
+                _ => Err(format_err!("Expected a single letter, found {:?}", arg)),
+            }
+        };
+
+        match name {
+            "walk_to" => {
+                arity(2)?;
+                Ok(Command::WalkTo(args[0].parse()?, args[1].parse()?))
+            }
+            "turn" => {
+                arity(1)?;
+                Ok(Command::Turn(args[0].parse()?))
+            }
+            "wait" => {
+                arity(1)?;
+                Ok(Command::Wait(args[0].parse()?))
+            }
+            "expect_held" => {
+                arity(1)?;
+                Ok(Command::ExpectHeld(letter(args[0])?))
+            }
+            "expect_unlocked" => {
+                arity(1)?;
+                Ok(Command::ExpectUnlocked(letter(args[0])?))
+            }
+            "expect_done" => {
+                arity(1)?;
+                Ok(Command::ExpectDone(args[0].parse()?))
+            }
+            "expect_caught" => {
+                arity(1)?;
+                Ok(Command::ExpectCaught(args[0].parse()?))
+            }
+            _ => bail!("Unknown command {:?}", name),
+        }
+    }
+}
+
+/// A playtest script, i.e. a list of commands along with the lines they were on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    /// The commands, each with its (1-based) line number.
+    pub commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    /// Loads a script from a file.
+    pub fn load(path: impl AsRef<Path>) -> Fallible<Script> {
+        read_file_and_parse_to(path)
+    }
+}
+
+impl FromStr for Script {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Script> {
+        let mut commands = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let command = line
+                .parse::<Command>()
+                .with_context(|err| format_err!("On line {}: {}", i + 1, err))?;
+            commands.push((i + 1, command));
+        }
+        Ok(Script { commands })
+    }
+}
+
+/// A game being played without a display, by commands rather than a user. The real simulation
+/// systems run at the same fixed timestep as in the game.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{playtest::Playtest, World};
+/// # fn main() {
+/// let (_, world) = World::from_map_file("maps/simple.map").unwrap();
+/// let mut playtest = Playtest::new(world).unwrap();
+///
+/// let script = "
+///     walk_to(4, 4)     # Pick up the key,
+///     expect_held(a)
+///     walk_to(2, 4)
+///     walk_to(2, 1)     # then go through the door.
+///     expect_unlocked(A)
+/// ";
+/// playtest.run_script(&script.parse().unwrap()).unwrap();
+/// assert!(!playtest.state().is_done());
+///
+/// let err = playtest.run_script(&"expect_held(a)".parse().unwrap()).unwrap_err();
+/// assert_eq!(err.to_string(), "On line 1, expect_held(a): The key a isn't held");
+/// # }
+/// ```
+pub struct Playtest {
+    state: State,
+    schedule: Schedule,
+    step_millis: u64,
+    ticks: u64,
+    door_unlocked: EventReader<DoorUnlocked>,
+    unlocked: BTreeSet<char>,
+    player_caught: EventReader<PlayerCaught>,
+    caught: bool,
+}
+
+impl Playtest {
+    /// Starts playing in the given world.
+    pub fn new(world: World) -> Fallible<Playtest> {
+        Ok(Playtest {
+            state: State::Playing(world),
+            schedule: add_simulation_systems(Schedule::builder()).build()?,
+            step_millis: STEP_MILLIS,
+            ticks: 0,
+            door_unlocked: EventReader::default(),
+            unlocked: BTreeSet::new(),
+            player_caught: EventReader::default(),
+            caught: false,
+        })
+    }
+
+    /// Loads a map and plays it with the script in the given file.
+    pub fn run_files(
+        map_path: impl AsRef<Path>,
+        script_path: impl AsRef<Path>,
+    ) -> Fallible<Playtest> {
+        let (_, world) = World::from_map_file(map_path)?;
+        let script = Script::load(script_path.as_ref())?;
+        let mut playtest = Playtest::new(world)?;
+        playtest.run_script(&script).with_context(|err| {
+            format_err!("While running {}: {}", script_path.as_ref().display(), err)
+        })?;
+        Ok(playtest)
+    }
+
+    /// Returns the state of the game.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Returns the number of steps that have run.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Runs each command of a script in turn, stopping at the first one that fails.
+    pub fn run_script(&mut self, script: &Script) -> Fallible<()> {
+        for &(line, command) in &script.commands {
+            self.run(command)
+                .with_context(|err| format_err!("On line {}, {}: {}", line, command, err))?;
+        }
+        Ok(())
+    }
+
+    /// Runs a single command.
+    pub fn run(&mut self, command: Command) -> Fallible<()> {
+        match command {
+            Command::WalkTo(x, y) => self.walk_to(x, y),
+            Command::Turn(degrees) => self.step(InputState {
+                move_yaw: degrees / TURN_SPEED,
+                ..InputState::default()
+            }),
+            Command::Wait(ms) => {
+                for _ in 0..(ms + self.step_millis - 1) / self.step_millis {
+                    self.step(InputState::default())?;
+                }
+                Ok(())
+            }
+            Command::ExpectHeld(letter) => {
+                let held = self
+                    .world()?
+                    .iter::<Hlist![&KeyComponent, With<HeldComponent>]>()
+                    .any(|(_, hlist_pat![key, _])| key.letter == letter);
+                if !held {
+                    bail!("The key {} isn't held", letter);
+                }
+                Ok(())
+            }
+            Command::ExpectUnlocked(letter) => {
+                if !self.unlocked.contains(&letter) {
+                    bail!("The door {} hasn't been unlocked", letter);
+                }
+                Ok(())
+            }
+            Command::ExpectDone(ticks) => {
+                if !self.wait_for(ticks, |playtest| playtest.state.is_done())? {
+                    bail!("The goal wasn't reached in {} ticks", ticks);
+                }
+                Ok(())
+            }
+            Command::ExpectCaught(ticks) => {
+                if !self.wait_for(ticks, |playtest| playtest.caught)? {
+                    bail!("No enemy caught the player in {} ticks", ticks);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs a single step with the given input.
+    pub fn step(&mut self, input: InputState) -> Fallible<()> {
+        let dt = self.step_millis;
+        {
+            let world = self.world()?;
+            world.insert_resource(input);
+            if let Some(time) = world.resource_mut::<Time>() {
+                time.advance(dt);
+            }
+            world.update_events();
+        }
+        self.schedule.run(&mut self.state, dt);
+        self.ticks += 1;
+
+        if let Some(world) = self.state.world_mut() {
+            for event in self.door_unlocked.read(world) {
+                self.unlocked.insert(event.letter);
+            }
+            if self.player_caught.read(world).count() > 0 {
+                self.caught = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs steps without any input until a check passes, for at most the given number of ticks.
+    /// Returns whether it passed.
+    fn wait_for(&mut self, ticks: u64, check: impl Fn(&Playtest) -> bool) -> Fallible<bool> {
+        for _ in 0..ticks {
+            if check(self) {
+                return Ok(true);
+            }
+            self.step(InputState::default())?;
+        }
+        Ok(check(self))
+    }
+
+    /// Walks to the center of a tile, turning to face it on the way.
+    fn walk_to(&mut self, x: usize, y: usize) -> Fallible<()> {
+        let (target_x, target_z) = (x as f32 + 0.5, y as f32 + 0.5);
+        let step_distance = MOVE_SPEED * self.step_millis as f32 / 1000.0;
+
+        // Allow for twice the time walking straight there would take, plus a second.
+        let start = self.camera()?;
+        let distance = (target_x - start.xyz.x).hypot(target_z - start.xyz.z);
+        let max_ticks = 2 * (distance / step_distance) as u64 + 1000 / self.step_millis;
+
+        for _ in 0..max_ticks {
+            let loc = self.camera()?;
+            let (dx, dz) = (target_x - loc.xyz.x, target_z - loc.xyz.z);
+            let distance = dx.hypot(dz);
+            if distance < 1e-3 {
+                return Ok(());
+            }
+
+            // Face the tile, slowing down so as not to overshoot it. Since the camera moves before
+            // it turns, it stands still while making a large turn.
+            let mut turn = (dx.atan2(dz).to_degrees() - loc.rotation[1]) % 360.0;
+            if turn > 180.0 {
+                turn -= 360.0;
+            } else if turn < -180.0 {
+                turn += 360.0;
+            }
+            let forward = if turn.abs() < 1.0 {
+                (distance / step_distance).min(1.0)
+            } else {
+                0.0
+            };
+            self.step(InputState {
+                move_forward: forward,
+                move_yaw: turn / TURN_SPEED,
+                ..InputState::default()
+            })?;
+        }
+
+        let loc = self.camera()?;
+        bail!(
+            "Couldn't reach the tile ({}, {}); got stuck at ({:.2}, {:.2}) after {} ticks",
+            x,
+            y,
+            loc.xyz.x - 0.5,
+            loc.xyz.z - 0.5,
+            max_ticks
+        )
+    }
+
+    /// Returns the location of the camera.
+    fn camera(&mut self) -> Fallible<LocationComponent> {
+        let (_, hlist_pat![_, &loc]) = self
+            .world()?
+            .single::<Hlist![With<CameraComponent>, &LocationComponent]>()?;
+        Ok(loc)
+    }
+
+    fn world(&mut self) -> Fallible<&mut World> {
+        self.state
+            .world_mut()
+            .ok_or_else(|| format_err!("The game has closed"))
+    }
+}
+
+/// Returns the path of the script for the map with the given path, i.e. the map's path with
+/// `.playtest` appended.
+pub fn script_path(map_path: impl AsRef<Path>) -> PathBuf {
+    let mut path = OsString::from(map_path.as_ref());
+    path.push(".playtest");
+    PathBuf::from(path)
+}
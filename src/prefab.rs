@@ -0,0 +1,295 @@
+//! Prefabs, i.e. named bundles of components which maps can spawn.
+//!
+//! A prefab's components are written in the form they're saved in snapshots, under the names they
+//! have in the `ComponentRegistry`. Before spawning, parameters are substituted into them: a
+//! string which is exactly `"$name"` is replaced by the value of the `name` parameter, and
+//! `"${name}"` within a longer string is replaced by the parameter's value as text.
+//!
+//! Each component is parsed into a template when its prefab is added, so that spawning a prefab
+//! many times, as maps do for walls, only fills in the parameters each time.
+
+use crate::{
+    snapshot::ComponentRegistry, util::read_file_and_unjson, ComponentBundle, Entity, World,
+};
+use failure::{Fallible, ResultExt};
+use serde_json::{from_str, Map as JsonMap, Value};
+use std::{collections::BTreeMap, path::Path};
+
+/// A named bundle of components.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Prefab {
+    /// The default values of the prefab's parameters.
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+
+    /// The prefab's components, by their registered names.
+    pub components: BTreeMap<String, Value>,
+}
+
+/// A prefab placed on a specific tile by a map.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Placement {
+    /// The name of the prefab.
+    pub prefab: String,
+
+    /// The tile to place the prefab on.
+    pub at: (usize, usize),
+
+    /// The values of the prefab's parameters, overriding its defaults.
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+}
+
+/// A set of prefabs, by name.
+pub struct PrefabRegistry {
+    components: ComponentRegistry,
+    prefabs: BTreeMap<String, Entry>,
+}
+
+/// A prefab, along with its components parsed into templates.
+struct Entry {
+    prefab: Prefab,
+    templates: Vec<(String, Template)>,
+}
+
+impl PrefabRegistry {
+    /// Creates a registry with no prefabs in it, whose prefabs may use the given components.
+    pub fn new(components: ComponentRegistry) -> PrefabRegistry {
+        PrefabRegistry {
+            components,
+            prefabs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a prefab, replacing any existing prefab with the same name. Returns an error if the
+    /// prefab has a component that isn't registered.
+    pub fn add(&mut self, name: impl Into<String>, prefab: Prefab) -> Fallible<()> {
+        let name = name.into();
+        let mut templates = Vec::new();
+        for (component, value) in &prefab.components {
+            if !self.components.has_component(component) {
+                bail!(
+                    "Unknown component {:?} in prefab {:?}; the known components are {}",
+                    component,
+                    name,
+                    self.components.component_names().collect::<Vec<_>>().join(", ")
+                );
+            }
+            let template = Template::parse(value).with_context(|err| {
+                format_err!("In the {} component of prefab {:?}: {}", component, name, err)
+            })?;
+            templates.push((component.clone(), template));
+        }
+        self.prefabs.insert(name, Entry { prefab, templates });
+        Ok(())
+    }
+
+    /// Adds the prefabs from a JSON file, which should contain an object mapping names to
+    /// prefabs.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> Fallible<()> {
+        let path = path.as_ref();
+        let prefabs: BTreeMap<String, Prefab> = read_file_and_unjson(path)?;
+        for (name, prefab) in prefabs {
+            self.add(name, prefab)
+                .with_context(|err| format_err!("While loading {}: {}", path.display(), err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the prefab with the given name.
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name).map(|entry| &entry.prefab)
+    }
+
+    /// Spawns an entity from the named prefab, with the given parameters overriding the prefab's
+    /// defaults.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate game;
+    /// # use game::{components::KeyComponent, prefab::PrefabRegistry, World};
+    /// # use std::collections::BTreeMap;
+    /// # fn main() {
+    /// let prefabs = PrefabRegistry::default();
+    /// let mut world = World::default();
+    ///
+    /// let mut params = BTreeMap::new();
+    /// params.insert("x".to_string(), json!(1.5));
+    /// params.insert("z".to_string(), json!(2.5));
+    /// params.insert("letter".to_string(), json!("c"));
+    /// let key = prefabs.spawn(&mut world, "key", params).unwrap();
+    /// assert_eq!(world.get_one::<KeyComponent>(key).unwrap().letter, 'c');
+    /// assert_eq!(world.named(key).to_string(), format!("{}:key-c", key));
+    ///
+    /// assert!(prefabs.spawn(&mut world, "dragon", Default::default()).is_err());
+    /// # }
+    /// ```
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        name: &str,
+        params: BTreeMap<String, Value>,
+    ) -> Fallible<Entity> {
+        let entry = self
+            .prefabs
+            .get(name)
+            .ok_or_else(|| format_err!("Unknown prefab {:?}", name))?;
+        let param = |param: &str| {
+            params
+                .get(param)
+                .or_else(|| entry.prefab.params.get(param))
+                .ok_or_else(|| format_err!("Unknown parameter {:?}", param))
+        };
+
+        let mut bundle = ComponentBundle::new();
+        let result = entry
+            .templates
+            .iter()
+            .try_for_each(|(component, template)| {
+                let value = template.fill(&param).with_context(|err| {
+                    format_err!("Couldn't load the {} component: {}", component, err)
+                })?;
+                self.components
+                    .insert_into_bundle(&mut bundle, component, value)
+            });
+        if let Err(err) = result {
+            return Err(err
+                .context(format_err!("While spawning the prefab {:?}", name))
+                .into());
+        }
+        Ok(world.new_entity_from_bundle(bundle))
+    }
+}
+
+impl Default for PrefabRegistry {
+    /// Creates a registry with the game's components and built-in prefabs in it.
+    fn default() -> PrefabRegistry {
+        let mut registry = PrefabRegistry::new(ComponentRegistry::default());
+        let prefabs: BTreeMap<String, Prefab> =
+            from_str(include_str!("prefabs.json")).expect("Invalid built-in prefabs");
+        for (name, prefab) in prefabs {
+            registry.add(name, prefab).expect("Invalid built-in prefab");
+        }
+        registry
+    }
+}
+
+/// A component of a prefab, with the parameters in it found ahead of time.
+#[derive(Clone, Debug)]
+enum Template {
+    /// A value with no parameters in it.
+    Value(Value),
+
+    /// A string which is exactly `"$name"`.
+    Param(String),
+
+    /// A string with `"${name}"`s in it, split into text and the names of parameters.
+    Text(Vec<Piece>),
+
+    /// An array with parameters in it.
+    Array(Vec<Template>),
+
+    /// An object with parameters in it.
+    Object(Vec<(String, Template)>),
+}
+
+/// A part of a string with `"${name}"`s in it.
+#[derive(Clone, Debug)]
+enum Piece {
+    Text(String),
+    Param(String),
+}
+
+impl Template {
+    /// Finds the parameters in a value.
+    fn parse(value: &Value) -> Fallible<Template> {
+        Ok(match *value {
+            Value::String(ref s) if s.starts_with('$') && !s.starts_with("${") => {
+                Template::Param(s[1..].to_string())
+            }
+            Value::String(ref s) if s.contains("${") => {
+                let mut pieces = Vec::new();
+                let mut rest = &s[..];
+                while let Some(start) = rest.find("${") {
+                    let len = rest[start..]
+                        .find('}')
+                        .ok_or_else(|| format_err!("Unclosed parameter in {:?}", s))?;
+                    pieces.push(Piece::Text(rest[..start].to_string()));
+                    pieces.push(Piece::Param(rest[start + 2..start + len].to_string()));
+                    rest = &rest[start + len + 1..];
+                }
+                pieces.push(Piece::Text(rest.to_string()));
+                Template::Text(pieces)
+            }
+            Value::Array(ref values) => {
+                let templates = values
+                    .iter()
+                    .map(Template::parse)
+                    .collect::<Fallible<Vec<_>>>()?;
+                if templates.iter().all(Template::is_value) {
+                    Template::Value(value.clone())
+                } else {
+                    Template::Array(templates)
+                }
+            }
+            Value::Object(ref values) => {
+                let templates = values
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), Template::parse(value)?)))
+                    .collect::<Fallible<Vec<_>>>()?;
+                if templates.iter().all(|(_, template)| template.is_value()) {
+                    Template::Value(value.clone())
+                } else {
+                    Template::Object(templates)
+                }
+            }
+            ref value => Template::Value(value.clone()),
+        })
+    }
+
+    /// Returns whether the template has no parameters in it.
+    fn is_value(&self) -> bool {
+        match *self {
+            Template::Value(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Substitutes parameters into the template, looking them up with the given function.
+    fn fill<'a, F>(&self, param: &F) -> Fallible<Value>
+    where
+        F: Fn(&str) -> Fallible<&'a Value>,
+    {
+        Ok(match *self {
+            Template::Value(ref value) => value.clone(),
+            Template::Param(ref name) => param(name)?.clone(),
+            Template::Text(ref pieces) => {
+                let mut out = String::new();
+                for piece in pieces {
+                    match *piece {
+                        Piece::Text(ref text) => out += text,
+                        Piece::Param(ref name) => match *param(name)? {
+                            Value::String(ref value) => out += value,
+                            ref value => out += &value.to_string(),
+                        },
+                    }
+                }
+                Value::String(out)
+            }
+            Template::Array(ref templates) => Value::Array(
+                templates
+                    .iter()
+                    .map(|template| template.fill(param))
+                    .collect::<Fallible<_>>()?,
+            ),
+            Template::Object(ref templates) => Value::Object(
+                templates
+                    .iter()
+                    .map(|(key, template)| Ok((key.clone(), template.fill(param)?)))
+                    .collect::<Fallible<JsonMap<_, _>>>()?,
+            ),
+        })
+    }
+}
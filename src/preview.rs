@@ -0,0 +1,389 @@
+//! Drawing maps from above, as PNG or SVG images, without needing a GL context.
+
+use crate::{solve::Solution, Map, Tile};
+use image::RgbaImage;
+use std::fmt::{Result as FmtResult, Write};
+
+/// The color of the floor.
+const FLOOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// The color of walls.
+const WALL: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+
+/// The color of doors whose letters have no color.
+const UNKNOWN_DOOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+/// The colors of the start and goal markers.
+const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// The color of a solution's path.
+const PATH: [f32; 4] = [1.0, 0.5, 0.1, 0.8];
+
+/// The colors of enemies, and of the lines between their waypoints.
+const ENEMY: [f32; 4] = [0.8, 0.0, 0.0, 1.0];
+const PATROL: [f32; 4] = [0.8, 0.0, 0.0, 0.5];
+
+/// A top-down drawing of a map, which can be rendered as a PNG or SVG image. The map is drawn
+/// with a border of wall around it, as it has in the game. Doors are the color `door_colors`
+/// gives them, keys are circles of the inverse color, the start is a ringed circle, and the goal
+/// is a checkered square. Enemies are red circles on the first tiles of their patrols, with thin
+/// lines going through the rest of their waypoints.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{preview::Preview, solve::{solve, Verdict}, Map};
+/// # fn main() {
+/// let map = Map::load("maps/simple.map").unwrap();
+/// let solution = match solve(&map).unwrap() {
+///     Verdict::Solvable(solution) => solution,
+///     Verdict::Unsolvable(why) => panic!("{}", why),
+/// };
+/// let preview = Preview::new(&map, Some(&solution));
+///
+/// // The 5x5 map and its border, at 8 pixels per tile.
+/// let png = preview.to_png(8);
+/// assert_eq!(png.dimensions(), (56, 56));
+/// let svg = preview.to_svg(8);
+/// assert!(svg.starts_with("<svg"));
+/// assert!(svg.contains("<polyline"));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Preview {
+    dims: (usize, usize),
+    shapes: Vec<Shape>,
+}
+
+/// A shape, in tile units.
+#[derive(Clone, Debug)]
+enum Shape {
+    /// A rectangle, with its top-left corner and its size.
+    Rect {
+        min: (f32, f32),
+        size: (f32, f32),
+        color: [f32; 4],
+    },
+
+    /// A circle.
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+        color: [f32; 4],
+    },
+
+    /// A line through the points, with rounded ends.
+    Line {
+        points: Vec<(f32, f32)>,
+        width: f32,
+        color: [f32; 4],
+    },
+}
+
+impl Preview {
+    /// Draws a map, with the path a solution to it takes if one is given.
+    pub fn new(map: &Map, solution: Option<&Solution>) -> Preview {
+        let dims = (map.dims.0 + 2, map.dims.1 + 2);
+        let mut shapes = vec![Shape::Rect {
+            min: (0.0, 0.0),
+            size: (dims.0 as f32, dims.1 as f32),
+            color: WALL,
+        }];
+        let tile = |x: usize, y: usize, inset: f32, color| Shape::Rect {
+            min: (x as f32 + 1.0 + inset, y as f32 + 1.0 + inset),
+            size: (1.0 - 2.0 * inset, 1.0 - 2.0 * inset),
+            color,
+        };
+        let center = |x: usize, y: usize| (x as f32 + 1.5, y as f32 + 1.5);
+        let door_color = |letter: char| {
+            let i = (letter as usize).wrapping_sub('A' as usize);
+            match map.door_colors.get(i) {
+                Some(&[r, g, b]) => [r, g, b, 1.0],
+                None => UNKNOWN_DOOR,
+            }
+        };
+
+        for y in 0..map.dims.1 {
+            for x in 0..map.dims.0 {
+                match map.tile(x, y) {
+                    Some(Tile::Empty) => shapes.push(tile(x, y, 0.0, FLOOR)),
+                    Some(Tile::Wall) | None => {}
+                    Some(Tile::Door(letter)) => {
+                        shapes.push(tile(x, y, 0.0, FLOOR));
+                        shapes.push(tile(x, y, 0.1, door_color(letter)));
+                    }
+                }
+            }
+        }
+
+        if let Some(solution) = solution {
+            shapes.push(Shape::Line {
+                points: solution
+                    .path(map)
+                    .into_iter()
+                    .map(|(x, y)| center(x, y))
+                    .collect(),
+                width: 0.2,
+                color: PATH,
+            });
+        }
+
+        for &(x, y, letter) in &map.keys {
+            let [r, g, b, _] = door_color(letter.to_ascii_uppercase());
+            shapes.push(Shape::Circle {
+                center: center(x, y),
+                radius: 0.25,
+                color: [1.0 - r, 1.0 - g, 1.0 - b, 1.0],
+            });
+        }
+
+        for enemy in &map.enemies {
+            if enemy.patrol.len() > 1 {
+                let mut points = enemy
+                    .patrol
+                    .iter()
+                    .map(|&(x, y)| center(x, y))
+                    .collect::<Vec<_>>();
+                points.push(points[0]);
+                shapes.push(Shape::Line {
+                    points,
+                    width: 0.08,
+                    color: PATROL,
+                });
+            }
+            if let Some(&(x, y)) = enemy.patrol.first() {
+                shapes.push(Shape::Circle {
+                    center: center(x, y),
+                    radius: 0.3,
+                    color: ENEMY,
+                });
+            }
+        }
+
+        let (x, y) = map.start;
+        shapes.push(Shape::Circle {
+            center: center(x, y),
+            radius: 0.35,
+            color: BLACK,
+        });
+        shapes.push(Shape::Circle {
+            center: center(x, y),
+            radius: 0.25,
+            color: WHITE,
+        });
+
+        let (x, y) = map.goal;
+        shapes.push(tile(x, y, 0.15, BLACK));
+        for &(dx, dy) in &[(0.0, 0.0), (0.35, 0.35)] {
+            shapes.push(Shape::Rect {
+                min: (x as f32 + 1.15 + dx, y as f32 + 1.15 + dy),
+                size: (0.35, 0.35),
+                color: WHITE,
+            });
+        }
+
+        Preview { dims, shapes }
+    }
+
+    /// Renders the drawing as an image with the given number of pixels per tile.
+    pub fn to_png(&self, scale: u32) -> RgbaImage {
+        let (width, height) = (self.dims.0 as u32 * scale, self.dims.1 as u32 * scale);
+        let mut image = RgbaImage::new(width, height);
+        for shape in &self.shapes {
+            let color = shape.color();
+            let bytes = to_rgba8(color);
+
+            // Only visit the pixels in the shape's bounding box.
+            let (min, max) = shape.bounds();
+            let to_pixel = |t: f32, max: u32| ((t * scale as f32).max(0.0) as u32).min(max);
+            for py in to_pixel(min.1, height)..to_pixel(max.1 + 1.0, height) {
+                for px in to_pixel(min.0, width)..to_pixel(max.0 + 1.0, width) {
+                    let point = (
+                        (px as f32 + 0.5) / scale as f32,
+                        (py as f32 + 0.5) / scale as f32,
+                    );
+                    if shape.contains(point) {
+                        let pixel = &mut image.get_pixel_mut(px, py).data;
+                        *pixel = if color[3] >= 1.0 {
+                            bytes
+                        } else {
+                            to_rgba8(blend(from_rgba8(*pixel), color))
+                        };
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Renders the drawing as an SVG document, which is the given number of pixels per tile in
+    /// size by default.
+    pub fn to_svg(&self, scale: u32) -> String {
+        let mut svg = String::new();
+        self.write_svg(&mut svg, scale)
+            .expect("Writing to a String failed");
+        svg
+    }
+
+    fn write_svg(&self, svg: &mut String, scale: u32) -> FmtResult {
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\">",
+            self.dims.0 as u32 * scale,
+            self.dims.1 as u32 * scale,
+            self.dims.0,
+            self.dims.1
+        )?;
+        for shape in &self.shapes {
+            match *shape {
+                Shape::Rect { min, size, color } => writeln!(
+                    svg,
+                    "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+                    min.0,
+                    min.1,
+                    size.0,
+                    size.1,
+                    svg_paint("fill", color)
+                )?,
+                Shape::Circle {
+                    center,
+                    radius,
+                    color,
+                } => writeln!(
+                    svg,
+                    "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+                    center.0,
+                    center.1,
+                    radius,
+                    svg_paint("fill", color)
+                )?,
+                Shape::Line {
+                    ref points,
+                    width,
+                    color,
+                } => writeln!(
+                    svg,
+                    "  <polyline points=\"{}\" fill=\"none\" stroke-width=\"{}\" \
+                     stroke-linecap=\"round\" stroke-linejoin=\"round\" {}/>",
+                    points
+                        .iter()
+                        .map(|&(x, y)| format!("{},{}", x, y))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    width,
+                    svg_paint("stroke", color)
+                )?,
+            }
+        }
+        writeln!(svg, "</svg>")
+    }
+}
+
+impl Shape {
+    fn color(&self) -> [f32; 4] {
+        match *self {
+            Shape::Rect { color, .. } | Shape::Circle { color, .. } | Shape::Line { color, .. } => {
+                color
+            }
+        }
+    }
+
+    /// Returns the top-left and bottom-right corners of a box around the shape.
+    fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        match *self {
+            Shape::Rect { min, size, .. } => (min, (min.0 + size.0, min.1 + size.1)),
+            Shape::Circle { center, radius, .. } => (
+                (center.0 - radius, center.1 - radius),
+                (center.0 + radius, center.1 + radius),
+            ),
+            Shape::Line {
+                ref points, width, ..
+            } => points.iter().fold(
+                ((::std::f32::INFINITY, ::std::f32::INFINITY), (0.0, 0.0)),
+                |(min, max), &(x, y)| {
+                    (
+                        (min.0.min(x - width), min.1.min(y - width)),
+                        (max.0.max(x + width), max.1.max(y + width)),
+                    )
+                },
+            ),
+        }
+    }
+
+    fn contains(&self, (x, y): (f32, f32)) -> bool {
+        match *self {
+            Shape::Rect { min, size, .. } => {
+                min.0 <= x && x < min.0 + size.0 && min.1 <= y && y < min.1 + size.1
+            }
+            Shape::Circle { center, radius, .. } => {
+                (x - center.0).hypot(y - center.1) < radius
+            }
+            Shape::Line {
+                ref points, width, ..
+            } => {
+                let near = |a: (f32, f32), b: (f32, f32)| {
+                    // Find the nearest point on the segment from a to b.
+                    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                    let length2 = dx * dx + dy * dy;
+                    let t = if length2 == 0.0 {
+                        0.0
+                    } else {
+                        (((x - a.0) * dx + (y - a.1) * dy) / length2).max(0.0).min(1.0)
+                    };
+                    (x - a.0 - t * dx).hypot(y - a.1 - t * dy) < width / 2.0
+                };
+                match points.len() {
+                    0 => false,
+                    1 => near(points[0], points[0]),
+                    _ => points.windows(2).any(|w| near(w[0], w[1])),
+                }
+            }
+        }
+    }
+}
+
+/// Draws a color over another, by its alpha.
+fn blend(under: [f32; 4], over: [f32; 4]) -> [f32; 4] {
+    let alpha = over[3] + under[3] * (1.0 - over[3]);
+    if alpha == 0.0 {
+        return [0.0; 4];
+    }
+    let mut out = [0.0; 4];
+    for i in 0..3 {
+        out[i] = (over[i] * over[3] + under[i] * under[3] * (1.0 - over[3])) / alpha;
+    }
+    out[3] = alpha;
+    out
+}
+
+/// Converts a color to the bytes of an image's pixel.
+fn to_rgba8(color: [f32; 4]) -> [u8; 4] {
+    let mut out = [0; 4];
+    for (out, &c) in out.iter_mut().zip(&color) {
+        *out = (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    }
+    out
+}
+
+/// Converts the bytes of an image's pixel to a color.
+fn from_rgba8(pixel: [u8; 4]) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for (out, &c) in out.iter_mut().zip(&pixel) {
+        *out = f32::from(c) / 255.0;
+    }
+    out
+}
+
+/// Returns the SVG attributes to fill or stroke with a color.
+fn svg_paint(attr: &str, color: [f32; 4]) -> String {
+    let [r, g, b, _] = to_rgba8(color);
+    let paint = format!("{}=\"#{:02x}{:02x}{:02x}\"", attr, r, g, b);
+    if color[3] < 1.0 {
+        format!("{} {}-opacity=\"{}\"", paint, attr, color[3])
+    } else {
+        paint
+    }
+}
@@ -0,0 +1,375 @@
+//! Recording the user's inputs, and replaying them to reproduce a game exactly.
+//!
+//! Since the simulation runs in fixed steps, feeding the same inputs to the same steps always
+//! gives the same result. A `Recording` stores each input along with the step it arrived before,
+//! and a checksum of the world after every step, so a replay can tell where it diverged.
+
+use crate::{
+    components::{
+        CollisionComponent, DoorComponent, EnemyComponent, HeldComponent, KeyComponent,
+        LocationComponent,
+    },
+    resources::{Input, InputState, Time},
+    schedule::Schedule,
+    systems::add_simulation_systems,
+    util::read_file_and_unjson,
+    Entity, State, System, With, World,
+};
+use failure::{Fallible, ResultExt};
+use serde_json::to_writer_pretty;
+use std::{cell::RefCell, fs::File, path::Path, rc::Rc};
+
+/// An input, along with the number of simulation steps that had run when it arrived.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordedInput {
+    /// The number of steps that had run, i.e. `Time::frame` when the input arrived.
+    pub tick: u64,
+
+    /// The input.
+    pub input: Input,
+}
+
+/// How a game ended.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Outcome {
+    /// The game was still being played.
+    #[serde(rename = "playing")]
+    Playing,
+
+    /// The goal had been reached.
+    #[serde(rename = "done")]
+    Done,
+
+    /// An enemy had just caught the player.
+    #[serde(rename = "lost")]
+    Lost,
+
+    /// The game was closed.
+    #[serde(rename = "close")]
+    Close,
+}
+
+impl Outcome {
+    /// Returns the outcome the state represents.
+    pub fn of(state: &State) -> Outcome {
+        match *state {
+            State::Playing(_) => Outcome::Playing,
+            State::Done(_, _) => Outcome::Done,
+            State::Lost(_, _) => Outcome::Lost,
+            State::Close => Outcome::Close,
+        }
+    }
+}
+
+/// The inputs of a game, along with enough information to check that replaying them reproduces
+/// the game.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{
+/// #     replay::{Outcome, Recorder},
+/// #     resources::{Input, InputState, Time},
+/// #     schedule::Schedule,
+/// #     systems::add_simulation_systems,
+/// #     State, World,
+/// # };
+/// # fn main() {
+/// let (_, world) = World::from_map_file("maps/textured.json").unwrap();
+/// let mut schedule = add_simulation_systems(Schedule::builder()).build().unwrap();
+/// let mut state = State::Playing(world);
+///
+/// // Walk forwards for a while, then quit, as ControlSystem would.
+/// let recorder = Recorder::new(8);
+/// for tick in 0..50 {
+///     let input = match tick {
+///         10 => Input::Key { scancode: 17, pressed: true },
+///         40 => Input::Key { scancode: 1, pressed: true },
+///         _ => Input::MouseMotion { x: 1.0, y: 0.0 },
+///     };
+///     recorder.record_input(tick, input);
+///     if input.is_close() {
+///         state = State::Close;
+///         break;
+///     }
+///
+///     let world = state.world_mut().unwrap();
+///     world.resource_mut::<InputState>().unwrap().apply(input);
+///     world.resource_mut::<Time>().unwrap().advance(8);
+///     schedule.run(&mut state, 8);
+///     recorder.record_step(state.world_mut().unwrap());
+/// }
+/// let recording = recorder.finish(&state);
+/// assert_eq!(recording.checksums.len(), 40);
+///
+/// // Replaying the inputs gives the same game.
+/// let (_, world) = World::from_map_file("maps/textured.json").unwrap();
+/// let state = recording.replay(world).unwrap();
+/// assert_eq!(Outcome::of(&state), Outcome::Close);
+///
+/// // A game that went differently is caught.
+/// let mut tampered = recording.clone();
+/// tampered.checksums[20] ^= 1;
+/// let (_, world) = World::from_map_file("maps/textured.json").unwrap();
+/// let err = tampered.replay(world).err().unwrap();
+/// assert!(err.to_string().starts_with("The replay diverged from the recording at tick 21"));
+/// # }
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Recording {
+    /// The length of a simulation step, in milliseconds.
+    pub step_millis: u64,
+
+    /// The inputs, in the order they arrived.
+    pub inputs: Vec<RecordedInput>,
+
+    /// The checksum of the world after each step; the first is after one step has run.
+    pub checksums: Vec<u64>,
+
+    /// How the game ended, or `None` if the recording isn't finished.
+    pub outcome: Option<Outcome>,
+}
+
+impl Recording {
+    /// Creates an empty recording of a game whose steps are `step_millis` milliseconds long.
+    pub fn new(step_millis: u64) -> Recording {
+        Recording {
+            step_millis,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    /// Loads a recording from a JSON file written by `Recording::save`.
+    pub fn load(path: impl AsRef<Path>) -> Fallible<Recording> {
+        read_file_and_unjson(path.as_ref())
+    }
+
+    /// Saves the recording to a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Fallible<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|err| format_err!("Couldn't create {}: {}", path.display(), err))?;
+        to_writer_pretty(file, self)
+            .with_context(|err| format_err!("Couldn't write to {}: {}", path.display(), err))?;
+        Ok(())
+    }
+
+    /// Checks that the world matches the recording after the step given by its `Time::frame`.
+    pub fn check_step(&self, world: &World) -> Fallible<()> {
+        let tick = world.resource::<Time>().map_or(0, |time| time.frame);
+        let expected = match self.checksums.get(tick.wrapping_sub(1) as usize) {
+            Some(&expected) => expected,
+            None => bail!(
+                "The replay ran for longer than the recording, which has {} ticks",
+                self.checksums.len()
+            ),
+        };
+        let actual = checksum(world);
+        if actual != expected {
+            bail!(
+                "The replay diverged from the recording at tick {}: expected the checksum \
+                 {:016x}, but got {:016x}",
+                tick,
+                expected,
+                actual
+            );
+        }
+        Ok(())
+    }
+
+    /// Replays the recording without a display, as fast as possible, starting from the given
+    /// world. Returns the state the game ended in, or an error if it didn't end the way the
+    /// recording did.
+    pub fn replay(&self, world: World) -> Fallible<State> {
+        let outcome = match self.outcome {
+            Some(outcome) => outcome,
+            None => bail!("The recording isn't finished"),
+        };
+        let mut schedule =
+            add_simulation_systems(Schedule::builder().system("replay", ReplaySystem::new(self)))
+                .build()?;
+
+        let mut state = State::Playing(world);
+        let mut ticks = 0;
+        while !state.should_close() && ticks <= self.checksums.len() {
+            if let Some(world) = state.world_mut() {
+                if let Some(time) = world.resource_mut::<Time>() {
+                    time.advance(self.step_millis);
+                }
+                world.update_events();
+            }
+            schedule.run(&mut state, self.step_millis);
+            ticks += 1;
+
+            if let Some(world) = state.world_mut() {
+                self.check_step(world)?;
+            }
+        }
+
+        if Outcome::of(&state) != outcome {
+            bail!(
+                "The replay ended {:?} after {} ticks, but the recording ended {:?} after {}",
+                Outcome::of(&state),
+                ticks,
+                outcome,
+                self.checksums.len()
+            );
+        }
+        Ok(state)
+    }
+}
+
+/// A handle to a `Recording` being made. Clones of a recorder all add to the same recording, so
+/// `ControlSystem` can record inputs while the main loop records steps.
+#[derive(Clone)]
+pub struct Recorder(Rc<RefCell<Recording>>);
+
+impl Recorder {
+    /// Starts a recording of a game whose steps are `step_millis` milliseconds long.
+    pub fn new(step_millis: u64) -> Recorder {
+        Recorder(Rc::new(RefCell::new(Recording::new(step_millis))))
+    }
+
+    /// Records an input which arrived after `tick` steps had run.
+    pub fn record_input(&self, tick: u64, input: Input) {
+        self.0.borrow_mut().inputs.push(RecordedInput { tick, input });
+    }
+
+    /// Records the checksum of the world after a step has run.
+    pub fn record_step(&self, world: &World) {
+        let checksum = checksum(world);
+        self.0.borrow_mut().checksums.push(checksum);
+    }
+
+    /// Finishes the recording, with the game having ended in the given state.
+    pub fn finish(&self, state: &State) -> Recording {
+        let mut recording = self.0.borrow_mut();
+        recording.outcome = Some(Outcome::of(state));
+        recording.clone()
+    }
+}
+
+/// A system that feeds the inputs of a `Recording` to the simulation, in place of
+/// `ControlSystem`. It should run at the start of every simulation step.
+pub struct ReplaySystem {
+    inputs: Vec<RecordedInput>,
+    next: usize,
+}
+
+impl ReplaySystem {
+    /// Creates a system which replays the recording's inputs.
+    pub fn new(recording: &Recording) -> ReplaySystem {
+        ReplaySystem {
+            inputs: recording.inputs.clone(),
+            next: 0,
+        }
+    }
+}
+
+impl System for ReplaySystem {
+    fn step(&mut self, state: &mut State, _dt: u64) {
+        // Time has already been advanced for this step, so the inputs to apply are those which
+        // arrived before it.
+        let tick = match state.world_mut() {
+            Some(world) => world.resource::<Time>().map_or(0, |time| time.frame),
+            None => return,
+        };
+        while let Some(&RecordedInput { tick: input_tick, input }) = self.inputs.get(self.next) {
+            if input_tick >= tick {
+                break;
+            }
+            self.next += 1;
+
+            if input.is_close() {
+                *state = State::Close;
+                return;
+            }
+            if let Some(world) = state.world_mut() {
+                if world.resource::<InputState>().is_none() {
+                    world.insert_resource(InputState::default());
+                }
+                world.resource_mut::<InputState>().unwrap().apply(input);
+            }
+        }
+    }
+}
+
+/// Computes a checksum of the parts of the world that change as the game is played: the step,
+/// which entities are alive and where they are, which doors are locked and keys held, and where
+/// the enemies are headed. Since this runs after every step, it hashes the components directly
+/// rather than going through a snapshot.
+pub fn checksum(world: &World) -> u64 {
+    let mut hash = Fnv::default();
+    hash.write_u64(world.resource::<Time>().map_or(0, |time| time.frame));
+    for (entity, hlist_pat![loc]) in world.iter::<Hlist![&LocationComponent]>() {
+        hash.write_entity(entity);
+        for &x in &[
+            loc.xyz.x,
+            loc.xyz.y,
+            loc.xyz.z,
+            loc.rotation.x,
+            loc.rotation.y,
+            loc.rotation.z,
+            loc.scale,
+        ] {
+            hash.write_u32(x.to_bits());
+        }
+    }
+    for (entity, hlist_pat![_, collision]) in
+        world.iter::<Hlist![With<DoorComponent>, Option<&CollisionComponent>]>()
+    {
+        hash.write_entity(entity);
+        hash.write_u32(collision.is_some() as u32);
+    }
+    for (entity, hlist_pat![_, held]) in
+        world.iter::<Hlist![With<KeyComponent>, Option<&HeldComponent>]>()
+    {
+        hash.write_entity(entity);
+        hash.write_u32(held.is_some() as u32);
+    }
+    for (entity, hlist_pat![enemy]) in world.iter::<Hlist![&EnemyComponent]>() {
+        hash.write_entity(entity);
+        hash.write_u64(enemy.next as u64);
+        hash.write_u32(enemy.last_seen.is_some() as u32);
+        if let Some((x, z)) = enemy.last_seen {
+            hash.write_u64(x as u64);
+            hash.write_u64(z as u64);
+        }
+    }
+    hash.0
+}
+
+/// FNV-1a, which unlike the standard library's hasher is guaranteed to stay the same. Numbers are
+/// hashed as little-endian bytes, so checksums are the same on every platform.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_entity(&mut self, entity: Entity) {
+        self.write_u32(entity.index());
+        self.write_u32(entity.generation());
+    }
+}
@@ -0,0 +1,150 @@
+//! Common resources, i.e. global values stored in the `World`.
+
+use crate::Tile;
+
+/// The time elapsed in the game, which advances by a fixed amount each simulation step.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Time {
+    /// The length of the current step, in milliseconds.
+    pub dt: u64,
+
+    /// The total time elapsed, in milliseconds.
+    pub elapsed: u64,
+
+    /// The number of steps that have started.
+    pub frame: u64,
+}
+
+impl Time {
+    /// Starts a new step, which was `dt` milliseconds after the previous one.
+    pub fn advance(&mut self, dt: u64) {
+        self.dt = dt;
+        self.elapsed += dt;
+        self.frame += 1;
+    }
+}
+
+/// The state of the user's input.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputState {
+    /// How the player is moving forward; `1.0` for forwards, `-1.0` for backwards.
+    pub move_forward: f32,
+
+    /// How the player is moving sideways; `1.0` for right, `-1.0` for left.
+    pub move_strafe: f32,
+
+    /// How far the mouse has moved vertically since `MovementSystem` last turned the camera.
+    pub move_pitch: f32,
+
+    /// How far the mouse has moved horizontally since `MovementSystem` last turned the camera.
+    pub move_yaw: f32,
+}
+
+impl InputState {
+    /// Updates the state with an input from the user. Inputs that don't move the camera are
+    /// ignored.
+    pub fn apply(&mut self, input: Input) {
+        match input {
+            Input::Key {
+                scancode,
+                pressed: true,
+            } => match scancode {
+                17 => self.move_forward = 1.0,  // W
+                30 => self.move_strafe = -1.0,  // A
+                31 => self.move_forward = -1.0, // S
+                32 => self.move_strafe = 1.0,   // D
+                _ => {}
+            },
+            Input::Key {
+                scancode,
+                pressed: false,
+            } => match scancode {
+                17 | 31 => self.move_forward = 0.0, // W, S
+                30 | 32 => self.move_strafe = 0.0,  // A, D
+                _ => {}
+            },
+            Input::MouseMotion { x, y } => {
+                self.move_yaw -= x;
+                self.move_pitch += y;
+            }
+            Input::Close => {}
+        }
+    }
+}
+
+/// An input from the user, as consumed by `ControlSystem`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Input {
+    /// A key was pressed or released.
+    #[serde(rename = "key")]
+    Key {
+        /// The scancode of the key.
+        scancode: u32,
+
+        /// Whether the key was pressed, rather than released.
+        pressed: bool,
+    },
+
+    /// The mouse moved.
+    #[serde(rename = "mouse")]
+    MouseMotion {
+        /// How far the mouse moved horizontally.
+        x: f32,
+
+        /// How far the mouse moved vertically.
+        y: f32,
+    },
+
+    /// The window was closed.
+    #[serde(rename = "close")]
+    Close,
+}
+
+impl Input {
+    /// Returns whether the input asks for the game to close, i.e. whether it's Escape being
+    /// pressed or the window being closed.
+    pub fn is_close(self) -> bool {
+        match self {
+            Input::Key {
+                scancode: 1,
+                pressed: true,
+            }
+            | Input::Close => true,
+            _ => false,
+        }
+    }
+}
+
+/// How far between the last simulation step and the next one a frame is being rendered, from
+/// `0.0` to `1.0`. Rendering interpolates each entity's location from its
+/// `PreviousLocationComponent` by this much; without this resource, the current locations are
+/// rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Interpolation(pub f32);
+
+/// The layout of the map the world was loaded from.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MapInfo {
+    /// The dimensions of the map.
+    pub dims: (usize, usize),
+
+    /// The floor layout, in row-major order.
+    pub tiles: Vec<Tile>,
+
+    /// The start location.
+    pub start: (usize, usize),
+
+    /// The goal location.
+    pub goal: (usize, usize),
+}
+
+impl MapInfo {
+    /// Returns the tile at the given location, or `None` if it's out of bounds.
+    pub fn tile(&self, x: usize, y: usize) -> Option<Tile> {
+        if x < self.dims.0 && y < self.dims.1 {
+            Some(self.tiles[x + y * self.dims.0])
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Ordering systems, and running them in parallel where their data access allows it.
+
+use crate::{ParallelSystem, State, System, SystemAccess, WorldView};
+use failure::Fallible;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use std::{
+    any::TypeId,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
+
+/// A set of systems, split into stages that run one after another. The systems in each stage
+/// don't conflict with each other, so they run in parallel.
+///
+/// Systems are ordered as follows:
+///
+/// - A system is run after any system it's explicitly ordered after, and before any system it's
+///   explicitly ordered before.
+/// - An exclusive system (i.e. a `System`) runs alone, in the order it was added relative to
+///   every other system it isn't explicitly ordered with.
+/// - A `ParallelSystem` that reads something another writes runs in the order it was added
+///   relative to it, unless they're explicitly ordered.
+///
+/// Two parallel systems that write the same component or resource must be explicitly ordered,
+/// since otherwise which write happens first would be arbitrary.
+///
+/// Commands recorded by the systems in a stage are applied once the whole stage has finished.
+/// A system added with a condition is skipped in states it doesn't hold in; since it doesn't
+/// finish a step then, its `Changed` filters see everything changed while it was skipped.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{
+/// #     components::LocationComponent, schedule::Schedule, ParallelSystem, State,
+/// #     System, SystemAccess, WorldView,
+/// # };
+/// struct Input;
+/// impl System for Input {
+///     fn step(&mut self, _: &mut State, _: u64) {}
+/// }
+///
+/// struct Mover;
+/// impl ParallelSystem for Mover {
+///     fn access(&self, access: &mut SystemAccess) {
+///         access.write::<LocationComponent>();
+///     }
+///     fn step(&mut self, _: &mut WorldView, _: u64) {}
+/// }
+///
+/// struct Spinner;
+/// impl ParallelSystem for Spinner {
+///     fn access(&self, access: &mut SystemAccess) {
+///         access.write::<LocationComponent>();
+///     }
+///     fn step(&mut self, _: &mut WorldView, _: u64) {}
+/// }
+///
+/// struct Logger;
+/// impl ParallelSystem for Logger {
+///     fn access(&self, _: &mut SystemAccess) {}
+///     fn step(&mut self, _: &mut WorldView, _: u64) {}
+/// }
+///
+/// # fn main() {
+/// let schedule = Schedule::builder()
+///     .system("input", Input)
+///     .parallel_system("mover", Mover)
+///     .parallel_system("spinner", Spinner)
+///     .after("mover")
+///     .parallel_system("logger", Logger)
+///     .build()
+///     .unwrap();
+/// assert_eq!(
+///     schedule.to_string(),
+///     "Stage 1: input (exclusive)\nStage 2: mover, logger\nStage 3: spinner\n",
+/// );
+///
+/// // The movers both write `LocationComponent`, so they must be ordered.
+/// let err = Schedule::builder()
+///     .parallel_system("mover", Mover)
+///     .parallel_system("spinner", Spinner)
+///     .build()
+///     .unwrap_err();
+/// assert!(err.to_string().starts_with("mover and spinner both write"));
+///
+/// let err = Schedule::builder()
+///     .parallel_system("mover", Mover)
+///     .after("spinner")
+///     .parallel_system("spinner", Spinner)
+///     .after("mover")
+///     .build()
+///     .unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "Systems are ordered in a cycle: mover -> spinner -> mover",
+/// );
+/// # }
+/// ```
+pub struct Schedule {
+    nodes: Vec<Node>,
+    exclusive: Vec<Box<dyn System>>,
+    parallel: Vec<Box<dyn ParallelSystem>>,
+    parallel_nodes: Vec<usize>,
+    stages: Vec<Stage>,
+}
+
+impl Schedule {
+    /// Returns a builder for a schedule.
+    pub fn builder() -> ScheduleBuilder {
+        ScheduleBuilder::default()
+    }
+
+    /// Runs a step of every system.
+    pub fn run(&mut self, state: &mut State, dt: u64) {
+        for stage in &self.stages {
+            match *stage {
+                Stage::Exclusive(node) => {
+                    if !self.nodes[node].runs_in(state) {
+                        continue;
+                    }
+                    let id = self.nodes[node].id;
+                    if let Some(world) = state.world_mut() {
+                        world.start_system_by_id(id);
+                    }
+                    let index = self.nodes[node].kind.index();
+                    self.exclusive[index].step(state, dt);
+                    if let Some(world) = state.world_mut() {
+                        world.finish_systems(Some(id));
+                    }
+                }
+                Stage::Parallel(ref members) => {
+                    let (nodes, parallel_nodes) = (&self.nodes, &self.parallel_nodes);
+                    let members = members
+                        .iter()
+                        .cloned()
+                        .filter(|&index| nodes[parallel_nodes[index]].runs_in(state))
+                        .collect::<Vec<_>>();
+                    let world = match state.world_mut() {
+                        Some(world) => world,
+                        None => continue,
+                    };
+
+                    {
+                        let world = &*world;
+                        self.parallel
+                            .par_iter_mut()
+                            .enumerate()
+                            .filter(|&(index, _)| members.binary_search(&index).is_ok())
+                            .for_each(|(index, system)| {
+                                let node = &nodes[parallel_nodes[index]];
+                                let ticks = world.system_change_ticks(node.id);
+                                // The systems in a stage never conflict.
+                                let mut view = unsafe {
+                                    WorldView::new(world, &node.access, ticks, node.label)
+                                };
+                                system.step(&mut view, dt);
+                            });
+                    }
+
+                    let ids = members
+                        .iter()
+                        .map(|&index| self.nodes[self.parallel_nodes[index]].id);
+                    world.finish_systems(ids);
+                }
+            }
+
+            if let Some(world) = state.world_mut() {
+                for stale in world.apply_commands() {
+                    warn!("{}", stale);
+                }
+            }
+        }
+    }
+}
+
+impl Debug for Schedule {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "Schedule(\n{})", self)
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        for (i, stage) in self.stages.iter().enumerate() {
+            write!(fmt, "Stage {}: ", i + 1)?;
+            match *stage {
+                Stage::Exclusive(node) => writeln!(fmt, "{} (exclusive)", self.nodes[node].label)?,
+                Stage::Parallel(ref members) => {
+                    let labels = members
+                        .iter()
+                        .map(|&index| self.nodes[self.parallel_nodes[index]].label)
+                        .collect::<Vec<_>>();
+                    writeln!(fmt, "{}", labels.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A builder for a `Schedule`. Ordering constraints apply to the most recently added system.
+#[derive(Default)]
+pub struct ScheduleBuilder {
+    nodes: Vec<Node>,
+    exclusive: Vec<Box<dyn System>>,
+    parallel: Vec<Box<dyn ParallelSystem>>,
+}
+
+impl ScheduleBuilder {
+    /// Adds a system which needs exclusive access to the state.
+    pub fn system<S: System + 'static>(mut self, label: &'static str, system: S) -> Self {
+        self.nodes.push(Node {
+            label,
+            id: TypeId::of::<S>(),
+            kind: Kind::Exclusive(self.exclusive.len()),
+            access: SystemAccess::default(),
+            before: Vec::new(),
+            after: Vec::new(),
+            condition: None,
+        });
+        self.exclusive.push(Box::new(system));
+        self
+    }
+
+    /// Adds a system which may run in parallel with others.
+    pub fn parallel_system<S: ParallelSystem + 'static>(
+        mut self,
+        label: &'static str,
+        system: S,
+    ) -> Self {
+        let mut access = SystemAccess::default();
+        system.access(&mut access);
+        self.nodes.push(Node {
+            label,
+            id: TypeId::of::<S>(),
+            kind: Kind::Parallel(self.parallel.len()),
+            access,
+            before: Vec::new(),
+            after: Vec::new(),
+            condition: None,
+        });
+        self.parallel.push(Box::new(system));
+        self
+    }
+
+    /// Orders the most recently added system before the system with the given label.
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.last_node("before").before.push(label);
+        self
+    }
+
+    /// Orders the most recently added system after the system with the given label.
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.last_node("after").after.push(label);
+        self
+    }
+
+    /// Makes the most recently added system run only in states for which the condition holds.
+    pub fn run_if(mut self, condition: fn(&State) -> bool) -> Self {
+        self.nodes
+            .last_mut()
+            .expect("Can't add a condition to a system before adding one")
+            .condition = Some(condition);
+        self
+    }
+
+    /// Resolves the order of the systems, returning an error if the ordering constraints form a
+    /// cycle or two systems write the same data without being ordered.
+    pub fn build(self) -> Fallible<Schedule> {
+        let n = self.nodes.len();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if self.nodes[..i].iter().any(|other| other.label == node.label) {
+                bail!("Two systems are labelled {}", node.label);
+            }
+        }
+
+        // Add the explicit ordering constraints.
+        let mut succs = vec![Vec::new(); n];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &(labels, is_before) in &[(&node.before, true), (&node.after, false)] {
+                for &label in labels {
+                    let other = match self.nodes.iter().position(|other| other.label == label) {
+                        Some(other) => other,
+                        None => bail!(
+                            "{} is ordered {} {}, but no system has that label",
+                            node.label,
+                            if is_before { "before" } else { "after" },
+                            label
+                        ),
+                    };
+                    if is_before {
+                        succs[i].push(other);
+                    } else {
+                        succs[other].push(i);
+                    }
+                }
+            }
+        }
+        if let Some(cycle) = find_cycle(&succs) {
+            let cycle = cycle
+                .into_iter()
+                .map(|i| self.nodes[i].label)
+                .collect::<Vec<_>>();
+            bail!("Systems are ordered in a cycle: {}", cycle.join(" -> "));
+        }
+
+        // Add the implicit ordering constraints. An edge is only added if it agrees with the
+        // edges so far, so this can't create a cycle.
+        for j in 0..n {
+            for i in 0..j {
+                let (a, b) = (&self.nodes[i], &self.nodes[j]);
+                let needs_order = a.kind.is_exclusive()
+                    || b.kind.is_exclusive()
+                    || a.access.read_write_conflict(&b.access).is_some();
+                if needs_order && !reaches(&succs, i, j) && !reaches(&succs, j, i) {
+                    succs[i].push(j);
+                }
+            }
+        }
+
+        for j in 0..n {
+            for i in 0..j {
+                let (a, b) = (&self.nodes[i], &self.nodes[j]);
+                if let Some(name) = a.access.write_conflict(&b.access) {
+                    if !reaches(&succs, i, j) && !reaches(&succs, j, i) {
+                        bail!(
+                            "{} and {} both write {}, so one must be ordered before the other",
+                            a.label,
+                            b.label,
+                            name
+                        );
+                    }
+                }
+            }
+        }
+
+        // Put each system in the stage after the last of its predecessors.
+        let mut levels = vec![0; n];
+        for &i in &topological_order(&succs) {
+            for &j in &succs[i] {
+                levels[j] = levels[j].max(levels[i] + 1);
+            }
+        }
+        let stage_count = levels.iter().map(|&level| level + 1).max().unwrap_or(0);
+        let stages = (0..stage_count)
+            .map(|level| {
+                let members = (0..n).filter(|&i| levels[i] == level).collect::<Vec<_>>();
+                match self.nodes[members[0]].kind {
+                    Kind::Exclusive(_) => Stage::Exclusive(members[0]),
+                    Kind::Parallel(_) => Stage::Parallel(
+                        members
+                            .into_iter()
+                            .map(|i| self.nodes[i].kind.index())
+                            .collect(),
+                    ),
+                }
+            }).collect();
+
+        let parallel_nodes = (0..n)
+            .filter(|&i| !self.nodes[i].kind.is_exclusive())
+            .collect();
+        Ok(Schedule {
+            nodes: self.nodes,
+            exclusive: self.exclusive,
+            parallel: self.parallel,
+            parallel_nodes,
+            stages,
+        })
+    }
+
+    fn last_node(&mut self, constraint: &str) -> &mut Node {
+        self.nodes.last_mut().unwrap_or_else(|| {
+            panic!("Can't order a system {} another before adding one", constraint)
+        })
+    }
+}
+
+/// A system in a schedule.
+struct Node {
+    label: &'static str,
+    id: TypeId,
+    kind: Kind,
+    access: SystemAccess,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    condition: Option<fn(&State) -> bool>,
+}
+
+impl Node {
+    /// Returns whether the system should run in the given state.
+    fn runs_in(&self, state: &State) -> bool {
+        self.condition.map_or(true, |condition| condition(state))
+    }
+}
+
+/// The index of a system in either the exclusive or the parallel systems.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Exclusive(usize),
+    Parallel(usize),
+}
+
+impl Kind {
+    fn index(self) -> usize {
+        match self {
+            Kind::Exclusive(index) | Kind::Parallel(index) => index,
+        }
+    }
+
+    fn is_exclusive(self) -> bool {
+        match self {
+            Kind::Exclusive(_) => true,
+            Kind::Parallel(_) => false,
+        }
+    }
+}
+
+/// A set of systems that run together.
+enum Stage {
+    /// A single exclusive system, by node index.
+    Exclusive(usize),
+
+    /// Parallel systems, by (sorted) index into the parallel systems.
+    Parallel(Vec<usize>),
+}
+
+/// Returns whether `to` can be reached from `from` by following edges.
+fn reaches(succs: &[Vec<usize>], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; succs.len()];
+    let mut stack = vec![from];
+    while let Some(i) = stack.pop() {
+        if i == to {
+            return true;
+        }
+        if !seen[i] {
+            seen[i] = true;
+            stack.extend(&succs[i]);
+        }
+    }
+    false
+}
+
+/// Returns a cycle in the graph, starting and ending at the same node, if there is one.
+fn find_cycle(succs: &[Vec<usize>]) -> Option<Vec<usize>> {
+    fn visit(
+        succs: &[Vec<usize>],
+        i: usize,
+        done: &mut [bool],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        if let Some(start) = path.iter().position(|&j| j == i) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(i);
+            return Some(cycle);
+        }
+        if done[i] {
+            return None;
+        }
+
+        path.push(i);
+        for &j in &succs[i] {
+            if let Some(cycle) = visit(succs, j, done, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done[i] = true;
+        None
+    }
+
+    let mut done = vec![false; succs.len()];
+    (0..succs.len()).filter_map(|i| visit(succs, i, &mut done, &mut Vec::new())).next()
+}
+
+/// Returns the nodes of an acyclic graph in an order where every edge points forwards, preferring
+/// lower indices.
+fn topological_order(succs: &[Vec<usize>]) -> Vec<usize> {
+    let mut preds = vec![0; succs.len()];
+    for &j in succs.iter().flatten() {
+        preds[j] += 1;
+    }
+
+    let mut order = Vec::with_capacity(succs.len());
+    let mut ready = (0..succs.len()).filter(|&i| preds[i] == 0).collect::<Vec<_>>();
+    while let Some(i) = ready.iter().cloned().min() {
+        ready.retain(|&j| j != i);
+        order.push(i);
+        for &j in &succs[i] {
+            preds[j] -= 1;
+            if preds[j] == 0 {
+                ready.push(j);
+            }
+        }
+    }
+    order
+}
@@ -0,0 +1,363 @@
+//! Saving and loading snapshots of a `World`.
+
+use crate::{
+    components::{
+        CameraComponent, CollisionComponent, ControllerComponent, DecalComponent, DoorComponent,
+        EnemyComponent, GoalComponent, HeldComponent, KeyComponent, LocationComponent,
+        NameComponent, ParentComponent, RenderComponent,
+    },
+    resources::{MapInfo, Time},
+    util::read_file_and_unjson,
+    Component, ComponentBundle, Entity, Resource, World,
+};
+use failure::{Fallible, ResultExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{from_value, to_value, to_writer_pretty, Result as JsonResult, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::Path,
+};
+
+/// A saved `World`. Only the components and resources in the `ComponentRegistry` it was saved
+/// with are stored. Models and images are stored as the asset paths they were loaded from, so a
+/// snapshot can be loaded without a GL context.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// The saved entities.
+    pub entities: Vec<SavedEntity>,
+
+    /// The saved resources, by their registered names.
+    pub resources: BTreeMap<String, Value>,
+}
+
+/// An entity in a `Snapshot`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SavedEntity {
+    /// The entity's handle when it was saved. Loading the snapshot creates a new entity, and
+    /// `ParentComponent`s that refer to this handle are updated to refer to the new one.
+    pub entity: Entity,
+
+    /// The saved components, by their registered names.
+    pub components: BTreeMap<String, Value>,
+}
+
+/// The component and resource types which are saved in a `Snapshot`, each under a unique name.
+pub struct ComponentRegistry {
+    components: Vec<ComponentEntry>,
+    resources: Vec<ResourceEntry>,
+}
+
+struct ComponentEntry {
+    name: &'static str,
+    save: fn(&World, Entity) -> Option<JsonResult<Value>>,
+    load: fn(&mut World, Entity, Value) -> JsonResult<()>,
+    load_into_bundle: fn(&mut ComponentBundle, Value) -> JsonResult<()>,
+}
+
+struct ResourceEntry {
+    name: &'static str,
+    save: fn(&World) -> Option<JsonResult<Value>>,
+    load: fn(&mut World, Value) -> JsonResult<()>,
+}
+
+impl ComponentRegistry {
+    /// Creates a registry with no types in it.
+    pub fn new() -> ComponentRegistry {
+        ComponentRegistry {
+            components: Vec::new(),
+            resources: Vec::new(),
+        }
+    }
+
+    /// Registers a component type under the given name. Panics if the name is already taken.
+    pub fn register<T>(&mut self, name: &'static str) -> &mut ComponentRegistry
+    where
+        T: Component + DeserializeOwned + Serialize,
+    {
+        assert!(
+            self.components.iter().all(|entry| entry.name != name),
+            "The component name {:?} is already registered",
+            name
+        );
+        self.components.push(ComponentEntry {
+            name,
+            save: save_component::<T>,
+            load: load_component::<T>,
+            load_into_bundle: load_component_into_bundle::<T>,
+        });
+        self
+    }
+
+    /// Registers a resource type under the given name. Panics if the name is already taken.
+    pub fn register_resource<T>(&mut self, name: &'static str) -> &mut ComponentRegistry
+    where
+        T: DeserializeOwned + Resource + Serialize,
+    {
+        assert!(
+            self.resources.iter().all(|entry| entry.name != name),
+            "The resource name {:?} is already registered",
+            name
+        );
+        self.resources.push(ResourceEntry {
+            name,
+            save: save_resource::<T>,
+            load: load_resource::<T>,
+        });
+        self
+    }
+
+    /// Returns whether a component type is registered under the given name.
+    pub fn has_component(&self, name: &str) -> bool {
+        self.components.iter().any(|entry| entry.name == name)
+    }
+
+    /// Returns the names of the registered component types.
+    pub fn component_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.components.iter().map(|entry| entry.name)
+    }
+
+    /// Deserializes a component of the type registered under the given name from its saved form,
+    /// and inserts it into an entity.
+    pub fn insert(
+        &self,
+        world: &mut World,
+        entity: Entity,
+        name: &str,
+        value: Value,
+    ) -> Fallible<()> {
+        let entry = self
+            .components
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| format_err!("Unknown component {:?}", name))?;
+        (entry.load)(world, entity, value)
+            .with_context(|err| format_err!("Couldn't load the {} component: {}", name, err))?;
+        Ok(())
+    }
+
+    /// Deserializes a component of the type registered under the given name from its saved form,
+    /// and adds it to a bundle.
+    pub fn insert_into_bundle(
+        &self,
+        bundle: &mut ComponentBundle,
+        name: &str,
+        value: Value,
+    ) -> Fallible<()> {
+        let entry = self
+            .components
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| format_err!("Unknown component {:?}", name))?;
+        (entry.load_into_bundle)(bundle, value)
+            .with_context(|err| format_err!("Couldn't load the {} component: {}", name, err))?;
+        Ok(())
+    }
+}
+
+impl Default for ComponentRegistry {
+    /// Creates a registry with the game's components and resources in it.
+    fn default() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register::<CameraComponent>("camera")
+            .register::<CollisionComponent>("collision")
+            .register::<ControllerComponent>("controller")
+            .register::<DecalComponent>("decal")
+            .register::<DoorComponent>("door")
+            .register::<EnemyComponent>("enemy")
+            .register::<GoalComponent>("goal")
+            .register::<HeldComponent>("held")
+            .register::<KeyComponent>("key")
+            .register::<LocationComponent>("location")
+            .register::<NameComponent>("name")
+            .register::<ParentComponent>("parent")
+            .register::<RenderComponent>("render")
+            .register_resource::<MapInfo>("map-info")
+            .register_resource::<Time>("time");
+        registry
+    }
+}
+
+fn save_component<T: Component + Serialize>(
+    world: &World,
+    entity: Entity,
+) -> Option<JsonResult<Value>> {
+    world.get_one::<T>(entity).map(to_value)
+}
+
+fn load_component<T: Component + DeserializeOwned>(
+    world: &mut World,
+    entity: Entity,
+    value: Value,
+) -> JsonResult<()> {
+    world.insert::<T>(entity, from_value(value)?);
+    Ok(())
+}
+
+fn load_component_into_bundle<T: Component + DeserializeOwned>(
+    bundle: &mut ComponentBundle,
+    value: Value,
+) -> JsonResult<()> {
+    bundle.insert::<T>(from_value(value)?);
+    Ok(())
+}
+
+fn save_resource<T: Resource + Serialize>(world: &World) -> Option<JsonResult<Value>> {
+    world.resource::<T>().map(to_value)
+}
+
+fn load_resource<T: Resource + DeserializeOwned>(
+    world: &mut World,
+    value: Value,
+) -> JsonResult<()> {
+    world.insert_resource::<T>(from_value(value)?);
+    Ok(())
+}
+
+impl World {
+    /// Saves the world's registered components and resources to a `Snapshot`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # use game::{
+    /// #     components::{KeyComponent, LocationComponent, NameComponent, ParentComponent},
+    /// #     resources::Time,
+    /// #     snapshot::ComponentRegistry,
+    /// #     World,
+    /// # };
+    /// # fn main() {
+    /// let registry = ComponentRegistry::default();
+    /// let mut world = World::default();
+    /// world.insert_resource(Time::default());
+    /// world.resource_mut::<Time>().unwrap().advance(16);
+    /// let player = world.new_entity(hlist![NameComponent::new("player")]);
+    /// let key = world.new_entity(hlist![
+    ///     NameComponent::new("key-a"),
+    ///     KeyComponent { letter: 'a' },
+    ///     LocationComponent::pos(1.0, 0.1, 2.0),
+    /// ]);
+    /// world.set_parent(key, player).unwrap();
+    ///
+    /// let snapshot = world.snapshot(&registry).unwrap();
+    /// let loaded = World::from_snapshot(snapshot, &registry).unwrap();
+    /// assert_eq!(loaded.resource::<Time>().unwrap().elapsed, 16);
+    ///
+    /// let key = loaded.find_by_name("key-a").unwrap();
+    /// assert_eq!(loaded.get_one::<KeyComponent>(key).unwrap().letter, 'a');
+    /// assert_eq!(loaded.get_one::<LocationComponent>(key).unwrap().xyz.z, 2.0);
+    ///
+    /// // The parent refers to the loaded player, not the original one.
+    /// let player = loaded.find_by_name("player").unwrap();
+    /// assert_eq!(loaded.get_one::<ParentComponent>(key).unwrap().0, player);
+    /// # }
+    /// ```
+    pub fn snapshot(&self, registry: &ComponentRegistry) -> Fallible<Snapshot> {
+        let mut snapshot = Snapshot::default();
+        for (entity, _) in self.iter::<Hlist![]>() {
+            let mut components = BTreeMap::new();
+            for entry in &registry.components {
+                if let Some(value) = (entry.save)(self, entity) {
+                    let value = value.with_context(|err| {
+                        format_err!(
+                            "Couldn't save the {} component of {}: {}",
+                            entry.name,
+                            self.named(entity),
+                            err
+                        )
+                    })?;
+                    components.insert(entry.name.to_string(), value);
+                }
+            }
+            snapshot.entities.push(SavedEntity { entity, components });
+        }
+
+        for entry in &registry.resources {
+            if let Some(value) = (entry.save)(self) {
+                let value = value.with_context(|err| {
+                    format_err!("Couldn't save the {} resource: {}", entry.name, err)
+                })?;
+                snapshot.resources.insert(entry.name.to_string(), value);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Creates a world from a `Snapshot`, reloading any models and images it refers to. Names
+    /// which aren't in the registry are an error.
+    pub fn from_snapshot(snapshot: Snapshot, registry: &ComponentRegistry) -> Fallible<World> {
+        let mut world = World::default();
+        let entities = snapshot
+            .entities
+            .iter()
+            .map(|saved| (saved.entity, world.new_entity(hlist![])))
+            .collect::<HashMap<_, _>>();
+
+        for saved in snapshot.entities {
+            let (saved_entity, entity) = (saved.entity, entities[&saved.entity]);
+            // Load the name first, so that errors loading the rest can show it.
+            let mut components = saved.components.into_iter().collect::<Vec<_>>();
+            components.sort_by_key(|(name, _)| name != "name");
+            for (name, value) in components {
+                registry
+                    .insert(&mut world, entity, &name, value)
+                    .with_context(|err| {
+                        format_err!(
+                            "While loading {} (saved as {}): {}",
+                            world.named(entity),
+                            saved_entity,
+                            err
+                        )
+                    })?;
+            }
+        }
+
+        // Point parents at the newly created entities.
+        let mut orphans = Vec::new();
+        for (child, hlist_pat![parent]) in world.iter_mut::<Hlist![&mut ParentComponent]>() {
+            match entities.get(&parent.0) {
+                Some(&new_parent) => parent.0 = new_parent,
+                None => orphans.push(child),
+            }
+        }
+        for orphan in orphans {
+            warn!("The parent of {} wasn't saved, so it has no parent", world.named(orphan));
+            world.remove::<ParentComponent>(orphan);
+        }
+
+        for (name, value) in snapshot.resources {
+            let entry = registry
+                .resources
+                .iter()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| format_err!("Unknown resource {:?}", name))?;
+            (entry.load)(&mut world, value).with_context(|err| {
+                format_err!("Couldn't load the {} resource: {}", name, err)
+            })?;
+        }
+        Ok(world)
+    }
+
+    /// Saves a snapshot of the world to a JSON file.
+    pub fn save(&self, registry: &ComponentRegistry, path: impl AsRef<Path>) -> Fallible<()> {
+        let path = path.as_ref();
+        let snapshot = self.snapshot(registry)?;
+        let file = File::create(path)
+            .with_context(|err| format_err!("Couldn't create {}: {}", path.display(), err))?;
+        to_writer_pretty(file, &snapshot)
+            .with_context(|err| format_err!("Couldn't write to {}: {}", path.display(), err))?;
+        Ok(())
+    }
+
+    /// Loads a world from a JSON file written by `World::save`.
+    pub fn load(registry: &ComponentRegistry, path: impl AsRef<Path>) -> Fallible<World> {
+        let snapshot = read_file_and_unjson(path.as_ref())?;
+        World::from_snapshot(snapshot, registry)
+            .with_context(|err| {
+                format_err!("While loading {}: {}", path.as_ref().display(), err)
+            }).map_err(From::from)
+    }
+}
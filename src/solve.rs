@@ -0,0 +1,373 @@
+//! Finding the shortest way through a map, to prove that it can be completed.
+//!
+//! The solver works on the map's tiles rather than on a `World`. The player moves one tile at a
+//! time, picks up a key by stepping onto its tile, and unlocks a door by stepping onto it while
+//! holding a key with the door's letter. As with `UnlockSystem`, unlocking a door uses up the
+//! key, so the solver searches over which keys have been picked up and which doors unlocked as
+//! well as over where the player is.
+//!
+//! Enemies are ignored, since getting past them is a matter of timing rather than of which way
+//! to go.
+
+use crate::{Map, Tile};
+use failure::Fallible;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// A move from one tile to a neighbouring one. North is towards the first row of the map.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Move {
+    /// Moves to the previous row.
+    #[serde(rename = "north")]
+    North,
+
+    /// Moves to the next column.
+    #[serde(rename = "east")]
+    East,
+
+    /// Moves to the next row.
+    #[serde(rename = "south")]
+    South,
+
+    /// Moves to the previous column.
+    #[serde(rename = "west")]
+    West,
+}
+
+impl Move {
+    /// All the moves, in the order the solver tries them.
+    pub const ALL: [Move; 4] = [Move::North, Move::East, Move::South, Move::West];
+
+    /// Returns the tile this move leads to from the given one, or `None` if it would leave a map
+    /// with the given dimensions.
+    pub fn from(self, (x, y): (usize, usize), dims: (usize, usize)) -> Option<(usize, usize)> {
+        let (x, y) = match self {
+            Move::North => (Some(x), y.checked_sub(1)),
+            Move::East => (x.checked_add(1), Some(y)),
+            Move::South => (Some(x), y.checked_add(1)),
+            Move::West => (x.checked_sub(1), Some(y)),
+        };
+        match (x, y) {
+            (Some(x), Some(y)) if x < dims.0 && y < dims.1 => Some((x, y)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Move {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let s = match *self {
+            Move::North => "N",
+            Move::East => "E",
+            Move::South => "S",
+            Move::West => "W",
+        };
+        fmt.write_str(s)
+    }
+}
+
+/// Whether a map can be completed, and how.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "verdict")]
+pub enum Verdict {
+    /// The map can be completed.
+    #[serde(rename = "solvable")]
+    Solvable(Solution),
+
+    /// The map can't be completed.
+    #[serde(rename = "unsolvable")]
+    Unsolvable(Unsolvable),
+}
+
+/// A shortest way to complete a map.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Solution {
+    /// The moves from the start to the goal.
+    pub moves: Vec<Move>,
+
+    /// The keys, in the order they're picked up.
+    pub keys: Vec<(usize, usize, char)>,
+
+    /// The doors, in the order they're unlocked.
+    pub doors: Vec<(usize, usize, char)>,
+}
+
+impl Solution {
+    /// Returns the tiles visited by the solution, starting with the map's start.
+    pub fn path(&self, map: &Map) -> Vec<(usize, usize)> {
+        let mut pos = map.start;
+        let mut path = vec![pos];
+        for &m in &self.moves {
+            pos = m.from(pos, map.dims).expect("Solution leaves the map");
+            path.push(pos);
+        }
+        path
+    }
+}
+
+/// Why a map can't be completed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Unsolvable {
+    /// The doors between the reachable part of the map and the goal that couldn't be unlocked,
+    /// in row-major order. If this is empty, the goal is walled off from the start.
+    pub blocking_doors: Vec<(usize, usize, char)>,
+}
+
+impl Display for Unsolvable {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self.blocking_doors.len() {
+            0 => write!(fmt, "The goal is walled off from the start"),
+            1 => {
+                let (x, y, letter) = self.blocking_doors[0];
+                write!(
+                    fmt,
+                    "The goal is behind the door {} at ({}, {}), which can't be unlocked",
+                    letter, x, y
+                )
+            }
+            _ => {
+                let doors = self
+                    .blocking_doors
+                    .iter()
+                    .map(|&(x, y, letter)| format!("{} at ({}, {})", letter, x, y))
+                    .collect::<Vec<_>>();
+                write!(
+                    fmt,
+                    "The goal is behind the doors {}, none of which can be unlocked",
+                    doors.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// The solver's state: where the player is, and which keys and doors have been used, as bitsets
+/// of indices into the map's keys and doors.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Node {
+    pos: (usize, usize),
+    picked_up: u64,
+    unlocked: u64,
+}
+
+/// Finds a shortest way to complete the map, with a breadth-first search. Returns an error if
+/// the map is malformed, or has more than 64 keys or doors.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{solve::{solve, Verdict}, Map};
+/// # fn main() {
+/// let map: Map = "5 5\n0000G\nWW0W0\n0WAW0\n0W0WW\nS000a\n".parse().unwrap();
+/// let solution = match solve(&map).unwrap() {
+///     Verdict::Solvable(solution) => solution,
+///     Verdict::Unsolvable(why) => panic!("{}", why),
+/// };
+/// let moves = solution.moves.iter().map(ToString::to_string).collect::<String>();
+/// assert_eq!(moves, "EEEEWWNNNNEE");
+/// assert_eq!(solution.keys, vec![(4, 4, 'a')]);
+///
+/// // Without its key, the door blocks the way.
+/// let map: Map = "5 5\n0000G\nWW0W0\n0WAW0\n0W0WW\nS0000\n".parse().unwrap();
+/// match solve(&map).unwrap() {
+///     Verdict::Solvable(_) => panic!("Solved a map with no key"),
+///     Verdict::Unsolvable(why) => assert_eq!(why.blocking_doors, vec![(2, 2, 'A')]),
+/// }
+/// # }
+/// ```
+pub fn solve(map: &Map) -> Fallible<Verdict> {
+    if map.tiles.len() != map.dims.0 * map.dims.1 {
+        bail!(
+            "The map should have {} tiles, but has {}",
+            map.dims.0 * map.dims.1,
+            map.tiles.len()
+        );
+    }
+    for &(name, (x, y)) in &[("start", map.start), ("goal", map.goal)] {
+        if map.tile(x, y).is_none() {
+            bail!("The {} ({}, {}) is outside the map", name, x, y);
+        }
+    }
+
+    let doors = (0..map.dims.1)
+        .flat_map(|y| (0..map.dims.0).map(move |x| (x, y)))
+        .filter_map(|(x, y)| match map.tile(x, y) {
+            Some(Tile::Door(letter)) => Some((x, y, letter)),
+            _ => None,
+        }).collect::<Vec<_>>();
+    if map.keys.len() > 64 || doors.len() > 64 {
+        bail!(
+            "Can't solve a map with more than 64 keys or doors, but the map has {} keys and {} \
+             doors",
+            map.keys.len(),
+            doors.len()
+        );
+    }
+    let keys_at = |pos: (usize, usize)| {
+        map.keys
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(x, y, _))| (x, y) == pos)
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    };
+    let door_at = doors
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y, _))| ((x, y), i))
+        .collect::<HashMap<_, _>>();
+
+    // The number of keys with a letter that are held is the number picked up, less the number
+    // of doors with that letter unlocked.
+    let held = |node: &Node, door_letter: char| {
+        let picked_up = map
+            .keys
+            .iter()
+            .enumerate()
+            .filter(|&(i, &(_, _, letter))| {
+                node.picked_up & 1 << i != 0 && letter.to_ascii_uppercase() == door_letter
+            }).count();
+        let used = doors
+            .iter()
+            .enumerate()
+            .filter(|&(i, &(_, _, letter))| node.unlocked & 1 << i != 0 && letter == door_letter)
+            .count();
+        picked_up > used
+    };
+
+    let start = Node {
+        pos: map.start,
+        picked_up: keys_at(map.start),
+        unlocked: 0,
+    };
+    let mut parents = HashMap::new();
+    let mut queue = VecDeque::new();
+    let mut stuck_at = HashSet::new();
+    parents.insert(start, None);
+    queue.push_back(start);
+    while let Some(node) = queue.pop_front() {
+        if node.pos == map.goal {
+            return Ok(Verdict::Solvable(solution(map, &doors, &parents, node)));
+        }
+
+        for &m in &Move::ALL {
+            let pos = match m.from(node.pos, map.dims) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let mut next = Node { pos, ..node };
+            match map.tile(pos.0, pos.1) {
+                Some(Tile::Empty) => {}
+                Some(Tile::Door(letter)) => {
+                    let door = 1 << door_at[&pos];
+                    if node.unlocked & door == 0 {
+                        if !held(&node, letter) {
+                            stuck_at.insert(door_at[&pos]);
+                            continue;
+                        }
+                        next.unlocked |= door;
+                    }
+                }
+                Some(Tile::Wall) | None => continue,
+            }
+            next.picked_up |= keys_at(pos);
+
+            if let Entry::Vacant(entry) = parents.entry(next) {
+                entry.insert(Some((node, m)));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    Ok(Verdict::Unsolvable(Unsolvable {
+        blocking_doors: blocking_doors(map, &doors, &parents, &stuck_at),
+    }))
+}
+
+/// Retraces the moves which led to the node.
+fn solution(
+    map: &Map,
+    doors: &[(usize, usize, char)],
+    parents: &HashMap<Node, Option<(Node, Move)>>,
+    mut node: Node,
+) -> Solution {
+    let mut nodes = vec![node];
+    let mut moves = Vec::new();
+    while let Some((parent, m)) = parents[&node] {
+        nodes.push(parent);
+        moves.push(m);
+        node = parent;
+    }
+    nodes.reverse();
+    moves.reverse();
+
+    let mut keys = Vec::new();
+    let mut unlocked = Vec::new();
+    let mut last = Node {
+        picked_up: 0,
+        unlocked: 0,
+        ..nodes[0]
+    };
+    for node in nodes {
+        let new_keys = node.picked_up & !last.picked_up;
+        keys.extend(
+            (0..map.keys.len())
+                .filter(|i| new_keys & 1 << i != 0)
+                .map(|i| map.keys[i]),
+        );
+        let new_doors = node.unlocked & !last.unlocked;
+        unlocked.extend(
+            (0..doors.len())
+                .filter(|i| new_doors & 1 << i != 0)
+                .map(|i| doors[i]),
+        );
+        last = node;
+    }
+    Solution {
+        moves,
+        keys,
+        doors: unlocked,
+    }
+}
+
+/// Finds the doors that stopped the goal from being reached, i.e. the doors the player got stuck
+/// at which lead towards the goal. Doors which weren't unlocked on any path through the map are
+/// the most to blame, so if there are any, only they are returned.
+fn blocking_doors(
+    map: &Map,
+    doors: &[(usize, usize, char)],
+    parents: &HashMap<Node, Option<(Node, Move)>>,
+    stuck_at: &HashSet<usize>,
+) -> Vec<(usize, usize, char)> {
+    // Find the tiles the goal could be reached from if every door were unlocked.
+    let mut towards_goal = HashSet::new();
+    let mut stack = vec![map.goal];
+    while let Some(pos) = stack.pop() {
+        match map.tile(pos.0, pos.1) {
+            Some(Tile::Wall) | None => continue,
+            _ => {}
+        }
+        if towards_goal.insert(pos) {
+            stack.extend(Move::ALL.iter().filter_map(|m| m.from(pos, map.dims)));
+        }
+    }
+
+    let ever_unlocked = parents.keys().fold(0, |mask, node| mask | node.unlocked);
+    let blocking = (0..doors.len())
+        .filter(|i| stuck_at.contains(i))
+        .filter(|&i| towards_goal.contains(&(doors[i].0, doors[i].1)))
+        .collect::<Vec<_>>();
+    let never_unlocked = blocking
+        .iter()
+        .cloned()
+        .filter(|&i| ever_unlocked & 1 << i == 0)
+        .collect::<Vec<_>>();
+    let blocking = if never_unlocked.is_empty() {
+        blocking
+    } else {
+        never_unlocked
+    };
+    blocking.into_iter().map(|i| doors[i]).collect()
+}
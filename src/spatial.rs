@@ -0,0 +1,272 @@
+//! A uniform grid over the floor, for finding the entities near a point without visiting every
+//! entity in the world.
+
+use cgmath::{InnerSpace, Point3};
+use crate::{Component, Entity, World};
+use std::collections::HashMap;
+
+/// The width and depth of a cell of the grid, which is the size of a map tile.
+pub const CELL_SIZE: f32 = 1.0;
+
+/// A resource indexing entities by where they are on the floor, i.e. the x-z plane. Each entity
+/// is treated as a sphere, which is stored in every cell it overlaps.
+///
+/// `seed_transforms` fills the index when a world is built or loaded, and `SpatialIndexSystem`
+/// keeps it in sync with the entities' `TransformComponent`s, giving each the radius
+/// `TransformComponent::collides` uses. Since the index is only updated once per
+/// step, callers should check the components of the entities it returns.
+///
+/// # Example
+///
+/// ```
+/// # extern crate cgmath;
+/// # #[macro_use] extern crate frunk;
+/// # extern crate game;
+/// # use cgmath::Point3;
+/// # use game::{spatial::SpatialIndex, World};
+/// # fn main() {
+/// let mut world = World::default();
+/// let a = world.new_entity(hlist![]);
+/// let b = world.new_entity(hlist![]);
+///
+/// let mut index = SpatialIndex::default();
+/// index.insert(a, Point3::new(0.5, 0.0, 0.5), 0.25);
+/// index.insert(b, Point3::new(3.5, 0.0, 0.5), 0.25);
+///
+/// assert_eq!(index.query_radius(Point3::new(1.0, 0.0, 0.5), 0.5), vec![a]);
+/// assert_eq!(index.query_radius(Point3::new(2.0, 0.0, 0.5), 1.5), vec![a, b]);
+/// assert_eq!(
+///     index.query_aabb(Point3::new(3.0, -1.0, 0.0), Point3::new(4.0, 1.0, 1.0)),
+///     vec![b]
+/// );
+/// assert_eq!(index.nearest_where(Point3::new(9.0, 0.0, 9.0), |_| true), Some(b));
+/// assert_eq!(index.nearest_where(Point3::new(9.0, 0.0, 9.0), |e| e != b), Some(a));
+///
+/// index.insert(b, Point3::new(0.5, 0.0, 1.5), 0.25);
+/// assert!(index.query_radius(Point3::new(2.0, 0.0, 0.5), 1.0).is_empty());
+/// assert!(index.remove(a));
+/// assert_eq!(index.len(), 1);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SpatialIndex {
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    entries: HashMap<Entity, Entry>,
+    bounds: Option<CellRange>,
+}
+
+/// An entity's sphere, and the cells it was stored in.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    center: Point3<f32>,
+    radius: f32,
+    cells: CellRange,
+}
+
+/// An inclusive, rectangular range of cells.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct CellRange {
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl SpatialIndex {
+    /// Adds an entity to the index as a sphere with the given center and radius, or moves it
+    /// there if it's already in the index.
+    pub fn insert(&mut self, entity: Entity, center: Point3<f32>, radius: f32) {
+        let cells = CellRange::around(center, radius);
+        let old = self.entries.insert(
+            entity,
+            Entry {
+                center,
+                radius,
+                cells,
+            },
+        );
+        match old {
+            Some(old) if old.cells == cells => return,
+            Some(old) => self.remove_from_cells(entity, old.cells),
+            None => {}
+        }
+
+        for cell in cells.iter() {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+        self.bounds = Some(self.bounds.map_or(cells, |bounds| bounds.union(cells)));
+    }
+
+    /// Removes an entity from the index, returning whether it was in the index.
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        match self.entries.remove(&entity) {
+            Some(entry) => {
+                self.remove_from_cells(entity, entry.cells);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether the entity is in the index.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entries.contains_key(&entity)
+    }
+
+    /// Returns the number of entities in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entities whose spheres overlap the sphere with the given center and radius,
+    /// sorted by handle. With the radius of a `TransformComponent`, these are the entities it
+    /// collides with.
+    pub fn query_radius(&self, point: Point3<f32>, radius: f32) -> Vec<Entity> {
+        self.query(CellRange::around(point, radius), |entry| {
+            (entry.center - point).magnitude() < entry.radius + radius
+        })
+    }
+
+    /// Returns the entities whose spheres overlap the axis-aligned box with the given corners,
+    /// sorted by handle.
+    pub fn query_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> Vec<Entity> {
+        let cells = CellRange {
+            min: cell_of(min.x, min.z),
+            max: cell_of(max.x, max.z),
+        };
+        self.query(cells, |entry| {
+            let closest = Point3::new(
+                entry.center.x.max(min.x).min(max.x),
+                entry.center.y.max(min.y).min(max.y),
+                entry.center.z.max(min.z).min(max.z),
+            );
+            (entry.center - closest).magnitude() < entry.radius
+        })
+    }
+
+    /// Returns the entity with a component of type `T` whose center is nearest to the point.
+    /// Ties are broken by handle.
+    pub fn nearest<T: Component>(&self, world: &World, point: Point3<f32>) -> Option<Entity> {
+        self.nearest_where(point, |entity| world.get_one::<T>(entity).is_some())
+    }
+
+    /// Returns the entity for which the predicate returns `true` whose center is nearest to the
+    /// point. Ties are broken by handle.
+    pub fn nearest_where(
+        &self,
+        point: Point3<f32>,
+        mut pred: impl FnMut(Entity) -> bool,
+    ) -> Option<Entity> {
+        let bounds = self.bounds?;
+        let (x, z) = cell_of(point.x, point.z);
+        let max_ring = *[x - bounds.min.0, bounds.max.0 - x, z - bounds.min.1, bounds.max.1 - z]
+            .iter()
+            .max()
+            .unwrap();
+
+        // Search outwards in square rings of cells. An entity whose center is in ring `r` is at
+        // least `r - 1` cells away, so once the best entity found is no farther than that, the
+        // search can stop.
+        let mut best: Option<(f32, Entity)> = None;
+        for ring in 0..=max_ring {
+            if let Some((distance, _)) = best {
+                if distance <= (ring - 1) as f32 * CELL_SIZE {
+                    break;
+                }
+            }
+
+            for cell in ring_cells((x, z), ring) {
+                for &entity in self.cells.get(&cell).into_iter().flatten() {
+                    let distance = (self.entries[&entity].center - point).magnitude();
+                    let better = match best {
+                        Some((best_distance, best_entity)) => {
+                            distance < best_distance
+                                || (distance == best_distance && entity < best_entity)
+                        }
+                        None => true,
+                    };
+                    if better && pred(entity) {
+                        best = Some((distance, entity));
+                    }
+                }
+            }
+        }
+        best.map(|(_, entity)| entity)
+    }
+
+    /// Returns the entities in the given cells whose entries satisfy the predicate, sorted by
+    /// handle.
+    fn query(&self, cells: CellRange, mut pred: impl FnMut(&Entry) -> bool) -> Vec<Entity> {
+        let mut found = Vec::new();
+        for cell in cells.iter() {
+            for &entity in self.cells.get(&cell).into_iter().flatten() {
+                if pred(&self.entries[&entity]) {
+                    found.push(entity);
+                }
+            }
+        }
+
+        // Entities in several cells will have been found once per cell.
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    fn remove_from_cells(&mut self, entity: Entity, cells: CellRange) {
+        for cell in cells.iter() {
+            let now_empty = match self.cells.get_mut(&cell) {
+                Some(entities) => {
+                    entities.retain(|&e| e != entity);
+                    entities.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+}
+
+impl CellRange {
+    /// Returns the cells overlapped by the sphere with the given center and radius.
+    fn around(center: Point3<f32>, radius: f32) -> CellRange {
+        CellRange {
+            min: cell_of(center.x - radius, center.z - radius),
+            max: cell_of(center.x + radius, center.z + radius),
+        }
+    }
+
+    /// Returns the smallest range containing both ranges.
+    fn union(self, other: CellRange) -> CellRange {
+        CellRange {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    fn iter(self) -> impl Iterator<Item = (i32, i32)> {
+        let CellRange { min, max } = self;
+        (min.0..=max.0).flat_map(move |x| (min.1..=max.1).map(move |z| (x, z)))
+    }
+}
+
+/// Returns the cell containing the given point.
+fn cell_of(x: f32, z: f32) -> (i32, i32) {
+    ((x / CELL_SIZE).floor() as i32, (z / CELL_SIZE).floor() as i32)
+}
+
+/// Returns the cells which are exactly `ring` cells away from the given cell, horizontally or
+/// vertically.
+fn ring_cells((x, z): (i32, i32), ring: i32) -> impl Iterator<Item = (i32, i32)> {
+    (-ring..=ring).flat_map(move |dx| {
+        // Only the first and last columns are filled in.
+        let step = if dx.abs() == ring { 1 } else { 2 * ring };
+        (-ring..=ring)
+            .step_by(step as usize)
+            .map(move |dz| (x + dx, z + dz))
+    })
+}
@@ -1,19 +1,18 @@
-use cgmath::{Point3, Vector3};
 use crate::{
-    components::{
-        CameraComponent, CollisionComponent, DecalComponent, DoorComponent, GoalComponent,
-        KeyComponent, LocationComponent, RenderComponent,
-    },
-    gui::RenderData,
-    util::{load_texture, read_file, read_file_and_parse_to, read_file_and_unjson},
-    Entity, Map, Material, Model, Tile,
+    gui::RenderAssets,
+    lint::{lint, Severity},
+    prefab::PrefabRegistry,
+    resources::{InputState, MapInfo, Time},
+    systems::seed_transforms,
+    util::read_file,
+    Entity, Map, MaterialSource, Tile, World,
 };
 use failure::{Fallible, ResultExt};
-use frunk::hlist::{HCons, HNil};
-use glium::{backend::Facade, Program};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::{collections::HashMap, path::Path, sync::Arc};
-use typemap::{Key, ShareMap};
+use serde_json::{to_value, Value};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 /// The global game state.
 pub enum State {
@@ -23,6 +22,10 @@ pub enum State {
     /// The state of the game after the user has completed the maze.
     Done(World, u64),
 
+    /// The state of the game for a while after an enemy caught the user, with how long ago that
+    /// was. Afterwards, the user is sent back to the start and the game goes back to `Playing`.
+    Lost(World, u64),
+
     /// The state that represents a requested close.
     Close,
 }
@@ -36,6 +39,34 @@ impl State {
         }
     }
 
+    /// Returns whether the state indicates that an enemy has just caught the user.
+    pub fn is_lost(&self) -> bool {
+        match *self {
+            State::Lost(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the world, unless the state is `Close`.
+    pub fn world(&self) -> Option<&World> {
+        match *self {
+            State::Playing(ref world) | State::Done(ref world, _) | State::Lost(ref world, _) => {
+                Some(world)
+            }
+            State::Close => None,
+        }
+    }
+
+    /// Returns the world mutably, unless the state is `Close`.
+    pub fn world_mut(&mut self) -> Option<&mut World> {
+        match *self {
+            State::Playing(ref mut world)
+            | State::Done(ref mut world, _)
+            | State::Lost(ref mut world, _) => Some(world),
+            State::Close => None,
+        }
+    }
+
     /// Returns whether the state indicates that closing should occur.
     pub fn should_close(&self) -> bool {
         match *self {
@@ -46,123 +77,79 @@ impl State {
     }
 }
 
-/// The state of the game world during gameplay.
-#[derive(Default)]
-pub struct World {
-    next_entity: usize,
-    components: HashMap<Entity, ShareMap>,
-}
-
 impl World {
-    /// Loads the assets specified in the map, creating a `World` with them.
-    pub fn from_map(
-        map: Map,
-        base_path: impl AsRef<Path>,
-        facade: &impl Facade,
-    ) -> Fallible<(RenderData, World)> {
+    /// Loads the assets specified in the map, creating a `World` with them. Every entity is
+    /// spawned from a prefab; see `Map::prefab_for` for which prefabs are used. No GL context is
+    /// needed; the returned assets can be uploaded to one with `RenderData::upload`.
+    ///
+    /// The map is checked with `lint` first. Any errors stop it from loading, while warnings are
+    /// only logged.
+    pub fn from_map(map: Map, base_path: impl AsRef<Path>) -> Fallible<(RenderAssets, World)> {
+        let mut errors = Vec::new();
+        for issue in lint(&map) {
+            match issue.severity {
+                Severity::Warning => warn!("{}", issue),
+                Severity::Error => errors.push(issue.to_string()),
+            }
+        }
+        if !errors.is_empty() {
+            bail!("The map is invalid: {}", errors.join("; "));
+        }
+
         let base_path = base_path.as_ref();
-        let x_max = map.dims.0 as f32;
-        let z_max = map.dims.1 as f32;
+        let mut prefabs = PrefabRegistry::default();
+        for path in &map.prefabs {
+            prefabs.add_file(base_path.join(path))?;
+        }
+        let spawner = Spawner {
+            map: &map,
+            prefabs: &prefabs,
+            map_dir: to_value(base_path)?,
+        };
 
         let mut world = World::default();
+        world.insert_resource(Time::default());
+        world.insert_resource(InputState::default());
+        world.insert_resource(MapInfo {
+            dims: map.dims,
+            tiles: map.tiles.clone(),
+            start: map.start,
+            goal: map.goal,
+        });
 
-        // Add the player.
-        world.new_entity(
-            "player",
-            hlist![
-                CameraComponent,
-                LocationComponent {
-                    xyz: Point3::new(map.start.0 as f32 + 0.5, 0.25, map.start.1 as f32 + 0.5),
-                    rotation: Vector3::new(0.0, 0.0, 0.0),
-                    scale: 0.2,
-                }
-            ],
-        );
-
-        // Add the goal.
-        world.new_entity(
-            "goal",
-            hlist![
-                GoalComponent,
-                LocationComponent {
-                    xyz: Point3::new(map.goal.0 as f32 + 0.5, 0.5, map.goal.1 as f32 + 0.5),
-                    rotation: Vector3::new(0.0, 0.0, 0.0),
-                    scale: 1.0,
-                }
-            ],
-        );
+        // Add the player and the goal.
+        spawner.spawn_tile(&mut world, 'S', map.start, vec![])?;
+        spawner.spawn_tile(&mut world, 'G', map.goal, vec![])?;
 
         // Add the floor.
-        let floor_material = match map.material_floor {
-            Some(path) => Some(Material::load_mtl(base_path.join(path))?),
-            None => None,
-        };
-        let floor_model = Arc::new(Model::quad(
-            (0.0, 0.0, 0.0),
-            (0.0, 0.0, z_max),
-            (x_max, 0.0, z_max),
-            (x_max, 0.0, 0.0),
-            floor_material,
-        ));
-        world.new_entity(
+        let floor_material = material_source(base_path, &map.material_floor);
+        spawner.spawn(
+            &mut world,
             "floor",
-            hlist![
-                RenderComponent { model: floor_model },
-                LocationComponent::default(),
+            (0.0, 0.0),
+            vec![
+                ("width", json!(map.dims.0)),
+                ("depth", json!(map.dims.1)),
+                ("material", to_value(floor_material)?),
             ],
-        );
-
-        // Load the wall material and model.
-        let wall_material = match map.material_wall {
-            Some(path) => Some(Material::load_mtl(base_path.join(path))?),
-            None => None,
-        };
-        let wall_model = Arc::new(Model::cube(wall_material));
+        )?;
 
         // Add the border walls.
+        let wall_material = to_value(material_source(base_path, &map.material_wall))?;
+        let (x_max, z_max) = (map.dims.0 as f32, map.dims.1 as f32);
         for x in 0..map.dims.0 {
-            world.new_entity(
-                "border-wall",
-                hlist![
-                    RenderComponent {
-                        model: wall_model.clone(),
-                    },
-                    LocationComponent::pos(x as f32 + 0.5, 0.5, map.dims.1 as f32 + 0.5),
-                    CollisionComponent(true),
-                ],
-            );
-            world.new_entity(
-                "border-wall",
-                hlist![
-                    RenderComponent {
-                        model: wall_model.clone(),
-                    },
-                    LocationComponent::pos(x as f32 + 0.5, 0.5, -0.5),
-                    CollisionComponent(true),
-                ],
-            );
+            let x = x as f32 + 0.5;
+            for &z in &[z_max + 0.5, -0.5] {
+                let params = vec![("material", wall_material.clone())];
+                spawner.spawn_at(&mut world, 'W', (x, z), params)?;
+            }
         }
-        for y in 0..map.dims.1 {
-            world.new_entity(
-                "border-wall",
-                hlist![
-                    RenderComponent {
-                        model: wall_model.clone(),
-                    },
-                    LocationComponent::pos(map.dims.0 as f32 + 0.5, 0.5, y as f32 + 0.5),
-                    CollisionComponent(true),
-                ],
-            );
-            world.new_entity(
-                "border-wall",
-                hlist![
-                    RenderComponent {
-                        model: wall_model.clone(),
-                    },
-                    LocationComponent::pos(-0.5, 0.5, y as f32 + 0.5),
-                    CollisionComponent(true),
-                ],
-            );
+        for z in 0..map.dims.1 {
+            let z = z as f32 + 0.5;
+            for &x in &[x_max + 0.5, -0.5] {
+                let params = vec![("material", wall_material.clone())];
+                spawner.spawn_at(&mut world, 'W', (x, z), params)?;
+            }
         }
 
         // Add the tile walls and doors.
@@ -171,320 +158,150 @@ impl World {
                 match map.tiles[x + y * map.dims.0] {
                     Tile::Empty => {}
                     Tile::Wall => {
-                        world.new_entity(
-                            "wall",
-                            hlist![
-                                RenderComponent {
-                                    model: wall_model.clone(),
-                                },
-                                LocationComponent::pos(x as f32 + 0.5, 0.5, y as f32 + 0.5),
-                                CollisionComponent(true),
-                            ],
-                        );
+                        let params = vec![("material", wall_material.clone())];
+                        spawner.spawn_tile(&mut world, 'W', (x, y), params)?;
                     }
                     Tile::Door(key) => {
-                        let material = Arc::new(Material::flat(map.door_colors[key as usize - 65]));
-                        let model = Arc::new(Model::cube(Some(material)));
-                        world.new_entity(
-                            "door",
-                            hlist![
-                                RenderComponent { model },
-                                LocationComponent::pos(x as f32 + 0.5, 0.5, y as f32 + 0.5),
-                                CollisionComponent(true),
-                                DoorComponent(key),
-                            ],
-                        );
+                        let color = map.door_colors[key as usize - 65];
+                        let params = vec![("letter", json!(key)), ("color", json!(color))];
+                        spawner.spawn_tile(&mut world, key, (x, y), params)?;
                     }
                 }
             }
         }
 
-        // Load the keys.
-        for (x, y, ch) in map.keys {
+        // Add the keys.
+        for &(x, y, ch) in &map.keys {
             let mut color = map.door_colors[ch as usize - 97];
             for i in 0..3 {
                 color[i] = 1.0 - color[i];
             }
-            let material = Arc::new(Material::flat(color));
-            let model = Arc::new(Model::cube(Some(material)));
-            world.new_entity(
-                "key",
-                hlist![
-                    RenderComponent { model },
-                    LocationComponent {
-                        xyz: Point3::new(x as f32 + 0.5, 0.1, y as f32 + 0.5),
-                        rotation: Vector3::new(0.0, 0.0, 0.0),
-                        scale: 0.1,
-                    },
-                    KeyComponent {
-                        letter: ch,
-                        held: false,
-                    }
-                ],
-            );
+            let params = vec![("letter", json!(ch)), ("color", json!(color))];
+            spawner.spawn_tile(&mut world, ch, (x, y), params)?;
+        }
+
+        // Add the enemies, each on the first tile of its patrol.
+        for enemy in &map.enemies {
+            let (x, y) = enemy.patrol[0];
+            let xz = (x as f32 + 0.5, y as f32 + 0.5);
+            let params = vec![("patrol", to_value(&enemy.patrol)?)];
+            spawner.spawn(&mut world, "enemy", xz, params)?;
+        }
+
+        // Add the explicitly placed prefabs.
+        for placement in &map.placements {
+            let (x, y) = placement.at;
+            let xz = (x as f32 + 0.5, y as f32 + 0.5);
+            let params = placement.params.clone();
+            spawner.spawn(&mut world, &placement.prefab, xz, params)?;
         }
 
         // Create the win decal.
-        world.new_entity(
-            "win",
-            hlist![DecalComponent {
-                enabled: false,
-                image: load_texture("", base_path.join(map.win_decal))?,
-            }],
-        );
+        let image = base_path.join(&map.win_decal);
+        spawner.spawn(
+            &mut world,
+            "win-decal",
+            (0.0, 0.0),
+            vec![("image", to_value(image)?)],
+        )?;
 
-        let render_data = RenderData::new(
+        seed_transforms(&mut world);
+        let mut assets = RenderAssets::new(
             map.clear_color,
-            Program::from_source(
-                facade,
-                &read_file(base_path.join(&map.shader_vert))?,
-                &read_file(base_path.join(&map.shader_frag))?,
-                None,
-            )?,
+            read_file(base_path.join(&map.shader_vert))?,
+            read_file(base_path.join(&map.shader_frag))?,
         );
-        Ok((render_data, world))
-    }
-
-    /// Loads the world from the map whose file path is given.
-    pub fn from_map_file(
-        path: impl AsRef<Path>,
-        facade: &impl Facade,
-    ) -> Fallible<(RenderData, World)> {
-        let map = {
-            match read_file_and_unjson(path.as_ref()) {
-                Ok(map) => map,
-                Err(err) => {
-                    warn!("While loading map: {}", err);
-                    warn!("Falling back to old-style map loading...");
-                    let map = read_file_and_parse_to(path.as_ref())
-                        .with_context(|err| format_err!("While loading old-style map: {}", err))?;
-                    info!("Successfully loaded old-style map.");
-                    map
-                }
-            }
-        };
-        let base_path = path.as_ref().parent().unwrap_or_else(|| path.as_ref());
-        World::from_map(map, base_path, facade)
-            .context("While building world")
-            .map_err(From::from)
-    }
-
-    /// Tries to get the given components for a given entity.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # #[macro_use] extern crate frunk;
-    /// # extern crate game;
-    /// # #[macro_use] extern crate typemap;
-    /// # use game::World;
-    /// # fn main() {
-    /// #[derive(Debug, PartialEq)]
-    /// struct FooComponent(&'static str);
-    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
-    ///
-    /// #[derive(Debug, PartialEq)]
-    /// struct BarComponent(usize);
-    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
-    ///
-    /// #[derive(Debug, PartialEq)]
-    /// struct BazComponent;
-    /// impl typemap::Key for BazComponent { type Value = BazComponent; }
-    ///
-    /// let mut world = World::default();
-    /// let me = world.new_entity("h42", hlist![FooComponent("hello"), BarComponent(42)]);
-    ///
-    /// assert_eq!(world.get(me), Some(hlist![&FooComponent("hello")]));
-    /// assert_eq!(world.get(me), Some(hlist![&BarComponent(42)]));
-    /// assert_eq!(world.get(me), Some(hlist![
-    ///     &FooComponent("hello"),
-    ///     &BarComponent(42),
-    /// ]));
-    /// assert_eq!(world.get::<Hlist![&BazComponent]>(me), None);
-    /// # }
-    /// ```
-    pub fn get<'a, C: ComponentRefHList<'a>>(&'a self, entity: Entity) -> Option<C> {
-        self.components
-            .get(&entity)
-            .and_then(ComponentRefHList::get_from_component_map)
-    }
-
-    /// Tries to get a single component, mutably, for a given entity.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # #[macro_use] extern crate frunk;
-    /// # extern crate game;
-    /// # #[macro_use] extern crate typemap;
-    /// # use game::World;
-    /// # fn main() {
-    /// #[derive(Debug, PartialEq)]
-    /// struct FooComponent(&'static str);
-    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
-    ///
-    /// #[derive(Debug, PartialEq)]
-    /// struct BarComponent(usize);
-    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
-    ///
-    /// let mut world = World::default();
-    /// let me = world.new_entity("h", hlist![FooComponent("hello")]);
-    ///
-    /// assert_eq!(world.get_mut(me), Some(&mut FooComponent("hello")));
-    /// assert_eq!(world.get_mut::<BarComponent>(me), None);
-    /// # }
-    /// ```
-    pub fn get_mut<T>(&mut self, entity: Entity) -> Option<&mut T>
-    where
-        T: Key<Value = T> + Send + Sync,
-    {
-        self.components
-            .get_mut(&entity)
-            .and_then(ShareMap::get_mut::<T>)
-    }
-
-    /// Tries to get a single component for a given entity.
-    pub fn get_one<T>(&self, entity: Entity) -> Option<&T>
-    where
-        T: Key<Value = T> + Send + Sync,
-    {
-        self.components.get(&entity).and_then(ShareMap::get::<T>)
-    }
-
-    /// Iterates over entities which have all the given components.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # #[macro_use] extern crate frunk;
-    /// # extern crate game;
-    /// # #[macro_use] extern crate typemap;
-    /// # use game::World;
-    /// # fn main() {
-    /// #[derive(Debug)]
-    /// struct FooComponent;
-    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
-    ///
-    /// #[derive(Debug)]
-    /// struct BarComponent(usize);
-    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
-    ///
-    /// let mut world = World::default();
-    /// world.new_entity("foo", hlist![FooComponent]);
-    /// world.new_entity("foobar", hlist![FooComponent, BarComponent(42)]);
-    /// for (e, hlist_pat![foo, bar]) in world.iter() {
-    ///     println!("Entity: {:?}", e);
-    ///     println!("Foo: {:?}", foo as &FooComponent);
-    ///     println!("Bar: {:?}", bar as &BarComponent);
-    /// }
-    /// # }
-    /// ```
-    pub fn iter<'a, C>(&'a self) -> impl 'a + Iterator<Item = (Entity, C)>
-    where
-        C: 'a + ComponentRefHList<'a>,
-    {
-        self.components
-            .keys()
-            .cloned()
-            .filter_map(move |entity| self.get(entity).map(|cs| (entity, cs)))
-    }
-
-    /// Deletes an entity. Panics if the entity has already been deleted.
-    pub fn delete_entity(&mut self, entity: Entity) {
-        self.components.remove(&entity);
-    }
-
-    /// Creates a new entity with the given components.
-    pub fn new_entity<C: ComponentHList>(&mut self, name: &str, components: C) -> Entity {
-        let entity = Entity(format!("{}:{}", self.next_entity, name).into());
-        self.next_entity += 1;
-        let mut map = ShareMap::custom();
-        components.add_to_component_map(&mut map);
-        self.components.insert(entity, map);
-        entity
+        assets.add_world(&world);
+        Ok((assets, world))
     }
 
-    /// Iterates in parallel over entities which have all the given components.
+    /// Loads the world from the map whose file path is given, as with `World::from_map`.
     ///
     /// # Example
     ///
     /// ```
     /// # #[macro_use] extern crate frunk;
     /// # extern crate game;
-    /// # #[macro_use] extern crate typemap;
-    /// # extern crate rayon;
-    /// # use game::World;
-    /// # use rayon::iter::ParallelIterator;
+    /// # use game::{components::{CameraComponent, DoorComponent, KeyComponent}, With, World};
     /// # fn main() {
-    /// #[derive(Debug)]
-    /// struct FooComponent;
-    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
-    ///
-    /// #[derive(Debug)]
-    /// struct BarComponent(usize);
-    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
-    ///
-    /// let mut world = World::default();
-    /// world.new_entity("foo", hlist![FooComponent]);
-    /// world.new_entity("foobar", hlist![FooComponent, BarComponent(42)]);
-    /// world.par_iter().for_each(|(e, hlist_pat![foo, bar])| {
-    ///     println!("Entity: {:?}", e);
-    ///     println!("Foo: {:?}", foo as &FooComponent);
-    ///     println!("Bar: {:?}", bar as &BarComponent);
-    /// });
+    /// let (assets, world) = World::from_map_file("maps/textured.json").unwrap();
+    /// assert!(world.single::<Hlist![With<CameraComponent>]>().is_ok());
+    /// assert_eq!(
+    ///     world.iter::<Hlist![&DoorComponent]>().count(),
+    ///     world.iter::<Hlist![&KeyComponent]>().count()
+    /// );
+    /// assert!(!assets.models.is_empty());
     /// # }
     /// ```
-    pub fn par_iter<'a, C>(&'a self) -> impl 'a + ParallelIterator<Item = (Entity, C)>
-    where
-        C: 'a + ComponentRefHList<'a> + Send,
-    {
-        self.components
-            .par_iter()
-            .map(|(&k, _)| k)
-            .filter_map(move |entity| self.get(entity).map(|cs| (entity, cs)))
+    pub fn from_map_file(path: impl AsRef<Path>) -> Fallible<(RenderAssets, World)> {
+        let map = Map::load(path.as_ref())?;
+        let base_path = path.as_ref().parent().unwrap_or_else(|| path.as_ref());
+        World::from_map(map, base_path)
+            .context("While building world")
+            .map_err(From::from)
     }
 }
 
-/// A trait for an HList containing only components (i.e. types that
-/// `impl typemap::Key<Value = Self>`).
-pub trait ComponentHList {
-    fn add_to_component_map(self, component_map: &mut ShareMap);
-}
-
-impl<H, T> ComponentHList for HCons<H, T>
-where
-    H: Key<Value = H> + Send + Sync,
-    T: ComponentHList,
-{
-    fn add_to_component_map(self, component_map: &mut ShareMap) {
-        component_map.insert::<H>(self.head);
-        self.tail.add_to_component_map(component_map);
+/// Returns the source of a material which may be loaded from a file.
+fn material_source(base_path: &Path, path: &Option<PathBuf>) -> MaterialSource {
+    match *path {
+        Some(ref path) => MaterialSource::Mtl(base_path.join(path)),
+        None => MaterialSource::Default,
     }
 }
 
-impl ComponentHList for HNil {
-    fn add_to_component_map(self, _: &mut ShareMap) {}
+/// Spawns prefabs for a map.
+struct Spawner<'a> {
+    map: &'a Map,
+    prefabs: &'a PrefabRegistry,
+    map_dir: Value,
 }
 
-/// A trait for an HList containing only references to components.
-pub trait ComponentRefHList<'a>: Sized {
-    fn get_from_component_map(component_map: &'a ShareMap) -> Option<Self>;
-}
+impl<'a> Spawner<'a> {
+    /// Spawns the prefab for a tile character in the center of a tile.
+    fn spawn_tile(
+        &self,
+        world: &mut World,
+        ch: char,
+        (x, y): (usize, usize),
+        params: Vec<(&str, Value)>,
+    ) -> Fallible<Entity> {
+        self.spawn_at(world, ch, (x as f32 + 0.5, y as f32 + 0.5), params)
+    }
 
-impl<'a, H, T> ComponentRefHList<'a> for HCons<&'a H, T>
-where
-    H: Key<Value = H> + Send + Sync,
-    T: ComponentRefHList<'a>,
-{
-    fn get_from_component_map(component_map: &'a ShareMap) -> Option<Self> {
-        let head = component_map.get::<H>()?;
-        let tail = T::get_from_component_map(component_map)?;
-        Some(HCons { head, tail })
+    /// Spawns the prefab for a tile character at the given point.
+    fn spawn_at(
+        &self,
+        world: &mut World,
+        ch: char,
+        xz: (f32, f32),
+        params: Vec<(&str, Value)>,
+    ) -> Fallible<Entity> {
+        let prefab = self
+            .map
+            .prefab_for(ch)
+            .ok_or_else(|| format_err!("No prefab is defined for the tile {:?}", ch))?;
+        self.spawn(world, prefab, xz, params)
     }
-}
 
-impl<'a> ComponentRefHList<'a> for HNil {
-    fn get_from_component_map(_: &'a ShareMap) -> Option<Self> {
-        Some(HNil)
+    /// Spawns a prefab at the given point, setting its `x`, `z` and `map_dir` parameters before
+    /// the given ones.
+    fn spawn<P, S>(
+        &self,
+        world: &mut World,
+        prefab: &str,
+        (x, z): (f32, f32),
+        params: P,
+    ) -> Fallible<Entity>
+    where
+        P: IntoIterator<Item = (S, Value)>,
+        S: Into<String>,
+    {
+        let mut all_params = BTreeMap::new();
+        all_params.insert("x".to_string(), json!(x));
+        all_params.insert("z".to_string(), json!(z));
+        all_params.insert("map_dir".to_string(), self.map_dir.clone());
+        all_params.extend(params.into_iter().map(|(name, value)| (name.into(), value)));
+        self.prefabs.spawn(world, prefab, all_params)
     }
 }
@@ -1,102 +1,620 @@
 //! Common systems.
 
-use cgmath::{Deg, Matrix3, Vector3};
+use cgmath::{InnerSpace, Matrix4, Point2, Point3, SquareMatrix, Vector2};
 pub use crate::gui::{ControlSystem, GuiSystem};
 use crate::{
+    collision::{slide, Aabb},
     components::{
-        CameraComponent, CollisionComponent, DecalComponent, DoorComponent, GoalComponent,
-        KeyComponent, LocationComponent,
+        CameraComponent, CollisionComponent, ControllerComponent, DoorComponent, EnemyComponent,
+        GoalComponent, HeldComponent, KeyComponent, LocationComponent, NameComponent,
+        ParentComponent, PreviousLocationComponent, TransformComponent,
     },
-    State, System,
+    events::{DoorUnlocked, GoalReached, KeyPickedUp, PlayerCaught},
+    pathfind::{find_path, line_of_sight},
+    resources::{InputState, MapInfo},
+    schedule::ScheduleBuilder,
+    spatial::{SpatialIndex, CELL_SIZE},
+    Changed, ComponentRemoved, EntityDeleted, Entity, EventReader, Events, ParallelSystem, State,
+    System, SystemAccess, Tile, With, Without, World, WorldView,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::replace,
 };
-use smallvec::SmallVec;
-use std::mem::replace;
 
-/// A system that lets the user grab keys.
-pub struct HoldSystem;
+/// Adds the systems that make up a step of the game's simulation to a schedule, after any systems
+/// already in it. Input and rendering aren't included, since they happen once per frame rather
+/// than once per step.
+///
+/// While the player is caught, the systems that move things or change the game are paused, but
+/// the ones keeping derived data such as transforms up to date still run.
+pub fn add_simulation_systems(schedule: ScheduleBuilder) -> ScheduleBuilder {
+    schedule
+        .parallel_system("previous-locations", PreviousLocationSystem)
+        .parallel_system("movement", MovementSystem)
+        .run_if(not_caught)
+        .parallel_system("sinking-doors", SinkingDoorSystem)
+        .after("movement")
+        .run_if(not_caught)
+        .parallel_system("spinning-keys", SpinningKeySystem)
+        .after("sinking-doors")
+        .run_if(not_caught)
+        .parallel_system("enemies", EnemySystem)
+        .after("spinning-keys")
+        .run_if(not_caught)
+        .parallel_system("transforms", TransformSystem::default())
+        .parallel_system("spatial-index", SpatialIndexSystem::default())
+        .parallel_system("snag", SnagSystem)
+        .run_if(not_caught)
+        .parallel_system("the-floor-is-lava", TheFloorIsLavaSystem)
+        .run_if(not_caught)
+        .parallel_system("unlock", UnlockSystem)
+        .run_if(not_caught)
+        .system("win", WinSystem)
+        .system("lose", LoseSystem)
+}
 
-impl System for HoldSystem {
-    fn step(&mut self, state: &mut State, _dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
+/// Returns whether the game is going on, rather than paused because an enemy caught the player.
+fn not_caught(state: &State) -> bool {
+    !state.is_lost()
+}
+
+/// How fast the camera moves, in tiles per second.
+pub const MOVE_SPEED: f32 = 1.5;
+
+/// How far the camera turns per unit of mouse motion, in degrees.
+pub const TURN_SPEED: f32 = 0.1;
+
+/// A system that moves the camera according to the `InputState`, sliding along the entities with
+/// a `CollisionComponent` in its way. The camera is a circle on the floor with the radius in its
+/// `ControllerComponent`, or that of its `LocationComponent` if it has none. The mouse motion in
+/// the `InputState` is used up, so that it turns the camera only once however many steps run in a
+/// frame.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate frunk;
+/// # extern crate game;
+/// # use game::{
+/// #     components::{CameraComponent, LocationComponent},
+/// #     resources::InputState,
+/// #     schedule::Schedule,
+/// #     systems::MovementSystem,
+/// #     State, World,
+/// # };
+/// # fn main() {
+/// let mut world = World::default();
+/// let camera = world.new_entity(hlist![CameraComponent, LocationComponent::pos(0.5, 0.0, 0.5)]);
+/// world.insert_resource(InputState {
+///     move_forward: 1.0,
+///     ..InputState::default()
+/// });
+///
+/// // Gameplay systems don't need a display to run.
+/// let mut schedule = Schedule::builder()
+///     .parallel_system("movement", MovementSystem)
+///     .build()
+///     .unwrap();
+/// let mut state = State::Playing(world);
+/// schedule.run(&mut state, 100);
+///
+/// // The camera moves at the same speed however long the step was.
+/// let world = state.world_mut().unwrap();
+/// let z = world.get_one::<LocationComponent>(camera).unwrap().xyz.z;
+/// assert!((z - 0.65).abs() < 1e-6);
+/// # }
+/// ```
+pub struct MovementSystem;
+
+impl ParallelSystem for MovementSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<CollisionComponent>()
+            .read::<ControllerComponent>()
+            .write::<LocationComponent>()
+            .write_resource::<InputState>()
+            .read_resource::<SpatialIndex>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, dt: u64) {
+        let input = match world.resource::<InputState>() {
+            Some(&input) => input,
+            None => return,
+        };
+        let camera = world.single::<Hlist![With<CameraComponent>, &LocationComponent]>();
+        let (camera, old_loc) = match camera {
+            Ok((camera, hlist_pat![_, &loc])) => (camera, loc),
+            Err(err) => {
+                warn!("Couldn't find the camera: {}", err);
+                return;
+            }
         };
 
-        let camera: LocationComponent = match world.iter().next() {
-            Some((_, hlist_pat![CameraComponent, loc])) => *loc,
-            None => {
-                warn!("No camera?");
+        // Apply motion to the camera, sliding along anything in the way.
+        let distance = MOVE_SPEED * dt as f32 / 1000.0;
+        let mut new_loc =
+            old_loc.move_by(input.move_forward * distance, input.move_strafe * distance);
+        let motion = new_loc.xyz - old_loc.xyz;
+        let radius = match world.get::<Hlist![&ControllerComponent]>(camera) {
+            Some(hlist_pat![controller]) => controller.radius,
+            None => old_loc.radius(),
+        };
+
+        // The spatial index measures distances in three dimensions, so search a little further to
+        // take in colliders above or below the camera.
+        let reach = radius + motion.magnitude() + CELL_SIZE;
+        let nearby = world
+            .resource::<SpatialIndex>()
+            .map(|index| index.query_radius(old_loc.xyz, reach))
+            .unwrap_or_default();
+        let boxes = nearby
+            .into_iter()
+            .filter_map(|entity| {
+                world
+                    .get::<Hlist![&CollisionComponent, &LocationComponent]>(entity)
+                    .map(|hlist_pat![_, loc]| {
+                        Aabb::around(Point2::new(loc.xyz.x, loc.xyz.z), loc.scale)
+                    })
+            })
+            .collect::<Vec<_>>();
+        let start = Point2::new(old_loc.xyz.x, old_loc.xyz.z);
+        let end = slide(start, Vector2::new(motion.x, motion.z), radius, &boxes);
+        new_loc.xyz.x = end.x;
+        new_loc.xyz.z = end.y;
+
+        if let Some(hlist_pat![camera_loc]) =
+            world.get_mut::<Hlist![&mut LocationComponent]>(camera)
+        {
+            *camera_loc =
+                new_loc.rotate_by(input.move_pitch * TURN_SPEED, input.move_yaw * TURN_SPEED);
+        }
+        if let Some(input) = world.resource_mut::<InputState>() {
+            input.move_pitch = 0.0;
+            input.move_yaw = 0.0;
+        }
+    }
+}
+
+/// How fast enemies move, in tiles per second. It's faster than `MOVE_SPEED`, so that the player
+/// can't outrun an enemy which has seen them.
+pub const ENEMY_SPEED: f32 = 2.0;
+
+/// How far enemies can see, in tiles.
+pub const ENEMY_SIGHT: f32 = 4.0;
+
+/// A system that moves enemies. An enemy walks from one waypoint of its patrol to the next until
+/// the player comes within `ENEMY_SIGHT` of it with nothing in the way, then chases them. If it
+/// loses sight of them, it goes to where it last saw them before heading back to its patrol.
+///
+/// Enemies find their way around with `find_path`, walking from the center of one tile to the
+/// next, and treat walls and locked doors as blocking both their way and their sight. They keep
+/// to the way they found until they head somewhere else or it's blocked.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::{playtest::Playtest, Enemy, Map, World};
+/// # fn main() {
+/// let mut map: Map = "5 1\nS000G\n".parse().unwrap();
+/// map.enemies.push(Enemy {
+///     patrol: vec![(3, 0)],
+/// });
+/// let (_, world) = World::from_map(map, "maps").unwrap();
+/// let mut playtest = Playtest::new(world).unwrap();
+///
+/// // The enemy can see the player from where it stands, so it comes for them.
+/// playtest.run_script(&"expect_caught(1000)".parse().unwrap()).unwrap();
+///
+/// // After a while, the game goes on, with the player back at the start.
+/// playtest.run_script(&"wait(2000)".parse().unwrap()).unwrap();
+/// assert!(!playtest.state().is_lost());
+/// # }
+/// ```
+pub struct EnemySystem;
+
+impl ParallelSystem for EnemySystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<CameraComponent>()
+            .read::<CollisionComponent>()
+            .read::<DoorComponent>()
+            .write::<EnemyComponent>()
+            .write::<LocationComponent>()
+            .read_resource::<MapInfo>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, dt: u64) {
+        let camera = world.single::<Hlist![With<CameraComponent>, &LocationComponent]>();
+        let player = match camera {
+            Ok((_, hlist_pat![_, loc])) => (loc.xyz.x, loc.xyz.z),
+            Err(err) => {
+                warn!("Couldn't find the camera: {}", err);
                 return;
             }
         };
 
-        let held_keys = world
-            .iter()
-            .filter(|(_, hlist_pat![&KeyComponent {held, ..}])| held)
-            .map(|(entity, _)| entity)
-            .collect::<SmallVec<[_; 8]>>();
+        // Doors block enemies until they're unlocked, which removes their collision.
+        let locked = world
+            .iter::<Hlist![With<DoorComponent>, With<CollisionComponent>, &LocationComponent]>()
+            .map(|(_, hlist_pat![_, _, loc])| tile_at((loc.xyz.x, loc.xyz.z)))
+            .collect::<HashSet<_>>();
+        let enemies = world
+            .iter::<Hlist![&EnemyComponent, &LocationComponent]>()
+            .map(|(entity, hlist_pat![enemy, &loc])| (entity, enemy.clone(), loc))
+            .collect::<Vec<_>>();
+
+        let mut moved = Vec::new();
+        {
+            let map = match world.resource::<MapInfo>() {
+                Some(map) => map,
+                None => return,
+            };
+            let passable = |(x, y)| match map.tile(x, y) {
+                Some(Tile::Empty) => true,
+                Some(Tile::Door(_)) => !locked.contains(&(x, y)),
+                Some(Tile::Wall) | None => false,
+            };
+            let distance = ENEMY_SPEED * dt as f32 / 1000.0;
+            for (entity, mut enemy, mut loc) in enemies {
+                move_enemy(&mut enemy, &mut loc, player, map.dims, &passable, distance);
+                moved.push((entity, enemy, loc));
+            }
+        }
 
-        for entity in held_keys {
-            let loc: &mut LocationComponent = world.get_mut(entity).unwrap();
-            let forward = Matrix3::from_angle_y(Deg(camera.rotation[1])) * Vector3::unit_z();
-            loc.xyz = camera.xyz + 0.3 * forward;
-            loc.xyz.y = 0.1;
+        for (entity, new_enemy, new_loc) in moved {
+            if let Some(hlist_pat![enemy, loc]) =
+                world.get_mut::<Hlist![&mut EnemyComponent, &mut LocationComponent]>(entity)
+            {
+                *enemy = new_enemy;
+                *loc = new_loc;
+            }
         }
     }
 }
 
-/// A system that makes unlocked doors sink.
-pub struct SinkingDoorSystem;
+/// Moves an enemy up to the given distance for a step, towards the player if it can see them and
+/// along its patrol otherwise.
+fn move_enemy(
+    enemy: &mut EnemyComponent,
+    loc: &mut LocationComponent,
+    player: (f32, f32),
+    dims: (usize, usize),
+    passable: &impl Fn((usize, usize)) -> bool,
+    distance: f32,
+) {
+    let pos = (loc.xyz.x, loc.xyz.z);
+    let sees = (player.0 - pos.0).hypot(player.1 - pos.1) <= ENEMY_SIGHT
+        && line_of_sight(pos, player, passable);
+    if sees {
+        enemy.last_seen = Some(tile_at(player));
+    }
+
+    // Head for the player while they can be seen, and otherwise for the middle of the tile they
+    // were last seen on, or of the next waypoint.
+    let (target, point) = match (enemy.last_seen, enemy.patrol.get(enemy.next)) {
+        (Some(tile), _) if sees => (tile, player),
+        (Some(tile), _) | (None, Some(&tile)) => (tile, tile_center(tile)),
+        (None, None) => return,
+    };
+    let here = tile_at(pos);
+    let aim = if here == target {
+        point
+    } else {
+        // Keep to the way found before, unless it leads somewhere else or has been blocked.
+        let along = match enemy.path.iter().position(|&tile| tile == here) {
+            Some(i) if enemy.path.last() == Some(&target) && passable(enemy.path[i + 1]) => {
+                Some(i)
+            }
+            _ => None,
+        };
+        let next = match along {
+            Some(i) => i + 1,
+            None => match find_path(dims, here, target, passable) {
+                Some(path) => {
+                    enemy.path = path;
+                    1
+                }
+                None => {
+                    // The way has been blocked, so give up on the chase, or wait for it to clear.
+                    enemy.path.clear();
+                    enemy.last_seen = None;
+                    return;
+                }
+            },
+        };
+        tile_center(enemy.path[next])
+    };
+
+    let (dx, dz) = (aim.0 - pos.0, aim.1 - pos.1);
+    let left = dx.hypot(dz);
+    if left > distance {
+        loc.xyz.x += dx / left * distance;
+        loc.xyz.z += dz / left * distance;
+    } else {
+        loc.xyz.x = aim.0;
+        loc.xyz.z = aim.1;
+    }
+    if left > 0.0 {
+        loc.rotation[1] = dx.atan2(dz).to_degrees();
+    }
+
+    // Once there, look around for the player, or go on to the next waypoint.
+    if here == target && left <= distance && !sees {
+        if enemy.last_seen.is_some() {
+            enemy.last_seen = None;
+        } else {
+            enemy.next = (enemy.next + 1) % enemy.patrol.len();
+        }
+    }
+}
+
+/// Returns the tile a point, in tile units, is on.
+fn tile_at((x, z): (f32, f32)) -> (usize, usize) {
+    (x.max(0.0) as usize, z.max(0.0) as usize)
+}
 
-impl System for SinkingDoorSystem {
+/// Returns the point in the middle of a tile.
+fn tile_center((x, y): (usize, usize)) -> (f32, f32) {
+    (x as f32 + 0.5, y as f32 + 0.5)
+}
+
+/// Returns whether the camera, with the given radius, collides with another object.
+fn touches(camera: &LocationComponent, radius: f32, other: &LocationComponent) -> bool {
+    (camera.xyz - other.xyz).magnitude() < radius + other.radius()
+}
+
+/// How long the game stays lost after an enemy catches the player, in milliseconds.
+pub const CAUGHT_MILLIS: u64 = 1_500;
+
+/// A system that lets enemies catch the player. When one does, the game is lost for
+/// `CAUGHT_MILLIS`, after which the player is sent back to the start, the enemies go back to the
+/// start of their patrols, and the game goes on. Keys that were picked up stay held, and doors
+/// that were unlocked stay open.
+pub struct LoseSystem;
+
+impl System for LoseSystem {
     fn step(&mut self, state: &mut State, dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
+        let caught = match state {
+            State::Playing(ref mut world) => {
+                let camera = world.single::<Hlist![With<CameraComponent>, &LocationComponent]>();
+                let (camera, camera_loc) = match camera {
+                    Ok((camera, hlist_pat![_, &loc])) => (camera, loc),
+                    Err(err) => {
+                        warn!("Couldn't find the camera: {}", err);
+                        return;
+                    }
+                };
+                let radius = match world.get::<Hlist![&ControllerComponent]>(camera) {
+                    Some(hlist_pat![controller]) => controller.radius,
+                    None => camera_loc.radius(),
+                };
+
+                let nearby = match world.resource::<SpatialIndex>() {
+                    Some(index) => index.query_radius(camera_loc.xyz, radius),
+                    None => return,
+                };
+                let enemy = nearby.into_iter().find(|&entity| {
+                    match world.get::<Hlist![&EnemyComponent, &LocationComponent]>(entity) {
+                        Some(hlist_pat![_, loc]) => touches(&camera_loc, radius, loc),
+                        None => false,
+                    }
+                });
+
+                if let Some(enemy) = enemy {
+                    info!("{} caught the player!", world.named(enemy));
+                    world.send_event(PlayerCaught { enemy });
+                    true
+                } else {
+                    false
+                }
+            }
+            State::Lost(ref mut world, ref mut t) => {
+                *t += dt;
+                if *t > CAUGHT_MILLIS {
+                    return_to_start(world);
+                }
+                false
+            }
+            State::Done(_, _) | State::Close => false,
         };
 
-        let to_sink = world
-            .iter()
-            .filter(|(_, hlist_pat![&DoorComponent(_), &CollisionComponent(c)])| !c)
-            .map(|(entity, _)| entity)
-            .collect::<SmallVec<[_; 8]>>();
+        let resume = match *state {
+            State::Lost(_, t) => t > CAUGHT_MILLIS,
+            _ => false,
+        };
+        if caught || resume {
+            let world = match replace(state, State::Close) {
+                State::Playing(world) | State::Lost(world, _) => world,
+                _ => unreachable!(),
+            };
+            *state = if caught {
+                State::Lost(world, 0)
+            } else {
+                State::Playing(world)
+            };
+        }
+    }
+}
+
+/// Moves the camera back to the start of the map, and each enemy back to the first waypoint of
+/// its patrol.
+fn return_to_start(world: &mut World) {
+    let start = match world.resource::<MapInfo>() {
+        Some(map) => tile_center(map.start),
+        None => {
+            warn!("Couldn't find the start, since there's no MapInfo");
+            return;
+        }
+    };
+    match world.single_mut::<Hlist![With<CameraComponent>, &mut LocationComponent]>() {
+        Ok((_, hlist_pat![_, loc])) => {
+            loc.xyz.x = start.0;
+            loc.xyz.z = start.1;
+        }
+        Err(err) => warn!("Couldn't find the camera: {}", err),
+    }
+
+    for (_, hlist_pat![enemy, loc]) in
+        world.iter_mut::<Hlist![&mut EnemyComponent, &mut LocationComponent]>()
+    {
+        enemy.next = 0;
+        enemy.last_seen = None;
+        enemy.path.clear();
+        if let Some(&tile) = enemy.patrol.first() {
+            let (x, z) = tile_center(tile);
+            loc.xyz.x = x;
+            loc.xyz.z = z;
+        }
+    }
+}
+
+/// A system that stores each entity's `LocationComponent` in its `PreviousLocationComponent`
+/// before the other systems move it, giving any entity with a location but no previous location
+/// one. It should run before anything else in a simulation step.
+pub struct PreviousLocationSystem;
 
-        for entity in to_sink {
-            if let Some(LocationComponent { xyz, .. }) = world.get_mut(entity) {
-                xyz[1] -= (dt as f32) / 2500.0;
+impl ParallelSystem for PreviousLocationSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<LocationComponent>()
+            .write::<PreviousLocationComponent>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        // Locations that didn't change last step are already stored.
+        let moved = world
+            .iter::<Hlist![Changed<LocationComponent>, &LocationComponent]>()
+            .map(|(entity, hlist_pat![_, &loc])| (entity, loc))
+            .collect::<Vec<_>>();
+        for (entity, loc) in moved {
+            match world.get_mut::<Hlist![&mut PreviousLocationComponent]>(entity) {
+                Some(hlist_pat![prev]) => prev.0 = loc,
+                None => world
+                    .commands()
+                    .insert(entity, PreviousLocationComponent(loc)),
             }
         }
     }
 }
 
+/// A system that makes unlocked doors sink.
+pub struct SinkingDoorSystem;
+
+impl ParallelSystem for SinkingDoorSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access.write::<LocationComponent>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, dt: u64) {
+        let doors = world.iter_mut::<Hlist![
+            With<DoorComponent>,
+            Without<CollisionComponent>,
+            &mut LocationComponent
+        ]>();
+        for (_, hlist_pat![_, _, loc]) in doors {
+            loc.xyz[1] -= (dt as f32) / 2500.0;
+        }
+    }
+}
+
 /// A system that lets the user grab keys.
 pub struct SnagSystem;
 
-impl System for SnagSystem {
-    fn step(&mut self, state: &mut State, _dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
-        };
+impl ParallelSystem for SnagSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<ControllerComponent>()
+            .read::<KeyComponent>()
+            .read::<LocationComponent>()
+            .read::<NameComponent>()
+            .read_resource::<SpatialIndex>();
+    }
 
-        let camera: LocationComponent = match world.iter().next() {
-            Some((_, hlist_pat![CameraComponent, loc])) => *loc,
-            None => {
-                warn!("No camera?");
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        let camera = world.single::<Hlist![With<CameraComponent>, &LocationComponent]>();
+        let (camera, camera_loc) = match camera {
+            Ok((camera, hlist_pat![_, &loc])) => (camera, loc),
+            Err(err) => {
+                warn!("Couldn't find the camera: {}", err);
                 return;
             }
         };
+        let radius = match world.get::<Hlist![&ControllerComponent]>(camera) {
+            Some(hlist_pat![controller]) => controller.radius,
+            None => camera_loc.radius(),
+        };
 
-        let snagged_keys = world
-            .iter()
-            .filter(|(_, hlist_pat![loc, &KeyComponent {held, ..}])| !held && camera.collides(loc))
-            .map(|(entity, _)| entity)
-            .collect::<SmallVec<[_; 2]>>();
+        let nearby = match world.resource::<SpatialIndex>() {
+            Some(index) => index.query_radius(camera_loc.xyz, radius),
+            None => return,
+        };
+        for key in nearby {
+            let components = world.get::<Hlist![
+                &KeyComponent,
+                Without<HeldComponent>,
+                &LocationComponent
+            ]>(key);
+            let (letter, loc) = match components {
+                Some(hlist_pat![&KeyComponent { letter }, _, loc]) => (letter, loc),
+                None => continue,
+            };
+            if touches(&camera_loc, radius, loc) {
+                // Hold the key in front of and below the camera, taking its scale into account.
+                let held_loc = LocationComponent {
+                    xyz: Point3::new(0.0, -0.15, 0.3) / camera_loc.scale,
+                    rotation: loc.rotation,
+                    scale: loc.scale / camera_loc.scale,
+                };
+                world.commands().remove::<CollisionComponent>(key);
+                world.commands().insert_all(key, hlist![HeldComponent, held_loc]);
+                world.commands().set_parent(key, camera);
+                world.commands().send_event(KeyPickedUp { key, letter });
+                info!("Snagged {}!", world.named(key));
+            }
+        }
+    }
+}
+
+/// A system that keeps the `SpatialIndex` in sync with the entities' `TransformComponent`s.
+#[derive(Default)]
+pub struct SpatialIndexSystem {
+    deleted: EventReader<EntityDeleted>,
+    removed: EventReader<ComponentRemoved<TransformComponent>>,
+}
 
-        for entity in snagged_keys {
-            let KeyComponent { ref mut held, .. } = world.get_mut(entity).unwrap();
-            *held = true;
-            info!("Snagged {}!", entity);
+impl ParallelSystem for SpatialIndexSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<TransformComponent>()
+            .read_resource::<Events<EntityDeleted>>()
+            .read_resource::<Events<ComponentRemoved<TransformComponent>>>()
+            .write_resource::<SpatialIndex>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        let moved = world
+            .iter::<Hlist![Changed<TransformComponent>, &TransformComponent]>()
+            .map(|(entity, hlist_pat![_, &transform])| (entity, transform))
+            .collect::<Vec<_>>();
+
+        // Removals can't be detected by a query, so the world sends events for them.
+        let removed = world
+            .read_events(&mut self.deleted)
+            .map(|&EntityDeleted(entity)| entity)
+            .chain(world.read_events(&mut self.removed).map(|event| event.entity))
+            .collect::<Vec<_>>();
+
+        let index = match world.resource_mut::<SpatialIndex>() {
+            Some(index) => index,
+            None => return,
+        };
+        for entity in removed {
+            index.remove(entity);
+        }
+        for (entity, transform) in moved {
+            index.insert(entity, transform.position(), transform.radius());
         }
     }
 }
@@ -104,22 +622,16 @@ impl System for SnagSystem {
 /// A system that makes keys spin.
 pub struct SpinningKeySystem;
 
-impl System for SpinningKeySystem {
-    fn step(&mut self, state: &mut State, dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
-        };
-
-        let to_spin = world
-            .iter()
-            .map(|(entity, hlist_pat![&KeyComponent {..}])| entity)
-            .collect::<SmallVec<[_; 8]>>();
+impl ParallelSystem for SpinningKeySystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access.read::<KeyComponent>().write::<LocationComponent>();
+    }
 
-        for entity in to_spin {
-            if let Some(LocationComponent { rotation, .. }) = world.get_mut(entity) {
-                rotation[1] += dt as f32 / 5.0;
-            }
+    fn step(&mut self, world: &mut WorldView, dt: u64) {
+        for (_, hlist_pat![&KeyComponent { .. }, loc]) in
+            world.iter_mut::<Hlist![&KeyComponent, &mut LocationComponent]>()
+        {
+            loc.rotation[1] += dt as f32 / 5.0;
         }
     }
 }
@@ -127,39 +639,230 @@ impl System for SpinningKeySystem {
 /// A system that destroys entities who have positions less than `y=-1`.
 pub struct TheFloorIsLavaSystem;
 
-impl System for TheFloorIsLavaSystem {
-    fn step(&mut self, state: &mut State, _dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
-        };
+impl ParallelSystem for TheFloorIsLavaSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access.read::<TransformComponent>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        for (entity, hlist_pat![transform]) in world.iter::<Hlist![&TransformComponent]>() {
+            if transform.position().y < -1.0 {
+                world.commands().delete(entity);
+            }
+        }
+    }
+}
+
+/// A system that computes the `TransformComponent` of each entity with a `LocationComponent`,
+/// taking its ancestors' locations into account. Entities without a `TransformComponent` are
+/// given one. Only the entities whose location or parent changed since the last step, and their
+/// descendants, are recomputed.
+#[derive(Default)]
+pub struct TransformSystem {
+    unparented: EventReader<ComponentRemoved<ParentComponent>>,
+    unlocated: EventReader<ComponentRemoved<LocationComponent>>,
+}
 
-        let mut to_delete = SmallVec::<[_; 4]>::new();
-        for (entity, hlist_pat![LocationComponent{xyz,..}]) in world.iter() {
-            if xyz[1] < -1.0 {
-                to_delete.push(entity);
+impl ParallelSystem for TransformSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<LocationComponent>()
+            .read::<NameComponent>()
+            .read::<ParentComponent>()
+            .write::<TransformComponent>()
+            .read_resource::<Events<ComponentRemoved<ParentComponent>>>()
+            .read_resource::<Events<ComponentRemoved<LocationComponent>>>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        let mut dirty = world
+            .iter::<Hlist![Changed<LocationComponent>]>()
+            .map(|(entity, _)| entity)
+            .chain(
+                world
+                    .iter::<Hlist![Changed<ParentComponent>]>()
+                    .map(|(entity, _)| entity),
+            )
+            .chain(
+                world
+                    .iter::<Hlist![With<LocationComponent>, Without<TransformComponent>]>()
+                    .map(|(entity, _)| entity),
+            )
+            .chain(world.read_events(&mut self.unparented).map(|e| e.entity))
+            .chain(world.read_events(&mut self.unlocated).map(|e| e.entity))
+            .collect::<Vec<_>>();
+        if dirty.is_empty() {
+            return;
+        }
+
+        // Moving an entity moves its descendants too.
+        let mut children = HashMap::<Entity, Vec<Entity>>::new();
+        for (child, hlist_pat![&ParentComponent(parent)]) in
+            world.iter::<Hlist![&ParentComponent]>()
+        {
+            children.entry(parent).or_default().push(child);
+        }
+        let (mut seen, mut moved) = (HashSet::new(), Vec::new());
+        while let Some(entity) = dirty.pop() {
+            if seen.insert(entity) {
+                moved.push(entity);
+                dirty.extend(children.get(&entity).into_iter().flatten().cloned());
             }
         }
-        for entity in to_delete {
-            world.delete_entity(entity);
+
+        let mut matrices = HashMap::new();
+        let mut cycles = Vec::new();
+        for &entity in &moved {
+            let cycle = world_matrix(
+                entity,
+                &mut matrices,
+                |entity| {
+                    world
+                        .get::<Hlist![&ParentComponent]>(entity)
+                        .map(|hlist_pat![&ParentComponent(parent)]| parent)
+                },
+                |entity| {
+                    world
+                        .get::<Hlist![&LocationComponent]>(entity)
+                        .map(|hlist_pat![&loc]| loc)
+                },
+            );
+            cycles.extend(cycle);
+        }
+
+        // A cycle can only be made by inserting a ParentComponent without `World::set_parent`.
+        // Break it, so that it's only warned about once.
+        for entity in cycles {
+            warn!("{} is its own ancestor; removing its parent", world.named(entity));
+            world.commands().remove::<ParentComponent>(entity);
+        }
+
+        // Only borrow the transforms that moved mutably, so that unmoved ones aren't marked as
+        // changed.
+        for entity in moved {
+            let matrix = match matrices.get(&entity) {
+                Some(&matrix) => matrix,
+                None => continue,
+            };
+            match world.get::<Hlist![With<LocationComponent>, &TransformComponent]>(entity) {
+                Some(hlist_pat![_, transform]) if transform.matrix == matrix => {}
+                Some(_) => {
+                    if let Some(hlist_pat![transform]) =
+                        world.get_mut::<Hlist![&mut TransformComponent]>(entity)
+                    {
+                        transform.matrix = matrix;
+                    }
+                }
+                None => {
+                    if world.get::<Hlist![With<LocationComponent>]>(entity).is_some() {
+                        world
+                            .commands()
+                            .insert(entity, TransformComponent { matrix });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Gives every entity with a `LocationComponent` its `TransformComponent`, and fills the world's
+/// `SpatialIndex` from them, as `TransformSystem` and `SpatialIndexSystem` would over the first
+/// steps. This should be done whenever a world is built or loaded, so that the systems that run
+/// before those in a step don't start out with an empty index.
+pub fn seed_transforms(world: &mut World) {
+    let entities = world
+        .iter::<Hlist![With<LocationComponent>]>()
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+
+    // Any cycles are left for `TransformSystem` to warn about and break.
+    let mut matrices = HashMap::new();
+    for &entity in &entities {
+        world_matrix(
+            entity,
+            &mut matrices,
+            |entity| world.get_one::<ParentComponent>(entity).map(|parent| parent.0),
+            |entity| world.get_one::<LocationComponent>(entity).cloned(),
+        );
+    }
+
+    let mut index = SpatialIndex::default();
+    for entity in entities {
+        let transform = TransformComponent {
+            matrix: matrices[&entity],
+        };
+        index.insert(entity, transform.position(), transform.radius());
+        world.insert(entity, transform);
+    }
+    world.insert_resource(index);
+}
+
+/// Computes the world matrix of an entity, and of each of its ancestors not already in
+/// `matrices`, storing them there. The hierarchy and the locations are looked up with the given
+/// functions, so that callers can choose which locations to use.
+///
+/// If the entity's ancestors form a cycle, the last of them reached is treated as having no
+/// parent, and is returned.
+pub(crate) fn world_matrix(
+    entity: Entity,
+    matrices: &mut HashMap<Entity, Matrix4<f32>>,
+    parent: impl Fn(Entity) -> Option<Entity>,
+    location: impl Fn(Entity) -> Option<LocationComponent>,
+) -> Option<Entity> {
+    // Find the ancestors whose matrices aren't known yet.
+    let mut chain = Vec::new();
+    let mut matrix = Matrix4::identity();
+    let mut cycle = None;
+    let mut current = Some(entity);
+    while let Some(entity) = current {
+        if let Some(&known) = matrices.get(&entity) {
+            matrix = known;
+            break;
+        } else if chain.contains(&entity) {
+            cycle = chain.last().cloned();
+            break;
+        }
+        chain.push(entity);
+        current = parent(entity);
+    }
+
+    for entity in chain.into_iter().rev() {
+        if let Some(loc) = location(entity) {
+            matrix = matrix * loc.model();
         }
+        matrices.insert(entity, matrix);
     }
+    cycle
 }
 
 /// A system that allows for the unlocking of doors with their corresponding keys.
 pub struct UnlockSystem;
 
-impl System for UnlockSystem {
-    fn step(&mut self, state: &mut State, _dt: u64) {
-        let world = match state {
-            State::Playing(ref mut world) | State::Done(ref mut world, _) => world,
-            _ => return,
+impl ParallelSystem for UnlockSystem {
+    fn access(&self, access: &mut SystemAccess) {
+        access
+            .read::<DoorComponent>()
+            .read::<KeyComponent>()
+            .read::<NameComponent>()
+            .read::<TransformComponent>()
+            .read_resource::<SpatialIndex>();
+    }
+
+    fn step(&mut self, world: &mut WorldView, _dt: u64) {
+        let index = match world.resource::<SpatialIndex>() {
+            Some(index) => index,
+            None => return,
         };
 
-        let mut unlocks = SmallVec::<[_; 2]>::new();
-        for (door, hlist_pat![&DoorComponent(door_letter), door_loc]) in world.iter() {
-            for (key, hlist_pat![&KeyComponent{letter: key_letter,..}, key_loc]) in world.iter() {
-                if !LocationComponent::collides(door_loc, key_loc) {
+        let doors = world.iter::<Hlist![&DoorComponent, &TransformComponent]>();
+        for (door, hlist_pat![&DoorComponent(door_letter), door_loc]) in doors {
+            for key in index.query_radius(door_loc.position(), door_loc.radius()) {
+                let (key_letter, key_loc) =
+                    match world.get::<Hlist![&KeyComponent, &TransformComponent]>(key) {
+                        Some(hlist_pat![&KeyComponent { letter }, loc]) => (letter, loc),
+                        None => continue,
+                    };
+                if !door_loc.collides(key_loc) {
                     continue;
                 }
                 let diff = (key_letter as u32).wrapping_sub(door_letter as u32);
@@ -167,15 +870,13 @@ impl System for UnlockSystem {
                     continue;
                 }
 
-                unlocks.push((door, key));
-            }
-        }
-
-        for (door, key) in unlocks {
-            info!("{} was unlocked with {}!", door, key);
-            world.delete_entity(key);
-            if let Some(CollisionComponent(ref mut active)) = world.get_mut(door) {
-                *active = false;
+                info!("{} was unlocked with {}!", world.named(door), world.named(key));
+                world.commands().delete(key);
+                world.commands().remove::<CollisionComponent>(door);
+                world.commands().send_event(DoorUnlocked {
+                    door,
+                    letter: door_letter,
+                });
             }
         }
     }
@@ -188,33 +889,34 @@ impl System for WinSystem {
     fn step(&mut self, state: &mut State, dt: u64) {
         let won = match state {
             State::Playing(ref mut world) => {
-                let camera: LocationComponent = match world.iter().next() {
-                    Some((_, hlist_pat![CameraComponent, loc])) => *loc,
-                    None => {
-                        warn!("No camera?");
+                let camera = world.single::<Hlist![With<CameraComponent>, &LocationComponent]>();
+                let (camera, camera_loc) = match camera {
+                    Ok((camera, hlist_pat![_, &loc])) => (camera, loc),
+                    Err(err) => {
+                        warn!("Couldn't find the camera: {}", err);
                         return;
                     }
                 };
+                let radius = match world.get::<Hlist![&ControllerComponent]>(camera) {
+                    Some(hlist_pat![controller]) => controller.radius,
+                    None => camera_loc.radius(),
+                };
 
-                let goal = world
-                    .iter()
-                    .filter(|(_, hlist_pat![GoalComponent, loc])| camera.collides(loc))
-                    .map(|(entity, _)| entity)
-                    .next();
+                let nearby = match world.resource::<SpatialIndex>() {
+                    Some(index) => index.query_radius(camera_loc.xyz, radius),
+                    None => return,
+                };
+                let goal = nearby.into_iter().find(|&entity| {
+                    match world.get::<Hlist![&GoalComponent, &LocationComponent]>(entity) {
+                        Some(hlist_pat![_, loc]) => touches(&camera_loc, radius, loc),
+                        None => false,
+                    }
+                });
 
                 if let Some(goal) = goal {
                     info!("Player won the game!");
                     world.delete_entity(goal);
-
-                    let decal = match world.iter().next() {
-                        Some((entity, hlist_pat![DecalComponent{..}])) => entity,
-                        None => {
-                            warn!("No win decal?");
-                            return;
-                        }
-                    };
-                    world.get_mut::<DecalComponent>(decal).unwrap().enabled = true;
-
+                    world.send_event(GoalReached { goal });
                     true
                 } else {
                     false
@@ -224,7 +926,7 @@ impl System for WinSystem {
                 *t += dt;
                 false
             }
-            State::Close => false,
+            State::Lost(_, _) | State::Close => false,
         };
 
         if won {
@@ -0,0 +1,99 @@
+//! Running the simulation in steps of a fixed length, independent of the frame rate.
+
+use std::time::Duration;
+
+/// The length of a simulation step, in milliseconds, i.e. the simulation runs at 125 Hz.
+pub const STEP_MILLIS: u64 = 8;
+
+/// The most steps to run in a single frame. If rendering falls further behind than this, the
+/// simulation slows down instead of spending ever longer catching up.
+pub const MAX_STEPS_PER_FRAME: u32 = 10;
+
+/// An accumulator of real time, which decides how many simulation steps each frame should run.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # use game::timestep::FixedTimestep;
+/// # use std::time::Duration;
+/// # fn main() {
+/// let mut timestep = FixedTimestep::new(Duration::from_millis(10), 3);
+/// assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+/// assert_eq!(timestep.alpha(), 0.5);
+///
+/// // A frame too long to catch up on only runs as many steps as are allowed.
+/// assert_eq!(timestep.advance(Duration::from_secs(1)), 3);
+/// assert_eq!(timestep.alpha(), 0.0);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32,
+    accumulated: Duration,
+}
+
+impl FixedTimestep {
+    /// Creates a timestep with the given step length, which runs at most `max_steps` steps per
+    /// frame.
+    pub fn new(step: Duration, max_steps: u32) -> FixedTimestep {
+        assert!(
+            step > Duration::from_millis(0),
+            "The step length must be positive"
+        );
+        FixedTimestep {
+            step,
+            max_steps,
+            accumulated: Duration::from_millis(0),
+        }
+    }
+
+    /// Returns the length of a step.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Returns the length of a step, in milliseconds, as systems expect.
+    pub fn step_millis(&self) -> u64 {
+        self.step.as_secs() * 1000 + u64::from(self.step.subsec_millis())
+    }
+
+    /// Adds the real time that elapsed during the last frame, returning the number of steps to
+    /// run. Time beyond the most steps allowed in a frame is dropped.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulated += elapsed;
+        let mut steps = 0;
+        while self.accumulated >= self.step {
+            if steps == self.max_steps {
+                warn!(
+                    "Simulation is running behind; dropping {:?}",
+                    self.accumulated
+                );
+                self.accumulated = Duration::from_millis(0);
+                break;
+            }
+            self.accumulated -= self.step;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Returns how far from the last step to the next one the accumulated time is, from `0.0` to
+    /// `1.0`. Rendering interpolates between the last two steps by this much.
+    pub fn alpha(&self) -> f32 {
+        duration_secs(self.accumulated) / duration_secs(self.step)
+    }
+}
+
+impl Default for FixedTimestep {
+    /// Creates a timestep with steps of `STEP_MILLIS`, running at most `MAX_STEPS_PER_FRAME`
+    /// steps per frame.
+    fn default() -> FixedTimestep {
+        FixedTimestep::new(Duration::from_millis(STEP_MILLIS), MAX_STEPS_PER_FRAME)
+    }
+}
+
+fn duration_secs(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1e9
+}
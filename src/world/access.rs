@@ -0,0 +1,154 @@
+//! Tracking of which components and resources a query or system reads and writes.
+
+use crate::world::{Component, Resource};
+use std::any::{type_name, TypeId};
+
+/// The set of components read and written by a query.
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+    reads: Vec<(TypeId, &'static str)>,
+    writes: Vec<(TypeId, &'static str)>,
+}
+
+impl Access {
+    /// Records that the component is read.
+    pub fn read<T: Component>(&mut self) {
+        self.reads.push((TypeId::of::<T>(), type_name::<T>()));
+    }
+
+    /// Records that the component is written.
+    pub fn write<T: Component>(&mut self) {
+        self.writes.push((TypeId::of::<T>(), type_name::<T>()));
+    }
+
+    /// Panics if any component is written while also being read or written elsewhere in the
+    /// same query, since that would create aliasing mutable references.
+    pub fn assert_no_aliasing(&self) {
+        for (i, &(ty, name)) in self.writes.iter().enumerate() {
+            let written_twice = self.writes[i + 1..].iter().any(|&(other, _)| other == ty);
+            let also_read = self.reads.iter().any(|&(other, _)| other == ty);
+            if written_twice || also_read {
+                panic!(
+                    "Query accesses {} mutably, but also accesses it elsewhere in the same query",
+                    name
+                );
+            }
+        }
+    }
+
+    /// Panics if this access reads or writes anything that `declared` doesn't allow. Writing
+    /// allows reading.
+    pub(crate) fn assert_declared_in(&self, declared: &Access, system: &str) {
+        for &(ty, name) in &self.reads {
+            if !declared.reads(ty) && !declared.writes(ty) {
+                panic!("System {} reads {}, but doesn't declare it", system, name);
+            }
+        }
+        for &(ty, name) in &self.writes {
+            if !declared.writes(ty) {
+                panic!("System {} writes {}, but doesn't declare it", system, name);
+            }
+        }
+    }
+
+    /// Returns the name of a type written by both this and `other`, if there is one.
+    pub(crate) fn write_conflict(&self, other: &Access) -> Option<&'static str> {
+        self.writes
+            .iter()
+            .find(|&&(ty, _)| other.writes(ty))
+            .map(|&(_, name)| name)
+    }
+
+    /// Returns the name of a type written by one of this and `other` and read by the other, if
+    /// there is one.
+    pub(crate) fn read_write_conflict(&self, other: &Access) -> Option<&'static str> {
+        let read_written = |a: &Access, b: &Access| {
+            a.reads
+                .iter()
+                .find(|&&(ty, _)| b.writes(ty))
+                .map(|&(_, name)| name)
+        };
+        read_written(self, other).or_else(|| read_written(other, self))
+    }
+
+    fn reads(&self, ty: TypeId) -> bool {
+        self.reads.iter().any(|&(other, _)| other == ty)
+    }
+
+    fn writes(&self, ty: TypeId) -> bool {
+        self.writes.iter().any(|&(other, _)| other == ty)
+    }
+}
+
+/// The components and resources a system reads and writes, as declared by
+/// `ParallelSystem::access`.
+#[derive(Clone, Debug, Default)]
+pub struct SystemAccess {
+    pub(crate) components: Access,
+    pub(crate) resources: Access,
+}
+
+impl SystemAccess {
+    /// Declares that the component is read.
+    pub fn read<T: Component>(&mut self) -> &mut SystemAccess {
+        self.components.read::<T>();
+        self
+    }
+
+    /// Declares that the component is written. This allows reading it, too.
+    pub fn write<T: Component>(&mut self) -> &mut SystemAccess {
+        self.components.write::<T>();
+        self
+    }
+
+    /// Declares that the resource is read.
+    pub fn read_resource<T: Resource>(&mut self) -> &mut SystemAccess {
+        self.resources
+            .reads
+            .push((TypeId::of::<T>(), type_name::<T>()));
+        self
+    }
+
+    /// Declares that the resource is written. This allows reading it, too.
+    pub fn write_resource<T: Resource>(&mut self) -> &mut SystemAccess {
+        self.resources
+            .writes
+            .push((TypeId::of::<T>(), type_name::<T>()));
+        self
+    }
+
+    /// Panics if the resource isn't declared, or if `write` is set and it isn't declared as
+    /// written.
+    pub(crate) fn assert_resource_declared<T: Resource>(&self, write: bool, system: &str) {
+        let ty = TypeId::of::<T>();
+        if write && !self.resources.writes(ty) {
+            panic!(
+                "System {} writes {}, but doesn't declare it",
+                system,
+                type_name::<T>()
+            );
+        } else if !self.resources.reads(ty) && !self.resources.writes(ty) {
+            panic!(
+                "System {} reads {}, but doesn't declare it",
+                system,
+                type_name::<T>()
+            );
+        }
+    }
+
+    /// Returns the name of a component or resource written by both this and `other`, if there is
+    /// one.
+    pub(crate) fn write_conflict(&self, other: &SystemAccess) -> Option<&'static str> {
+        self.components
+            .write_conflict(&other.components)
+            .or_else(|| self.resources.write_conflict(&other.resources))
+    }
+
+    /// Returns the name of a component or resource written by one of this and `other` and read
+    /// by the other, if there is one.
+    pub(crate) fn read_write_conflict(&self, other: &SystemAccess) -> Option<&'static str> {
+        self.components
+            .read_write_conflict(&other.components)
+            .or_else(|| self.resources.read_write_conflict(&other.resources))
+    }
+}
@@ -0,0 +1,276 @@
+//! Columnar storage for entities sharing the same set of components.
+
+use crate::{
+    world::{ColumnPtr, Component},
+    Entity,
+};
+use std::{
+    any::{Any, TypeId},
+    cell::UnsafeCell,
+};
+
+/// A table of entities that all have exactly the same set of component types. Each component
+/// type is stored contiguously in its own column, so row `i` of every column belongs to
+/// `entities[i]`. Rows are kept in the order their entities were spawned.
+pub struct Archetype {
+    types: Vec<TypeId>,
+    columns: Vec<UnsafeCell<ColumnData>>,
+    entities: Vec<Entity>,
+    spawned: Vec<u64>,
+}
+
+// Columns are only mutated through a shared reference by queries, which check that they don't
+// alias, and only while the `World` is borrowed mutably.
+unsafe impl Sync for Archetype {}
+
+/// A column, along with the tick at which each of its rows was last changed.
+struct ColumnData {
+    components: Box<dyn Column>,
+    changed: Vec<u64>,
+}
+
+impl Archetype {
+    /// Creates an empty archetype with the given columns. The columns may be in any order.
+    pub(crate) fn new(mut columns: Vec<(TypeId, Box<dyn Column>)>) -> Archetype {
+        columns.sort_by_key(|&(ty, _)| ty);
+        columns.dedup_by_key(|&mut (ty, _)| ty);
+        let (types, columns): (_, Vec<_>) = columns.into_iter().unzip();
+        Archetype {
+            types,
+            columns: columns
+                .into_iter()
+                .map(|components| {
+                    UnsafeCell::new(ColumnData {
+                        components,
+                        changed: Vec::new(),
+                    })
+                }).collect(),
+            entities: Vec::new(),
+            spawned: Vec::new(),
+        }
+    }
+
+    /// Returns the (sorted) component types stored in this archetype.
+    pub fn types(&self) -> &[TypeId] {
+        &self.types
+    }
+
+    /// Returns the entities stored in this archetype, in row order.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    /// Returns when each row's entity was spawned, relative to other entities. This is sorted.
+    pub(crate) fn spawned(&self) -> &[u64] {
+        &self.spawned
+    }
+
+    /// Returns whether this archetype stores the given component type.
+    pub fn has<T: Component>(&self) -> bool {
+        self.index_of(TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns the column of the given component type, if this archetype has one.
+    pub fn column<T: Component>(&self) -> Option<&[T]> {
+        let idx = self.index_of(TypeId::of::<T>())?;
+        let column = unsafe { &*self.columns[idx].get() };
+        let column = column.components.as_any().downcast_ref::<Vec<T>>();
+        Some(column.expect("Archetype column had the wrong type").as_slice())
+    }
+
+    /// Returns the ticks at which each row of the given component type's column was last
+    /// changed, if this archetype has the column.
+    pub fn changed<T: Component>(&self) -> Option<&[u64]> {
+        let idx = self.index_of(TypeId::of::<T>())?;
+        let column = unsafe { &*self.columns[idx].get() };
+        Some(&column.changed)
+    }
+
+    /// Returns pointers to the start of the column of the given component type and to the start
+    /// of its change ticks, if this archetype has the column. Writing through the pointers is
+    /// only sound while no other reference to the column is live.
+    pub(crate) fn column_ptr<T: Component>(&self) -> Option<(ColumnPtr<T>, ColumnPtr<u64>)> {
+        let idx = self.index_of(TypeId::of::<T>())?;
+        let column = unsafe { &mut *self.columns[idx].get() };
+        let components = column.components.as_any_mut().downcast_mut::<Vec<T>>();
+        Some((
+            ColumnPtr(
+                components
+                    .expect("Archetype column had the wrong type")
+                    .as_mut_ptr(),
+            ),
+            ColumnPtr(column.changed.as_mut_ptr()),
+        ))
+    }
+
+    /// Returns the column of the given component type mutably, if this archetype has one. This
+    /// doesn't update the change ticks; use `set_changed` for that.
+    pub(crate) fn column_mut<T: Component>(&mut self) -> Option<&mut Vec<T>> {
+        let idx = self.index_of(TypeId::of::<T>())?;
+        let column = self.columns[idx].get_mut();
+        let components = column.components.as_any_mut().downcast_mut::<Vec<T>>();
+        Some(components.expect("Archetype column had the wrong type"))
+    }
+
+    /// Records that the given row of the given component type's column was changed at the given
+    /// tick. The row must either already have a tick, or be the next row to be added.
+    pub(crate) fn set_changed(&mut self, ty: TypeId, row: usize, tick: u64) {
+        let idx = self
+            .index_of(ty)
+            .expect("Archetype is missing a component column");
+        let changed = &mut self.columns[idx].get_mut().changed;
+        if row < changed.len() {
+            changed[row] = tick;
+        } else {
+            changed.push(tick);
+        }
+    }
+
+    /// Returns empty columns of the same types as this archetype's, except for the given type.
+    pub(crate) fn empty_columns_except(
+        &self,
+        except: Option<TypeId>,
+    ) -> Vec<(TypeId, Box<dyn Column>)> {
+        self.types
+            .iter()
+            .zip(&self.columns)
+            .filter(|&(&ty, _)| Some(ty) != except)
+            .map(|(&ty, column)| (ty, unsafe { &*column.get() }.components.new_empty()))
+            .collect()
+    }
+
+    /// Moves the given row to the end of `dst`, removing it from this archetype and shifting the
+    /// rows after it back by one. Components that `dst` has no column for are moved into
+    /// `removed` if it is given, and dropped otherwise. Returns the row in `dst`, which is out of
+    /// spawn order until `settle_last_row` is called on `dst`.
+    pub(crate) fn move_row(
+        &mut self,
+        row: usize,
+        dst: &mut Archetype,
+        mut removed: Option<&mut dyn Column>,
+    ) -> usize {
+        for (ty, column) in self.types.iter().zip(&mut self.columns) {
+            let column = column.get_mut();
+            let changed = column.changed.remove(row);
+            match dst.index_of(*ty) {
+                Some(idx) => {
+                    let dst_column = dst.columns[idx].get_mut();
+                    column.components.move_row(row, &mut *dst_column.components);
+                    dst_column.changed.push(changed);
+                }
+                None => match removed {
+                    Some(ref mut removed) => column.components.move_row(row, &mut **removed),
+                    None => column.components.remove(row),
+                },
+            }
+        }
+
+        let entity = self.entities.remove(row);
+        let spawned = self.spawned.remove(row);
+        dst.push_entity(entity, spawned)
+    }
+
+    /// Moves the last row back to its place in spawn order, shifting the rows after that place
+    /// along by one. Returns its new row.
+    pub(crate) fn settle_last_row(&mut self) -> usize {
+        let last = self.entities.len() - 1;
+        let row = match self.spawned[..last].binary_search(&self.spawned[last]) {
+            Ok(row) | Err(row) => row,
+        };
+        if row < last {
+            for column in &mut self.columns {
+                let column = column.get_mut();
+                column.components.move_last_to(row);
+                column.changed[row..].rotate_right(1);
+            }
+            self.entities[row..].rotate_right(1);
+            self.spawned[row..].rotate_right(1);
+        }
+        row
+    }
+
+    /// Moves the first component of `src` onto the end of the given component type's column, as
+    /// the given row, marking it as changed at the given tick. The row must be the last row.
+    pub(crate) fn push_from(&mut self, ty: TypeId, src: &mut dyn Column, row: usize, tick: u64) {
+        let idx = self
+            .index_of(ty)
+            .expect("Archetype is missing a component column");
+        src.move_row(0, &mut *self.columns[idx].get_mut().components);
+        self.set_changed(ty, row, tick);
+    }
+
+    /// Adds an entity, spawned at the given time, to the end of the entity list, returning its
+    /// row. The caller must push exactly one value onto every column afterwards, and then call
+    /// `settle_last_row` unless the entity was spawned after every other one in the archetype.
+    pub(crate) fn push_entity(&mut self, entity: Entity, spawned: u64) -> usize {
+        self.entities.push(entity);
+        self.spawned.push(spawned);
+        self.entities.len() - 1
+    }
+
+    /// Removes the given row, shifting the rows after it back by one.
+    pub(crate) fn remove(&mut self, row: usize) {
+        for column in &mut self.columns {
+            let column = column.get_mut();
+            column.components.remove(row);
+            column.changed.remove(row);
+        }
+        self.entities.remove(row);
+        self.spawned.remove(row);
+    }
+
+    fn index_of(&self, ty: TypeId) -> Option<usize> {
+        self.types.binary_search(&ty).ok()
+    }
+}
+
+/// A type-erased column of components.
+pub trait Column: Any + Send + Sync {
+    /// Upcasts to `Any`, for downcasting to the concrete `Vec`.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Upcasts to `Any` mutably, for downcasting to the concrete `Vec`.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Returns an empty column of the same type.
+    fn new_empty(&self) -> Box<dyn Column>;
+
+    /// Removes the given row, shifting the rows after it back by one.
+    fn remove(&mut self, row: usize);
+
+    /// Removes the given row as with `remove`, pushing it onto the end of `dst`. Panics if `dst`
+    /// is a column of a different type.
+    fn move_row(&mut self, row: usize, dst: &mut dyn Column);
+
+    /// Moves the last row to the given row, shifting the rows from there on along by one.
+    fn move_last_to(&mut self, row: usize);
+}
+
+impl<T: Component> Column for Vec<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn new_empty(&self) -> Box<dyn Column> {
+        Box::new(Vec::<T>::new())
+    }
+
+    fn remove(&mut self, row: usize) {
+        Vec::remove(self, row);
+    }
+
+    fn move_row(&mut self, row: usize, dst: &mut dyn Column) {
+        dst.as_any_mut()
+            .downcast_mut::<Vec<T>>()
+            .expect("Tried to move a component into a column of a different type")
+            .push(Vec::remove(self, row));
+    }
+
+    fn move_last_to(&mut self, row: usize) {
+        self[row..].rotate_right(1);
+    }
+}
@@ -0,0 +1,90 @@
+//! Sets of components whose types are only known at runtime.
+
+use crate::world::{Archetype, Column, Component};
+use std::any::TypeId;
+
+/// A set of components, at most one of each type, which can be put together one at a time and
+/// then spawned as an entity with `World::new_entity_from_bundle`. Unlike spawning an entity and
+/// inserting each component, this puts the entity straight into the archetype it ends up in.
+///
+/// # Example
+///
+/// ```
+/// # extern crate game;
+/// # #[macro_use] extern crate typemap;
+/// # use game::{ComponentBundle, World};
+/// # fn main() {
+/// #[derive(Debug, PartialEq)]
+/// struct FooComponent(usize);
+/// impl typemap::Key for FooComponent { type Value = FooComponent; }
+///
+/// #[derive(Debug, PartialEq)]
+/// struct BarComponent;
+/// impl typemap::Key for BarComponent { type Value = BarComponent; }
+///
+/// let mut bundle = ComponentBundle::new();
+/// bundle.insert(FooComponent(1));
+/// bundle.insert(BarComponent);
+/// bundle.insert(FooComponent(2));
+/// assert_eq!(bundle.len(), 2);
+///
+/// let mut world = World::default();
+/// let me = world.new_entity_from_bundle(bundle);
+/// assert_eq!(world.get_one::<FooComponent>(me), Some(&FooComponent(2)));
+/// assert_eq!(world.get_one::<BarComponent>(me), Some(&BarComponent));
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ComponentBundle {
+    /// A column holding exactly one component for each type, sorted by type.
+    columns: Vec<(TypeId, Box<dyn Column>)>,
+}
+
+impl ComponentBundle {
+    /// Creates an empty bundle.
+    pub fn new() -> ComponentBundle {
+        ComponentBundle::default()
+    }
+
+    /// Adds a component to the bundle, replacing any component of the same type.
+    pub fn insert<T: Component>(&mut self, component: T) {
+        let ty = TypeId::of::<T>();
+        let column: Box<dyn Column> = Box::new(vec![component]);
+        match self.columns.binary_search_by_key(&ty, |&(ty, _)| ty) {
+            Ok(idx) => self.columns[idx].1 = column,
+            Err(idx) => self.columns.insert(idx, (ty, column)),
+        }
+    }
+
+    /// Returns the number of components in the bundle.
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Returns whether the bundle has no components.
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Returns the (sorted) component types in the bundle.
+    pub(crate) fn types(&self) -> Vec<TypeId> {
+        self.columns.iter().map(|&(ty, _)| ty).collect()
+    }
+
+    /// Returns empty columns of the same types as the bundle's components.
+    pub(crate) fn empty_columns(&self) -> Vec<(TypeId, Box<dyn Column>)> {
+        self.columns
+            .iter()
+            .map(|&(ty, ref column)| (ty, column.new_empty()))
+            .collect()
+    }
+
+    /// Pushes the components onto the given row of an archetype with exactly the bundle's
+    /// component types, marking them as changed at the given tick. The row must be the last row
+    /// of the archetype.
+    pub(crate) fn push_into_archetype(self, archetype: &mut Archetype, row: usize, tick: u64) {
+        for (ty, mut column) in self.columns {
+            archetype.push_from(ty, &mut *column, row, tick);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Deferred structural changes to a `World`.
+
+use crate::{
+    world::{Component, ComponentHList, Resource},
+    Entity, World,
+};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    mem::take,
+    sync::Mutex,
+};
+
+/// A buffer of structural changes (spawning, deleting, and adding or removing components) and
+/// events to apply to a `World` later. Since commands are recorded through a shared reference,
+/// they can be recorded while iterating over the world, including from a parallel iterator.
+///
+/// Commands are applied by `World::apply_commands`. Commands targeting an existing entity are
+/// applied first, ordered by entity; commands for the same entity are applied in the order they
+/// were recorded. Spawns and events are applied last, in the order they were recorded.
+#[derive(Default)]
+pub struct Commands {
+    queue: Mutex<Vec<Command>>,
+}
+
+impl Commands {
+    /// Records the creation of an entity with the given components.
+    pub fn spawn<C: 'static + ComponentHList + Send>(&self, components: C) {
+        self.push(Command::Spawn(Box::new(move |world| {
+            world.new_entity(components);
+        })));
+    }
+
+    /// Records the deletion of an entity.
+    pub fn delete(&self, entity: Entity) {
+        self.push(Command::Delete(entity));
+    }
+
+    /// Records adding a component to an entity, replacing any component of the same type.
+    pub fn insert<T: Component>(&self, entity: Entity, component: T) {
+        self.push(Command::Modify(
+            entity,
+            "insert a component into",
+            Box::new(move |world, entity| {
+                world.insert(entity, component);
+            }),
+        ));
+    }
+
+    /// Records adding several components to an entity, replacing any components of the same
+    /// types.
+    pub fn insert_all<C: 'static + ComponentHList + Send>(&self, entity: Entity, components: C) {
+        self.push(Command::Modify(
+            entity,
+            "insert components into",
+            Box::new(move |world, entity| {
+                world.insert_all(entity, components);
+            }),
+        ));
+    }
+
+    /// Records removing a component from an entity.
+    pub fn remove<T: Component>(&self, entity: Entity) {
+        self.push(Command::Modify(
+            entity,
+            "remove a component from",
+            Box::new(|world, entity| {
+                world.remove::<T>(entity);
+            }),
+        ));
+    }
+
+    /// Records making `child`'s location relative to `parent`'s, as with `World::set_parent`. If
+    /// that fails, a warning is logged.
+    pub fn set_parent(&self, child: Entity, parent: Entity) {
+        self.push(Command::Modify(
+            child,
+            "set the parent of",
+            Box::new(move |world, child| {
+                if let Err(err) = world.set_parent(child, parent) {
+                    warn!("{}", err);
+                }
+            }),
+        ));
+    }
+
+    /// Records sending an event.
+    pub fn send_event<E: Resource>(&self, event: E) {
+        self.push(Command::SendEvent(Box::new(move |world| {
+            world.send_event(event);
+        })));
+    }
+
+    /// Returns whether no commands have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    fn push(&self, command: Command) {
+        self.queue.lock().unwrap().push(command);
+    }
+
+    /// Removes all recorded commands, in the order they should be applied.
+    pub(crate) fn take(&mut self) -> Vec<Command> {
+        let mut commands = take(self.queue.get_mut().unwrap());
+        commands.sort_by_key(|command| match *command {
+            Command::Spawn(_) | Command::SendEvent(_) => (1, None),
+            Command::Delete(entity) | Command::Modify(entity, _, _) => (0, Some(entity)),
+        });
+        commands
+    }
+}
+
+/// A single recorded command.
+pub(crate) enum Command {
+    Spawn(WorldFn),
+    Delete(Entity),
+    Modify(Entity, &'static str, ModifyFn),
+    SendEvent(WorldFn),
+}
+
+type WorldFn = Box<dyn FnOnce(&mut World) + Send>;
+type ModifyFn = Box<dyn FnOnce(&mut World, Entity) + Send>;
+
+/// A command that couldn't be applied, because the entity it targeted no longer exists. The
+/// entity is shown without its name, since its `NameComponent` went with it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StaleCommand {
+    /// The entity the command targeted.
+    pub entity: Entity,
+
+    /// A description of the command.
+    pub action: &'static str,
+}
+
+impl Display for StaleCommand {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "Couldn't {} {}, since it no longer exists",
+            self.action, self.entity
+        )
+    }
+}
@@ -0,0 +1,173 @@
+//! Double-buffered channels for events sent between systems.
+
+use crate::{
+    world::{Component, Resource, World},
+    Entity,
+};
+use std::{marker::PhantomData, mem::take};
+
+/// A channel of events of type `E`, stored as a resource in the `World`.
+///
+/// Events are kept for two steps: the step they were sent in, and the one after it. Since
+/// `World::update_events` is called once per step, a reader that reads every step sees each
+/// event exactly once, regardless of whether it runs before or after the system sending it.
+pub struct Events<E> {
+    previous: Vec<E>,
+    previous_start: usize,
+    current: Vec<E>,
+    current_start: usize,
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Events<E> {
+        Events {
+            previous: Vec::new(),
+            previous_start: 0,
+            current: Vec::new(),
+            current_start: 0,
+        }
+    }
+}
+
+impl<E> Events<E> {
+    /// Sends an event.
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+
+    /// Drops the events from the previous step, and starts a new step.
+    pub fn update(&mut self) {
+        self.previous = take(&mut self.current);
+        self.previous_start = self.current_start;
+        self.current_start += self.previous.len();
+    }
+
+    /// Returns the number of events ever sent, which is the ID of the next event.
+    fn count(&self) -> usize {
+        self.current_start + self.current.len()
+    }
+
+    /// Iterates over the events that are still stored and have an ID of at least `start`.
+    fn since(&self, start: usize) -> impl Iterator<Item = &E> {
+        let previous = start.saturating_sub(self.previous_start);
+        let current = start.saturating_sub(self.current_start);
+        self.previous
+            .iter()
+            .skip(previous)
+            .chain(self.current.iter().skip(current))
+    }
+}
+
+/// An event the `World` sends when an entity is deleted, including when it's deleted along with
+/// one of its ancestors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EntityDeleted(pub Entity);
+
+/// An event the `World` sends when a component of type `T` is removed from an entity. Deleting an
+/// entity sends `EntityDeleted` instead.
+pub struct ComponentRemoved<T> {
+    /// The entity the component was removed from.
+    pub entity: Entity,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: Component> ComponentRemoved<T> {
+    /// Creates the event for removing a `T` from the given entity.
+    pub fn new(entity: Entity) -> ComponentRemoved<T> {
+        ComponentRemoved {
+            entity,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// A cursor into an `Events` channel, which remembers which events it has already read. Systems
+/// that subscribe to an event should keep one of these between steps.
+pub struct EventReader<E> {
+    next: usize,
+    _phantom: PhantomData<fn() -> E>,
+}
+
+impl<E> Default for EventReader<E> {
+    fn default() -> EventReader<E> {
+        EventReader {
+            next: 0,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<E: Resource> EventReader<E> {
+    /// Iterates over the events of this type that this reader hasn't read yet. Events that were
+    /// sent more than a step before the last read are missed, and a warning is logged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate game;
+    /// # use game::{EventReader, World};
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct Ping(usize);
+    ///
+    /// let mut world = World::default();
+    /// let mut reader = EventReader::<Ping>::default();
+    /// assert_eq!(reader.read(&world).count(), 0);
+    ///
+    /// world.send_event(Ping(1));
+    /// world.update_events();
+    /// world.send_event(Ping(2));
+    /// assert_eq!(reader.read(&world).collect::<Vec<_>>(), vec![&Ping(1), &Ping(2)]);
+    /// assert_eq!(reader.read(&world).count(), 0);
+    ///
+    /// world.send_event(Ping(3));
+    /// world.update_events();
+    /// assert_eq!(reader.read(&world).collect::<Vec<_>>(), vec![&Ping(3)]);
+    /// # }
+    /// ```
+    pub fn read<'a>(&mut self, world: &'a World) -> impl Iterator<Item = &'a E> {
+        let events = world.resource::<Events<E>>();
+        let mut start = self.next;
+        if let Some(events) = events {
+            if start > events.count() {
+                // The reader has read events that were never sent, so the world must have been
+                // replaced, e.g. by loading a snapshot.
+                start = events.previous_start;
+            } else if start < events.previous_start {
+                warn!(
+                    "{} events were dropped before being read",
+                    events.previous_start - start
+                );
+            }
+            self.next = events.count();
+        }
+        events.into_iter().flat_map(move |events| events.since(start))
+    }
+}
+
+impl World {
+    /// Sends an event, creating the channel for the event type if necessary. To send events
+    /// while iterating over the world, use `Commands::send_event`.
+    pub fn send_event<E: Resource>(&mut self, event: E) {
+        if self.resource::<Events<E>>().is_none() {
+            self.insert_resource(Events::<E>::default());
+            self.event_updaters.push(update_events::<E>);
+        }
+        self.resource_mut::<Events<E>>().unwrap().send(event);
+    }
+
+    /// Starts a new step for every event channel. This should be called exactly once per
+    /// simulation step, before the step's systems run.
+    pub fn update_events(&mut self) {
+        for update in self.event_updaters.clone() {
+            update(self);
+        }
+    }
+}
+
+/// Starts a new step for the channel of events of type `E`.
+fn update_events<E: Resource>(world: &mut World) {
+    if let Some(events) = world.resource_mut::<Events<E>>() {
+        events.update();
+    }
+}
@@ -0,0 +1,1155 @@
+//! The entity-component store.
+
+mod access;
+mod archetype;
+mod bundle;
+mod commands;
+mod events;
+mod query;
+mod view;
+
+pub use crate::world::{
+    access::{Access, SystemAccess},
+    archetype::{Archetype, Column},
+    bundle::ComponentBundle,
+    commands::{Commands, StaleCommand},
+    events::{ComponentRemoved, EntityDeleted, EventReader, Events},
+    query::{
+        ChangeTicks, Changed, ColumnPtr, ComponentHList, ComponentQuery, ComponentRefHList, With,
+        Without,
+    },
+    view::WorldView,
+};
+use crate::{
+    components::{NameComponent, ParentComponent},
+    world::commands::Command,
+    Entity,
+};
+use failure::Fallible;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::{
+    any::{Any, TypeId},
+    cell::UnsafeCell,
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
+use typemap::Key;
+
+/// A trait for types that can be stored as components. This is implemented for every type that
+/// `impl typemap::Key<Value = Self>`, which the `impl_Component!` macro provides.
+pub trait Component: Key<Value = Self> + Send + Sync {}
+
+impl<T: Key<Value = T> + Send + Sync> Component for T {}
+
+/// A trait for types that can be stored as resources, i.e. values of which the `World` holds at
+/// most one, rather than one per entity.
+pub trait Resource: Any + Send + Sync {}
+
+impl<T: Any + Send + Sync> Resource for T {}
+
+/// The state of the game world during gameplay.
+///
+/// Entities with the same set of components are stored together in an `Archetype`, so queries
+/// only need to visit the archetypes that have every requested component.
+#[derive(Default)]
+pub struct World {
+    archetypes: Vec<Archetype>,
+    archetype_ids: HashMap<Vec<TypeId>, usize>,
+    entities: Vec<EntityMeta>,
+    free_entities: Vec<u32>,
+    next_spawn: u64,
+    commands: Commands,
+    change_tick: u64,
+    last_run_tick: Option<u64>,
+    system_ticks: HashMap<TypeId, u64>,
+    resources: HashMap<TypeId, ResourceCell>,
+    event_updaters: Vec<fn(&mut World)>,
+}
+
+/// A resource, which may be borrowed mutably by a system running in parallel with others.
+struct ResourceCell(UnsafeCell<Box<dyn Any + Send + Sync>>);
+
+// Resources are only mutated through a shared reference by systems that declare it, and the
+// scheduler never runs two such systems at once.
+unsafe impl Sync for ResourceCell {}
+
+/// The bookkeeping for a single entity index.
+#[derive(Clone, Copy, Debug)]
+struct EntityMeta {
+    /// The generation of the entity currently (or next) using this index.
+    generation: u32,
+
+    /// The archetype and row of the entity, or `None` if the index is free.
+    location: Option<(usize, usize)>,
+
+    /// When the entity currently using this index was spawned, relative to other entities.
+    spawned: u64,
+}
+
+impl World {
+    /// Tries to get the given components for a given entity.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(&'static str);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BarComponent(usize);
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BazComponent;
+    /// impl typemap::Key for BazComponent { type Value = BazComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let me = world.new_entity(hlist![FooComponent("hello"), BarComponent(42)]);
+    ///
+    /// assert_eq!(world.get(me), Some(hlist![&FooComponent("hello")]));
+    /// assert_eq!(world.get(me), Some(hlist![&BarComponent(42)]));
+    /// assert_eq!(world.get(me), Some(hlist![
+    ///     &FooComponent("hello"),
+    ///     &BarComponent(42),
+    /// ]));
+    /// assert_eq!(world.get::<Hlist![&BazComponent]>(me), None);
+    /// # }
+    /// ```
+    pub fn get<'a, C: ComponentRefHList<'a>>(&'a self, entity: Entity) -> Option<C> {
+        // Read-only queries can't alias.
+        unsafe { self.get_unchecked(entity, self.change_ticks()) }
+    }
+
+    /// Gets the components for a given entity without checking that the query doesn't alias.
+    unsafe fn get_unchecked<'a, C>(&'a self, entity: Entity, ticks: ChangeTicks) -> Option<C>
+    where
+        C: ComponentQuery<'a>,
+    {
+        let (archetype, row) = self.location(entity)?;
+        let columns = C::columns(&self.archetypes[archetype], ticks)?;
+        if C::filter(columns, row) {
+            Some(C::fetch(columns, row))
+        } else {
+            None
+        }
+    }
+
+    /// Tries to get a single component, mutably, for a given entity. The component is marked as
+    /// changed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(&'static str);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BarComponent(usize);
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let me = world.new_entity(hlist![FooComponent("hello")]);
+    ///
+    /// assert_eq!(world.get_mut(me), Some(&mut FooComponent("hello")));
+    /// assert_eq!(world.get_mut::<BarComponent>(me), None);
+    /// # }
+    /// ```
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        let (archetype, row) = self.location(entity)?;
+        let archetype = &mut self.archetypes[archetype];
+        if !archetype.has::<T>() {
+            return None;
+        }
+        archetype.set_changed(TypeId::of::<T>(), row, self.change_tick);
+        archetype.column_mut::<T>().map(|column| &mut column[row])
+    }
+
+    /// Tries to get a single component for a given entity.
+    pub fn get_one<T: Component>(&self, entity: Entity) -> Option<&T> {
+        let (archetype, row) = self.location(entity)?;
+        self.archetypes[archetype]
+            .column::<T>()
+            .map(|column| &column[row])
+    }
+
+    /// Iterates over entities which match the given query, in the order they were spawned. See
+    /// `ComponentQuery` for the filters a query may contain.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::{With, Without, World};
+    /// # fn main() {
+    /// #[derive(Debug)]
+    /// struct FooComponent;
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug)]
+    /// struct BarComponent(usize);
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// world.new_entity(hlist![FooComponent]);
+    /// world.new_entity(hlist![FooComponent, BarComponent(42)]);
+    /// for (e, hlist_pat![foo, bar]) in world.iter() {
+    ///     println!("Entity: {:?}", e);
+    ///     println!("Foo: {:?}", foo as &FooComponent);
+    ///     println!("Bar: {:?}", bar as &BarComponent);
+    /// }
+    ///
+    /// let query = world.iter::<Hlist![&FooComponent, Option<&BarComponent>]>();
+    /// assert_eq!(query.filter(|(_, hlist_pat![_, bar])| bar.is_some()).count(), 1);
+    /// assert_eq!(world.iter::<Hlist![With<FooComponent>, Without<BarComponent>]>().count(), 1);
+    ///
+    /// // Changing an entity's components doesn't change where it comes in the order.
+    /// world.new_entity(hlist![BarComponent(1)]);
+    /// let b = world.new_entity(hlist![BarComponent(2)]);
+    /// world.insert(b, FooComponent);
+    /// let order = world.iter::<Hlist![&BarComponent]>().map(|(_, hlist_pat![bar])| bar.0);
+    /// assert_eq!(order.collect::<Vec<_>>(), vec![42, 1, 2]);
+    /// # }
+    /// ```
+    pub fn iter<'a, C>(&'a self) -> impl 'a + Iterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentRefHList<'a>,
+    {
+        // Read-only queries can't alias.
+        unsafe { self.iter_unchecked(self.change_ticks()) }
+    }
+
+    /// Iterates over entities which have all the given components, in the order they were
+    /// spawned, allowing some of them to be borrowed mutably. Panics if the same component is
+    /// borrowed mutably more than once, or both mutably and immutably.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BarComponent(usize);
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let me = world.new_entity(hlist![FooComponent(2), BarComponent(21)]);
+    /// let query = world.iter_mut::<Hlist![&FooComponent, &mut BarComponent]>();
+    /// for (_, hlist_pat![foo, bar]) in query {
+    ///     bar.0 *= foo.0;
+    /// }
+    /// assert_eq!(world.get(me), Some(hlist![&BarComponent(42)]));
+    /// # }
+    /// ```
+    pub fn iter_mut<'a, C>(&'a mut self) -> impl 'a + Iterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentQuery<'a>,
+    {
+        assert_query_does_not_alias::<C>();
+        unsafe { self.iter_unchecked(self.change_ticks()) }
+    }
+
+    /// Gets the only entity which matches the given query. Returns an error if no entities or
+    /// several entities match.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::{components::NameComponent, With, World};
+    /// # fn main() {
+    /// #[derive(Debug)]
+    /// struct CameraComponent;
+    /// impl typemap::Key for CameraComponent { type Value = CameraComponent; }
+    ///
+    /// let mut world = World::default();
+    /// assert!(world.single::<Hlist![With<CameraComponent>]>().is_err());
+    ///
+    /// let camera = world.new_entity(hlist![CameraComponent, NameComponent::new("player")]);
+    /// let (entity, hlist_pat![_, name]) = world
+    ///     .single::<Hlist![With<CameraComponent>, &NameComponent]>()
+    ///     .unwrap();
+    /// assert_eq!((entity, &name.0[..]), (camera, "player"));
+    ///
+    /// world.new_entity(hlist![CameraComponent]);
+    /// assert!(world.single::<Hlist![With<CameraComponent>]>().is_err());
+    /// # }
+    /// ```
+    pub fn single<'a, C>(&'a self) -> Fallible<(Entity, C)>
+    where
+        C: 'a + ComponentRefHList<'a>,
+    {
+        expect_single(self.iter(), |entity| self.named(entity).to_string())
+    }
+
+    /// Gets the only entity which matches the given query, allowing some of its components to be
+    /// borrowed mutably. Returns an error if no entities or several entities match, and panics if
+    /// the query aliases, as with `iter_mut`.
+    pub fn single_mut<'a, C>(&'a mut self) -> Fallible<(Entity, C)>
+    where
+        C: 'a + ComponentQuery<'a>,
+    {
+        assert_query_does_not_alias::<C>();
+        let world = &*self;
+        let matches = unsafe { world.iter_unchecked(world.change_ticks()) };
+        expect_single(matches, |entity| world.named(entity).to_string())
+    }
+
+    /// Iterates over the query without checking that it doesn't alias.
+    unsafe fn iter_unchecked<'a, C>(
+        &'a self,
+        ticks: ChangeTicks,
+    ) -> impl 'a + Iterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentQuery<'a>,
+    {
+        let cursors = self
+            .archetypes
+            .iter()
+            .filter(|archetype| !archetype.entities().is_empty())
+            .filter_map(|archetype| {
+                C::columns(archetype, ticks).map(|columns| (archetype, columns, 0))
+            }).collect();
+        SpawnOrder { cursors }
+    }
+
+    /// Returns the buffer of commands to apply at the next call to `apply_commands`. Commands can
+    /// be recorded while iterating over the world, unlike direct structural changes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let a = world.new_entity(hlist![FooComponent(1)]);
+    /// let b = world.new_entity(hlist![FooComponent(2)]);
+    ///
+    /// for (entity, hlist_pat![&FooComponent(n)]) in world.iter() {
+    ///     if n == 1 {
+    ///         world.commands().delete(entity);
+    ///     } else {
+    ///         world.commands().spawn(hlist![FooComponent(n + 1)]);
+    ///     }
+    /// }
+    /// world.commands().delete(a);
+    ///
+    /// let stale = world.apply_commands();
+    /// assert_eq!(stale.len(), 1);
+    /// assert_eq!(stale[0].entity, a);
+    /// assert!(!world.is_alive(a));
+    /// assert_eq!(world.iter::<Hlist![&FooComponent]>().count(), 2);
+    /// # }
+    /// ```
+    pub fn commands(&self) -> &Commands {
+        &self.commands
+    }
+
+    /// Applies the commands recorded in `commands`, returning the ones that targeted entities
+    /// which no longer exist.
+    pub fn apply_commands(&mut self) -> Vec<StaleCommand> {
+        let mut stale = Vec::new();
+        for command in self.commands.take() {
+            match command {
+                Command::Spawn(apply) | Command::SendEvent(apply) => apply(self),
+                Command::Delete(entity) => {
+                    if !self.delete_entity(entity) {
+                        stale.push(StaleCommand {
+                            entity,
+                            action: "delete",
+                        });
+                    }
+                }
+                Command::Modify(entity, action, modify) => {
+                    if self.is_alive(entity) {
+                        modify(self, entity);
+                    } else {
+                        stale.push(StaleCommand { entity, action });
+                    }
+                }
+            }
+        }
+        stale
+    }
+
+    /// Deletes an entity and its descendants, returning whether it was alive, and sends an
+    /// `EntityDeleted` event for each. Once deleted, the entity's handle is stale, and will not
+    /// refer to any entity created afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let old = world.new_entity(hlist![FooComponent(1)]);
+    /// assert!(world.delete_entity(old));
+    /// assert!(!world.delete_entity(old));
+    ///
+    /// let new = world.new_entity(hlist![FooComponent(2)]);
+    /// assert_eq!(old.index(), new.index());
+    /// assert!(!world.is_alive(old));
+    /// assert_eq!(world.get_one::<FooComponent>(old), None);
+    /// assert_eq!(world.get_one::<FooComponent>(new), Some(&FooComponent(2)));
+    /// # }
+    /// ```
+    pub fn delete_entity(&mut self, entity: Entity) -> bool {
+        if !self.delete_one(entity) {
+            return false;
+        }
+
+        let mut deleted = vec![entity];
+        while let Some(parent) = deleted.pop() {
+            let children = self
+                .iter::<Hlist![&ParentComponent]>()
+                .filter(|&(_, hlist_pat![&ParentComponent(p)])| p == parent)
+                .map(|(child, _)| child)
+                .collect::<Vec<_>>();
+            for child in children {
+                if self.delete_one(child) {
+                    deleted.push(child);
+                }
+            }
+        }
+        true
+    }
+
+    /// Makes `child`'s location relative to `parent`'s, by giving it a `ParentComponent`.
+    /// Returns an error if either entity has been deleted, or if `child` is `parent` or one of its
+    /// ancestors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # use game::{components::LocationComponent, World};
+    /// # fn main() {
+    /// let mut world = World::default();
+    /// let player = world.new_entity(hlist![LocationComponent::default()]);
+    /// let hand = world.new_entity(hlist![LocationComponent::default()]);
+    /// let torch = world.new_entity(hlist![LocationComponent::default()]);
+    ///
+    /// world.set_parent(hand, player).unwrap();
+    /// world.set_parent(torch, hand).unwrap();
+    /// assert!(world.set_parent(player, torch).is_err());
+    ///
+    /// world.delete_entity(player);
+    /// assert!(!world.is_alive(hand));
+    /// assert!(!world.is_alive(torch));
+    /// # }
+    /// ```
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Fallible<()> {
+        for &entity in &[child, parent] {
+            if !self.is_alive(entity) {
+                bail!(
+                    "Can't parent {} to {}, since {} has been deleted",
+                    self.named(child),
+                    self.named(parent),
+                    entity
+                );
+            }
+        }
+
+        let mut ancestors = Vec::new();
+        let mut ancestor = Some(parent);
+        while let Some(entity) = ancestor {
+            if entity == child {
+                bail!(
+                    "Can't parent {} to {}, since that would create a cycle",
+                    self.named(child),
+                    self.named(parent)
+                );
+            } else if ancestors.contains(&entity) {
+                // The parent is already in a cycle, which doesn't involve the child.
+                break;
+            }
+            ancestors.push(entity);
+            ancestor = self.get_one::<ParentComponent>(entity).map(|p| p.0);
+        }
+
+        self.insert(child, ParentComponent(parent));
+        Ok(())
+    }
+
+    /// Deletes a single entity, returning whether it was alive.
+    fn delete_one(&mut self, entity: Entity) -> bool {
+        let (archetype, row) = match self.location(entity) {
+            Some(location) => location,
+            None => return false,
+        };
+        self.archetypes[archetype].remove(row);
+        self.relocate_from(archetype, row);
+
+        // Once an index's generation can't be bumped any further, it's retired rather than
+        // wrapping around, which would let stale handles refer to new entities.
+        let meta = &mut self.entities[entity.index as usize];
+        meta.location = None;
+        if let Some(generation) = meta.generation.checked_add(1) {
+            meta.generation = generation;
+            self.free_entities.push(entity.index);
+        }
+        self.send_event(EntityDeleted(entity));
+        true
+    }
+
+    /// Adds a component to an entity, returning the component of the same type the entity
+    /// previously had, if any. Panics if the entity has been deleted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BarComponent;
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let me = world.new_entity(hlist![FooComponent(1)]);
+    ///
+    /// assert_eq!(world.insert(me, BarComponent), None);
+    /// assert_eq!(world.insert(me, FooComponent(2)), Some(FooComponent(1)));
+    /// assert_eq!(world.get(me), Some(hlist![&FooComponent(2), &BarComponent]));
+    ///
+    /// assert_eq!(world.remove::<FooComponent>(me), Some(FooComponent(2)));
+    /// assert_eq!(world.remove::<FooComponent>(me), None);
+    /// assert_eq!(world.get(me), Some(hlist![&BarComponent]));
+    /// # }
+    /// ```
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> Option<T> {
+        let hlist_pat![previous] = self.insert_all(entity, hlist![component]);
+        previous
+    }
+
+    /// Adds several components to an entity, returning an HList of the components of the same
+    /// types the entity previously had. Panics if the entity has been deleted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::World;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct BarComponent;
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let me = world.new_entity(hlist![FooComponent(1)]);
+    ///
+    /// let previous = world.insert_all(me, hlist![FooComponent(2), BarComponent]);
+    /// assert_eq!(previous, hlist![Some(FooComponent(1)), None]);
+    /// assert_eq!(world.get(me), Some(hlist![&FooComponent(2), &BarComponent]));
+    /// # }
+    /// ```
+    pub fn insert_all<C: ComponentHList>(&mut self, entity: Entity, components: C) -> C::Previous {
+        let (src, row) = self.location(entity).unwrap_or_else(|| {
+            panic!(
+                "Can't insert components into {}, which has been deleted",
+                self.named(entity)
+            )
+        });
+
+        let mut types = self.archetypes[src].types().to_vec();
+        C::type_ids(&mut types);
+        types.sort();
+        types.dedup();
+
+        if types.len() == self.archetypes[src].types().len() {
+            components.insert_into_archetype(&mut self.archetypes[src], row, self.change_tick)
+        } else {
+            let dst = self.archetype_with_types(types, |world| {
+                let mut columns = world.archetypes[src].empty_columns_except(None);
+                C::empty_columns(&mut columns);
+                columns
+            });
+            let row = self.move_entity(entity, (src, row), dst, None);
+            let previous =
+                components.insert_into_archetype(&mut self.archetypes[dst], row, self.change_tick);
+            self.settle_last_row(dst);
+            previous
+        }
+    }
+
+    /// Removes a component from an entity, returning it, and sends a `ComponentRemoved` event.
+    /// Returns `None` if the entity has been deleted or doesn't have the component.
+    pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        let (src, row) = self.location(entity)?;
+        if !self.archetypes[src].has::<T>() {
+            return None;
+        }
+
+        let removed_type = TypeId::of::<T>();
+        let types = self.archetypes[src]
+            .types()
+            .iter()
+            .cloned()
+            .filter(|&ty| ty != removed_type)
+            .collect();
+        let dst = self.archetype_with_types(types, |world| {
+            world.archetypes[src].empty_columns_except(Some(removed_type))
+        });
+
+        let mut removed = Vec::<T>::with_capacity(1);
+        self.move_entity(entity, (src, row), dst, Some(&mut removed as &mut dyn Column));
+        self.settle_last_row(dst);
+        self.send_event(ComponentRemoved::<T>::new(entity));
+        removed.pop()
+    }
+
+    /// Returns whether the entity exists, i.e. whether it has been created and not deleted.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.location(entity).is_some()
+    }
+
+    /// Finds an entity with a `NameComponent` of the given name. If several entities have the
+    /// name, the first one spawned is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # use game::{components::NameComponent, World};
+    /// # fn main() {
+    /// let mut world = World::default();
+    /// let player = world.new_entity(hlist![NameComponent::new("player")]);
+    /// assert_eq!(world.find_by_name("player"), Some(player));
+    /// assert_eq!(world.find_by_name("goal"), None);
+    /// assert_eq!(world.named(player).to_string(), format!("{}:player", player));
+    /// # }
+    /// ```
+    pub fn find_by_name(&self, name: &str) -> Option<Entity> {
+        self.iter::<Hlist![&NameComponent]>()
+            .find(|(_, hlist_pat![n])| n.0 == name)
+            .map(|(entity, _)| entity)
+    }
+
+    /// Returns a wrapper around the entity whose `Display` and `Debug` impls include the
+    /// entity's name, if it has one. This is how entities should be shown to people, since an
+    /// `Entity` on its own only prints its index and generation.
+    pub fn named(&self, entity: Entity) -> NamedEntity<'_> {
+        NamedEntity {
+            entity,
+            name: self.get_one::<NameComponent>(entity),
+        }
+    }
+
+    /// Stores a resource, returning the resource of the same type that was previously stored, if
+    /// any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate game;
+    /// # use game::{resources::Time, World};
+    /// # fn main() {
+    /// let mut world = World::default();
+    /// assert_eq!(world.resource::<Time>(), None);
+    ///
+    /// world.insert_resource(Time::default());
+    /// world.resource_mut::<Time>().unwrap().advance(16);
+    /// assert_eq!(world.resource::<Time>().unwrap().elapsed, 16);
+    /// # }
+    /// ```
+    pub fn insert_resource<T: Resource>(&mut self, resource: T) -> Option<T> {
+        let resource = ResourceCell(UnsafeCell::new(Box::new(resource)));
+        self.resources
+            .insert(TypeId::of::<T>(), resource)
+            .map(|previous| *downcast_resource(previous.0.into_inner()))
+    }
+
+    /// Removes a resource, returning it.
+    pub fn remove_resource<T: Resource>(&mut self) -> Option<T> {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .map(|previous| *downcast_resource(previous.0.into_inner()))
+    }
+
+    /// Tries to get a resource.
+    pub fn resource<T: Resource>(&self) -> Option<&T> {
+        self.resources.get(&TypeId::of::<T>()).map(|resource| {
+            let resource: &dyn Any = unsafe { &**resource.0.get() };
+            resource
+                .downcast_ref()
+                .expect("Resource was stored under the wrong type")
+        })
+    }
+
+    /// Tries to get a resource mutably.
+    pub fn resource_mut<T: Resource>(&mut self) -> Option<&mut T> {
+        self.resource_ptr().map(|resource| unsafe { &mut *resource })
+    }
+
+    /// Returns a pointer to a resource, through which it may be mutated while no other reference
+    /// to it is live.
+    fn resource_ptr<T: Resource>(&self) -> Option<*mut T> {
+        self.resources.get(&TypeId::of::<T>()).map(|resource| {
+            let resource: &mut dyn Any = unsafe { &mut **resource.0.get() };
+            let resource: &mut T = resource
+                .downcast_mut()
+                .expect("Resource was stored under the wrong type");
+            resource as *mut T
+        })
+    }
+
+    /// Creates a new entity with the given components. Since it's the last entity spawned, its row
+    /// is already in spawn order.
+    pub fn new_entity<C: ComponentHList>(&mut self, components: C) -> Entity {
+        let archetype = self.archetype_for::<C>();
+        let entity = self.alloc_entity();
+        let spawned = self.entities[entity.index as usize].spawned;
+        let row = self.archetypes[archetype].push_entity(entity, spawned);
+        components.insert_into_archetype(&mut self.archetypes[archetype], row, self.change_tick);
+        self.entities[entity.index as usize].location = Some((archetype, row));
+        entity
+    }
+
+    /// Creates a new entity with the components in a bundle.
+    pub fn new_entity_from_bundle(&mut self, components: ComponentBundle) -> Entity {
+        let archetype =
+            self.archetype_with_types(components.types(), |_| components.empty_columns());
+        let entity = self.alloc_entity();
+        let spawned = self.entities[entity.index as usize].spawned;
+        let row = self.archetypes[archetype].push_entity(entity, spawned);
+        components.push_into_archetype(&mut self.archetypes[archetype], row, self.change_tick);
+        self.entities[entity.index as usize].location = Some((archetype, row));
+        entity
+    }
+
+    /// Iterates in parallel over entities which have all the given components, in no particular
+    /// order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # extern crate rayon;
+    /// # use game::World;
+    /// # use rayon::iter::ParallelIterator;
+    /// # fn main() {
+    /// #[derive(Debug)]
+    /// struct FooComponent;
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// #[derive(Debug)]
+    /// struct BarComponent(usize);
+    /// impl typemap::Key for BarComponent { type Value = BarComponent; }
+    ///
+    /// let mut world = World::default();
+    /// world.new_entity(hlist![FooComponent]);
+    /// world.new_entity(hlist![FooComponent, BarComponent(42)]);
+    /// world.par_iter().for_each(|(e, hlist_pat![foo, bar])| {
+    ///     println!("Entity: {:?}", e);
+    ///     println!("Foo: {:?}", foo as &FooComponent);
+    ///     println!("Bar: {:?}", bar as &BarComponent);
+    /// });
+    /// # }
+    /// ```
+    pub fn par_iter<'a, C>(&'a self) -> impl 'a + ParallelIterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentRefHList<'a> + Send,
+    {
+        // Read-only queries can't alias.
+        unsafe { self.par_iter_unchecked(self.change_ticks()) }
+    }
+
+    /// Iterates in parallel over entities which have all the given components, allowing some of
+    /// them to be borrowed mutably. Panics if the same component is borrowed mutably more than
+    /// once, or both mutably and immutably.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # extern crate rayon;
+    /// # use game::World;
+    /// # use rayon::iter::ParallelIterator;
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// let mut world = World::default();
+    /// let a = world.new_entity(hlist![FooComponent(1)]);
+    /// let b = world.new_entity(hlist![FooComponent(2)]);
+    /// world
+    ///     .par_iter_mut::<Hlist![&mut FooComponent]>()
+    ///     .for_each(|(_, hlist_pat![foo])| foo.0 *= 10);
+    /// assert_eq!(world.get(a), Some(hlist![&FooComponent(10)]));
+    /// assert_eq!(world.get(b), Some(hlist![&FooComponent(20)]));
+    /// # }
+    /// ```
+    pub fn par_iter_mut<'a, C>(&'a mut self) -> impl 'a + ParallelIterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentQuery<'a> + Send,
+    {
+        assert_query_does_not_alias::<C>();
+        unsafe { self.par_iter_unchecked(self.change_ticks()) }
+    }
+
+    /// Iterates over the query in parallel without checking that it doesn't alias.
+    unsafe fn par_iter_unchecked<'a, C>(
+        &'a self,
+        ticks: ChangeTicks,
+    ) -> impl 'a + ParallelIterator<Item = (Entity, C)>
+    where
+        C: 'a + ComponentQuery<'a> + Send,
+    {
+        self.archetypes
+            .par_iter()
+            .filter_map(move |archetype| {
+                C::columns(archetype, ticks).map(|columns| (archetype, columns))
+            }).flat_map(|(archetype, columns)| {
+                archetype
+                    .entities()
+                    .par_iter()
+                    .enumerate()
+                    .filter(move |&(row, _)| C::filter(columns, row))
+                    .map(move |(row, &entity)| (entity, C::fetch(columns, row)))
+            })
+    }
+
+    /// Marks the start of a step of the system `S`. Until `finish_system` is called, `Changed`
+    /// filters only match components changed since `S` last finished a step.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate frunk;
+    /// # extern crate game;
+    /// # #[macro_use] extern crate typemap;
+    /// # use game::{Changed, World};
+    /// # fn main() {
+    /// #[derive(Debug, PartialEq)]
+    /// struct FooComponent(usize);
+    /// impl typemap::Key for FooComponent { type Value = FooComponent; }
+    ///
+    /// struct MySystem;
+    ///
+    /// let mut world = World::default();
+    /// world.new_entity(hlist![FooComponent(1)]);
+    /// let b = world.new_entity(hlist![FooComponent(2)]);
+    ///
+    /// // Everything is new to a system that hasn't run yet.
+    /// world.start_system::<MySystem>();
+    /// assert_eq!(world.iter::<Hlist![Changed<FooComponent>]>().count(), 2);
+    /// world.finish_system::<MySystem>();
+    ///
+    /// world.get_mut::<FooComponent>(b).unwrap().0 += 1;
+    ///
+    /// world.start_system::<MySystem>();
+    /// let changed = world
+    ///     .iter::<Hlist![Changed<FooComponent>]>()
+    ///     .map(|(entity, _)| entity)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(changed, vec![b]);
+    /// world.finish_system::<MySystem>();
+    /// # }
+    /// ```
+    pub fn start_system<S: 'static>(&mut self) {
+        self.start_system_by_id(TypeId::of::<S>());
+    }
+
+    /// Marks the start of a step of the system with the given `TypeId`, as with `start_system`.
+    pub(crate) fn start_system_by_id(&mut self, system: TypeId) {
+        self.last_run_tick = self.system_ticks.get(&system).cloned();
+    }
+
+    /// Returns the ticks for queries made by the system with the given `TypeId`, as if it had
+    /// just been started with `start_system`.
+    pub(crate) fn system_change_ticks(&self, system: TypeId) -> ChangeTicks {
+        ChangeTicks {
+            last_run: self.system_ticks.get(&system).cloned(),
+            now: self.change_tick,
+        }
+    }
+
+    /// Finishes a step of each of the given systems, which were run concurrently.
+    pub(crate) fn finish_systems(&mut self, systems: impl IntoIterator<Item = TypeId>) {
+        for system in systems {
+            self.system_ticks.insert(system, self.change_tick);
+        }
+        self.last_run_tick = None;
+        self.change_tick += 1;
+    }
+
+    /// Marks the end of a step of the system `S`, which was started with `start_system`. Changes
+    /// made afterwards, including by `apply_commands`, are visible to `S`'s next step.
+    pub fn finish_system<S: 'static>(&mut self) {
+        self.finish_systems(Some(TypeId::of::<S>()));
+    }
+
+    /// Returns the ticks to use for change detection in queries.
+    fn change_ticks(&self) -> ChangeTicks {
+        ChangeTicks {
+            last_run: self.last_run_tick,
+            now: self.change_tick,
+        }
+    }
+
+    /// Returns a handle for a new entity, reusing a free index if one exists. The entity has no
+    /// location until it is pushed into an archetype.
+    fn alloc_entity(&mut self) -> Entity {
+        let spawned = self.next_spawn;
+        self.next_spawn += 1;
+        if let Some(index) = self.free_entities.pop() {
+            let meta = &mut self.entities[index as usize];
+            meta.spawned = spawned;
+            Entity {
+                index,
+                generation: meta.generation,
+            }
+        } else {
+            let index = self.entities.len() as u32;
+            self.entities.push(EntityMeta {
+                generation: 0,
+                location: None,
+                spawned,
+            });
+            Entity {
+                index,
+                generation: 0,
+            }
+        }
+    }
+
+    /// Returns the archetype and row of the entity, if it is alive.
+    fn location(&self, entity: Entity) -> Option<(usize, usize)> {
+        let meta = self.entities.get(entity.index as usize)?;
+        if meta.generation == entity.generation {
+            meta.location
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the archetype for the components in the given HList, creating it if
+    /// necessary.
+    fn archetype_for<C: ComponentHList>(&mut self) -> usize {
+        let mut types = Vec::new();
+        C::type_ids(&mut types);
+        types.sort();
+        let len = types.len();
+        types.dedup();
+        assert_eq!(len, types.len(), "An entity can't have two components of the same type");
+
+        self.archetype_with_types(types, |_| {
+            let mut columns = Vec::new();
+            C::empty_columns(&mut columns);
+            columns
+        })
+    }
+
+    /// Returns the index of the archetype with the given (sorted, deduplicated) component types,
+    /// creating it from the given columns if necessary.
+    fn archetype_with_types<F>(&mut self, types: Vec<TypeId>, columns: F) -> usize
+    where
+        F: FnOnce(&World) -> Vec<(TypeId, Box<dyn Column>)>,
+    {
+        if let Some(&idx) = self.archetype_ids.get(&types) {
+            return idx;
+        }
+
+        let columns = columns(self);
+        let idx = self.archetypes.len();
+        self.archetypes.push(Archetype::new(columns));
+        self.archetype_ids.insert(types, idx);
+        idx
+    }
+
+    /// Moves an entity from one archetype to the end of another, returning its new row.
+    /// Components the new archetype has no column for are moved into `removed` if it is given,
+    /// and dropped otherwise. Columns the old archetype has no column for are left for the caller
+    /// to fill, after which it must call `settle_last_row`.
+    fn move_entity(
+        &mut self,
+        entity: Entity,
+        (src, row): (usize, usize),
+        dst: usize,
+        removed: Option<&mut dyn Column>,
+    ) -> usize {
+        let (src_archetype, dst_archetype) = index_twice(&mut self.archetypes, src, dst);
+        let dst_row = src_archetype.move_row(row, dst_archetype, removed);
+        self.relocate_from(src, row);
+        self.entities[entity.index as usize].location = Some((dst, dst_row));
+        dst_row
+    }
+
+    /// Moves the last row of an archetype back to its place in spawn order, returning its new
+    /// row.
+    fn settle_last_row(&mut self, archetype: usize) -> usize {
+        let row = self.archetypes[archetype].settle_last_row();
+        self.relocate_from(archetype, row);
+        row
+    }
+
+    /// Updates the locations of the entities in an archetype from the given row on, after rows
+    /// have been shifted.
+    fn relocate_from(&mut self, archetype: usize, row: usize) {
+        let entities = self.archetypes[archetype].entities();
+        for (row, entity) in entities.iter().enumerate().skip(row) {
+            self.entities[entity.index as usize].location = Some((archetype, row));
+        }
+    }
+}
+
+/// An entity paired with its name, for display purposes.
+#[derive(Clone, Copy)]
+pub struct NamedEntity<'a> {
+    entity: Entity,
+    name: Option<&'a NameComponent>,
+}
+
+impl<'a> Debug for NamedEntity<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "Entity({})", self)
+    }
+}
+
+impl<'a> Display for NamedEntity<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self.name {
+            Some(name) => write!(fmt, "{}:{}", self.entity, name.0),
+            None => write!(fmt, "{}", self.entity),
+        }
+    }
+}
+
+/// Mutably borrows two different elements of a slice.
+fn index_twice<T>(slice: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert_ne!(a, b, "Tried to borrow the same element twice");
+    if a < b {
+        let (l, r) = slice.split_at_mut(b);
+        (&mut l[a], &mut r[0])
+    } else {
+        let (l, r) = slice.split_at_mut(a);
+        (&mut r[0], &mut l[b])
+    }
+}
+
+/// Downcasts a boxed resource to its concrete type.
+fn downcast_resource<T: Resource>(resource: Box<dyn Any + Send + Sync>) -> Box<T> {
+    let resource: Box<dyn Any> = resource;
+    resource
+        .downcast()
+        .expect("Resource was stored under the wrong type")
+}
+
+/// An iterator over the entities matching a query, in the order they were spawned. Each
+/// archetype's rows are already in that order, so this just merges the matching archetypes.
+struct SpawnOrder<'a, C: ComponentQuery<'a>> {
+    /// The matching archetypes, along with their columns and the next row to look at in each.
+    cursors: Vec<(&'a Archetype, C::Columns, usize)>,
+}
+
+impl<'a, C: ComponentQuery<'a>> Iterator for SpawnOrder<'a, C> {
+    type Item = (Entity, C);
+
+    fn next(&mut self) -> Option<(Entity, C)> {
+        loop {
+            let (archetype, columns, row) = {
+                let cursor = self
+                    .cursors
+                    .iter_mut()
+                    .filter(|(archetype, _, row)| *row < archetype.entities().len())
+                    .min_by_key(|(archetype, _, row)| archetype.spawned()[*row])?;
+                cursor.2 += 1;
+                (cursor.0, cursor.1, cursor.2 - 1)
+            };
+            if C::filter(columns, row) {
+                // This is only created by `iter_unchecked`, whose caller upholds `fetch`'s
+                // requirements.
+                let components = unsafe { C::fetch(columns, row) };
+                return Some((archetype.entities()[row], components));
+            }
+        }
+    }
+}
+
+/// Returns the only item of a query's results, or an error describing the matching entities if
+/// there isn't exactly one.
+fn expect_single<C>(
+    mut matches: impl Iterator<Item = (Entity, C)>,
+    describe: impl Fn(Entity) -> String,
+) -> Fallible<(Entity, C)> {
+    let first = matches
+        .next()
+        .ok_or_else(|| format_err!("Expected one entity to match the query, but none did"))?;
+    let others = matches.map(|(entity, _)| entity).collect::<Vec<_>>();
+    if !others.is_empty() {
+        let entities = Some(first.0)
+            .into_iter()
+            .chain(others)
+            .map(describe)
+            .collect::<Vec<_>>();
+        bail!(
+            "Expected one entity to match the query, but {} did: {}",
+            entities.len(),
+            entities.join(", ")
+        );
+    }
+    Ok(first)
+}
+
+/// Panics if the query borrows a component mutably more than once, or both mutably and
+/// immutably.
+fn assert_query_does_not_alias<'a, C: ComponentQuery<'a>>() {
+    let mut access = Access::default();
+    C::access(&mut access);
+    access.assert_no_aliasing();
+}
@@ -0,0 +1,466 @@
+//! HList-based component bundles and queries.
+
+use crate::world::{Access, Archetype, Column, Component};
+use frunk::hlist::{HCons, HNil};
+use std::{any::TypeId, marker::PhantomData, mem::replace};
+
+/// A trait for an HList containing only components (i.e. types that
+/// `impl typemap::Key<Value = Self>`).
+pub trait ComponentHList {
+    /// An HList of the previous value of each component, as returned by `World::insert_all`.
+    type Previous;
+
+    /// Adds the `TypeId`s of the components to the given list.
+    fn type_ids(ids: &mut Vec<TypeId>);
+
+    /// Adds an empty column for each component to the given list.
+    fn empty_columns(columns: &mut Vec<(TypeId, Box<dyn Column>)>);
+
+    /// Stores each component at the given row, marking it as changed at the given tick. If the
+    /// column already has a value at the row, it is replaced and returned; otherwise, the row
+    /// must be the last row of the archetype, and the component is pushed onto the column.
+    fn insert_into_archetype(
+        self,
+        archetype: &mut Archetype,
+        row: usize,
+        tick: u64,
+    ) -> Self::Previous;
+}
+
+impl<H, T> ComponentHList for HCons<H, T>
+where
+    H: Component,
+    T: ComponentHList,
+{
+    type Previous = HCons<Option<H>, T::Previous>;
+
+    fn type_ids(ids: &mut Vec<TypeId>) {
+        ids.push(TypeId::of::<H>());
+        T::type_ids(ids);
+    }
+
+    fn empty_columns(columns: &mut Vec<(TypeId, Box<dyn Column>)>) {
+        columns.push((TypeId::of::<H>(), Box::new(Vec::<H>::new())));
+        T::empty_columns(columns);
+    }
+
+    fn insert_into_archetype(
+        self,
+        archetype: &mut Archetype,
+        row: usize,
+        tick: u64,
+    ) -> Self::Previous {
+        let head = {
+            let column = archetype
+                .column_mut::<H>()
+                .expect("Archetype is missing a component column");
+            if row < column.len() {
+                Some(replace(&mut column[row], self.head))
+            } else {
+                column.push(self.head);
+                None
+            }
+        };
+        archetype.set_changed(TypeId::of::<H>(), row, tick);
+        let tail = self.tail.insert_into_archetype(archetype, row, tick);
+        HCons { head, tail }
+    }
+}
+
+impl ComponentHList for HNil {
+    type Previous = HNil;
+
+    fn type_ids(_: &mut Vec<TypeId>) {}
+
+    fn empty_columns(_: &mut Vec<(TypeId, Box<dyn Column>)>) {}
+
+    fn insert_into_archetype(self, _: &mut Archetype, _: usize, _: u64) -> HNil {
+        HNil
+    }
+}
+
+/// The ticks a query uses for change detection.
+#[derive(Clone, Copy, Debug)]
+pub struct ChangeTicks {
+    /// The tick at which the running system last ran, or `None` if it hasn't run before (or no
+    /// system is running).
+    pub last_run: Option<u64>,
+
+    /// The current tick, at which mutably borrowed components are marked as changed.
+    pub now: u64,
+}
+
+/// A trait for an HList of component references and filters, as used by `World::iter_mut`.
+/// Its elements may be:
+///
+/// - `&T`, which borrows the component `T`.
+/// - `&mut T`, which mutably borrows the component `T`, marking it as changed.
+/// - `Option<&T>` or `Option<&mut T>`, which borrow the component `T` if the entity has it.
+/// - `With<T>` or `Without<T>`, which require that the entity has (or doesn't have) `T`.
+/// - `Changed<T>`, which requires that the entity's `T` changed since the running system last
+///   ran.
+pub trait ComponentQuery<'a>: Sized {
+    /// The columns borrowed from a matching archetype.
+    type Columns: 'a + Copy + Send + Sync;
+
+    /// Records the components accessed by the query.
+    fn access(access: &mut Access);
+
+    /// Borrows the columns from the archetype, if it has all the required components.
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns>;
+
+    /// Returns whether the given row matches the query's per-row filters.
+    fn filter(columns: Self::Columns, row: usize) -> bool;
+
+    /// Gets the components at the given row of the columns.
+    ///
+    /// # Safety
+    ///
+    /// The row must be in bounds, and no other reference to a component this query accesses
+    /// mutably may be live at that row.
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self;
+}
+
+/// A trait for an HList of shared component references and filters, as used by `World::iter`.
+pub trait ComponentRefHList<'a>: ComponentQuery<'a> {}
+
+/// A query filter that matches entities with the component `T`, without borrowing it.
+pub struct With<T>(PhantomData<T>);
+
+/// A query filter that matches entities without the component `T`.
+pub struct Without<T>(PhantomData<T>);
+
+/// A query filter that matches entities whose component `T` was added or mutably borrowed since
+/// the running system last ran.
+pub struct Changed<T>(PhantomData<T>);
+
+/// A mutably borrowed column, along with its change ticks.
+pub struct MutColumn<T> {
+    components: ColumnPtr<T>,
+    changed: ColumnPtr<u64>,
+    now: u64,
+}
+
+impl<T> Clone for MutColumn<T> {
+    fn clone(&self) -> MutColumn<T> {
+        *self
+    }
+}
+
+impl<T> Copy for MutColumn<T> {}
+
+impl<T> MutColumn<T> {
+    fn new(archetype: &Archetype, ticks: ChangeTicks) -> Option<MutColumn<T>>
+    where
+        T: Component,
+    {
+        let (components, changed) = archetype.column_ptr::<T>()?;
+        Some(MutColumn {
+            components,
+            changed,
+            now: ticks.now,
+        })
+    }
+
+    unsafe fn fetch<'a>(self, row: usize) -> &'a mut T {
+        *self.changed.0.add(row) = self.now;
+        &mut *self.components.0.add(row)
+    }
+}
+
+/// The change ticks of a column, along with the tick to compare them against.
+#[derive(Clone, Copy)]
+pub struct ChangedColumn<'a> {
+    changed: &'a [u64],
+    last_run: Option<u64>,
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<&'a H, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = HCons<&'a [H], T::Columns>;
+
+    fn access(access: &mut Access) {
+        access.read::<H>();
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        let head = archetype.column::<H>()?;
+        let tail = T::columns(archetype, ticks)?;
+        Some(HCons { head, tail })
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns.tail, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: columns.head.get_unchecked(row),
+            tail: T::fetch(columns.tail, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentRefHList<'a> for HCons<&'a H, T>
+where
+    H: Component,
+    T: ComponentRefHList<'a>,
+{
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<&'a mut H, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = HCons<MutColumn<H>, T::Columns>;
+
+    fn access(access: &mut Access) {
+        access.write::<H>();
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        let head = MutColumn::new(archetype, ticks)?;
+        let tail = T::columns(archetype, ticks)?;
+        Some(HCons { head, tail })
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns.tail, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: columns.head.fetch(row),
+            tail: T::fetch(columns.tail, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<Option<&'a H>, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = HCons<Option<&'a [H]>, T::Columns>;
+
+    fn access(access: &mut Access) {
+        access.read::<H>();
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        let head = archetype.column::<H>();
+        let tail = T::columns(archetype, ticks)?;
+        Some(HCons { head, tail })
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns.tail, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: columns.head.map(|column| column.get_unchecked(row)),
+            tail: T::fetch(columns.tail, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentRefHList<'a> for HCons<Option<&'a H>, T>
+where
+    H: Component,
+    T: ComponentRefHList<'a>,
+{
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<Option<&'a mut H>, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = HCons<Option<MutColumn<H>>, T::Columns>;
+
+    fn access(access: &mut Access) {
+        access.write::<H>();
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        let head = MutColumn::new(archetype, ticks);
+        let tail = T::columns(archetype, ticks)?;
+        Some(HCons { head, tail })
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns.tail, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: columns.head.map(|column| column.fetch(row)),
+            tail: T::fetch(columns.tail, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<With<H>, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = T::Columns;
+
+    fn access(access: &mut Access) {
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        if archetype.has::<H>() {
+            T::columns(archetype, ticks)
+        } else {
+            None
+        }
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: With(PhantomData),
+            tail: T::fetch(columns, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentRefHList<'a> for HCons<With<H>, T>
+where
+    H: Component,
+    T: ComponentRefHList<'a>,
+{
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<Without<H>, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = T::Columns;
+
+    fn access(access: &mut Access) {
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        if archetype.has::<H>() {
+            None
+        } else {
+            T::columns(archetype, ticks)
+        }
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        T::filter(columns, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: Without(PhantomData),
+            tail: T::fetch(columns, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentRefHList<'a> for HCons<Without<H>, T>
+where
+    H: Component,
+    T: ComponentRefHList<'a>,
+{
+}
+
+impl<'a, H, T> ComponentQuery<'a> for HCons<Changed<H>, T>
+where
+    H: Component,
+    T: ComponentQuery<'a>,
+{
+    type Columns = HCons<ChangedColumn<'a>, T::Columns>;
+
+    fn access(access: &mut Access) {
+        access.read::<H>();
+        T::access(access);
+    }
+
+    fn columns(archetype: &'a Archetype, ticks: ChangeTicks) -> Option<Self::Columns> {
+        let head = ChangedColumn {
+            changed: archetype.changed::<H>()?,
+            last_run: ticks.last_run,
+        };
+        let tail = T::columns(archetype, ticks)?;
+        Some(HCons { head, tail })
+    }
+
+    fn filter(columns: Self::Columns, row: usize) -> bool {
+        let changed = match columns.head.last_run {
+            Some(last_run) => columns.head.changed[row] > last_run,
+            None => true,
+        };
+        changed && T::filter(columns.tail, row)
+    }
+
+    unsafe fn fetch(columns: Self::Columns, row: usize) -> Self {
+        HCons {
+            head: Changed(PhantomData),
+            tail: T::fetch(columns.tail, row),
+        }
+    }
+}
+
+impl<'a, H, T> ComponentRefHList<'a> for HCons<Changed<H>, T>
+where
+    H: Component,
+    T: ComponentRefHList<'a>,
+{
+}
+
+impl<'a> ComponentQuery<'a> for HNil {
+    type Columns = HNil;
+
+    fn access(_: &mut Access) {}
+
+    fn columns(_: &'a Archetype, _: ChangeTicks) -> Option<HNil> {
+        Some(HNil)
+    }
+
+    fn filter(_: HNil, _: usize) -> bool {
+        true
+    }
+
+    unsafe fn fetch(_: HNil, _: usize) -> HNil {
+        HNil
+    }
+}
+
+impl<'a> ComponentRefHList<'a> for HNil {}
+
+/// A pointer to the start of a column, used for handing out unique references to rows.
+pub struct ColumnPtr<T>(pub(crate) *mut T);
+
+impl<T> Clone for ColumnPtr<T> {
+    fn clone(&self) -> ColumnPtr<T> {
+        *self
+    }
+}
+
+impl<T> Copy for ColumnPtr<T> {}
+
+// The pointer is only dereferenced at distinct rows, and only while the `World` is borrowed in a
+// way that rules out other access to the column.
+unsafe impl<T: Send> Send for ColumnPtr<T> {}
+unsafe impl<T: Sync> Sync for ColumnPtr<T> {}
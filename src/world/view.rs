@@ -0,0 +1,174 @@
+//! Access to a `World` restricted to what a system declared.
+
+use crate::{
+    components::NameComponent,
+    world::{
+        assert_query_does_not_alias, expect_single, Access, ChangeTicks, Commands, ComponentQuery,
+        ComponentRefHList, EventReader, Events, NamedEntity, Resource, SystemAccess, World,
+    },
+    Entity,
+};
+use failure::Fallible;
+use rayon::iter::ParallelIterator;
+
+/// A view of a `World` for a `ParallelSystem`. Queries and resource accesses panic unless the
+/// system declared them in `ParallelSystem::access`, which is what allows systems with disjoint
+/// access to run at the same time.
+pub struct WorldView<'a> {
+    world: &'a World,
+    access: &'a SystemAccess,
+    ticks: ChangeTicks,
+    system: &'static str,
+}
+
+impl<'a> WorldView<'a> {
+    /// Creates a view of the world. No other system may be given a view with conflicting access
+    /// while this one is live.
+    pub(crate) unsafe fn new(
+        world: &'a World,
+        access: &'a SystemAccess,
+        ticks: ChangeTicks,
+        system: &'static str,
+    ) -> WorldView<'a> {
+        WorldView {
+            world,
+            access,
+            ticks,
+            system,
+        }
+    }
+
+    /// Tries to get the given components for a given entity, as with `World::get`.
+    pub fn get<'b, C>(&'b self, entity: Entity) -> Option<C>
+    where
+        C: ComponentRefHList<'b>,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.get_unchecked(entity, self.ticks) }
+    }
+
+    /// Tries to get the given components for a given entity, allowing some of them to be borrowed
+    /// mutably.
+    pub fn get_mut<'b, C>(&'b mut self, entity: Entity) -> Option<C>
+    where
+        C: ComponentQuery<'b>,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.get_unchecked(entity, self.ticks) }
+    }
+
+    /// Iterates over entities which match the given query, as with `World::iter`.
+    pub fn iter<'b, C>(&'b self) -> impl 'b + Iterator<Item = (Entity, C)>
+    where
+        C: 'b + ComponentRefHList<'b>,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.iter_unchecked(self.ticks) }
+    }
+
+    /// Iterates over entities which match the given query, allowing some of the components to be
+    /// borrowed mutably, as with `World::iter_mut`.
+    pub fn iter_mut<'b, C>(&'b mut self) -> impl 'b + Iterator<Item = (Entity, C)>
+    where
+        C: 'b + ComponentQuery<'b>,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.iter_unchecked(self.ticks) }
+    }
+
+    /// Gets the only entity which matches the given query, as with `World::single`.
+    pub fn single<'b, C>(&'b self) -> Fallible<(Entity, C)>
+    where
+        C: 'b + ComponentRefHList<'b>,
+        'a: 'b,
+    {
+        expect_single(self.iter(), |entity| entity.to_string())
+    }
+
+    /// Gets the only entity which matches the given query, allowing some of its components to be
+    /// borrowed mutably, as with `World::single_mut`.
+    pub fn single_mut<'b, C>(&'b mut self) -> Fallible<(Entity, C)>
+    where
+        C: 'b + ComponentQuery<'b>,
+        'a: 'b,
+    {
+        expect_single(self.iter_mut(), |entity| entity.to_string())
+    }
+
+    /// Iterates in parallel over entities which match the given query, as with
+    /// `World::par_iter`.
+    pub fn par_iter<'b, C>(&'b self) -> impl 'b + ParallelIterator<Item = (Entity, C)>
+    where
+        C: 'b + ComponentRefHList<'b> + Send,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.par_iter_unchecked(self.ticks) }
+    }
+
+    /// Iterates in parallel over entities which match the given query, allowing some of the
+    /// components to be borrowed mutably, as with `World::par_iter_mut`.
+    pub fn par_iter_mut<'b, C>(&'b mut self) -> impl 'b + ParallelIterator<Item = (Entity, C)>
+    where
+        C: 'b + ComponentQuery<'b> + Send,
+        'a: 'b,
+    {
+        self.check_query::<C>();
+        unsafe { self.world.par_iter_unchecked(self.ticks) }
+    }
+
+    /// Tries to get a resource.
+    pub fn resource<T: Resource>(&self) -> Option<&T> {
+        self.access.assert_resource_declared::<T>(false, self.system);
+        self.world.resource()
+    }
+
+    /// Tries to get a resource mutably.
+    pub fn resource_mut<T: Resource>(&mut self) -> Option<&mut T> {
+        self.access.assert_resource_declared::<T>(true, self.system);
+        self.world
+            .resource_ptr()
+            .map(|resource| unsafe { &mut *resource })
+    }
+
+    /// Iterates over the events the reader hasn't read yet, as with `EventReader::read`. The
+    /// system must declare that it reads the `Events<E>` resource.
+    pub fn read_events<'b, E: Resource>(
+        &'b self,
+        reader: &mut EventReader<E>,
+    ) -> impl 'b + Iterator<Item = &'b E> {
+        self.access
+            .assert_resource_declared::<Events<E>>(false, self.system);
+        reader.read(self.world)
+    }
+
+    /// Returns the world's command buffer, which is applied once every system in the current
+    /// stage has finished. Events should be sent through it, too.
+    pub fn commands(&self) -> &'a Commands {
+        self.world.commands()
+    }
+
+    /// Returns whether the entity exists.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.world.is_alive(entity)
+    }
+
+    /// Returns a wrapper around the entity for display purposes. The system must declare that it
+    /// reads `NameComponent`.
+    pub fn named(&self, entity: Entity) -> NamedEntity<'_> {
+        self.check_query::<Hlist![&NameComponent]>();
+        self.world.named(entity)
+    }
+
+    /// Panics if the query aliases, or accesses components the system didn't declare.
+    fn check_query<'b, C: ComponentQuery<'b>>(&self) {
+        assert_query_does_not_alias::<C>();
+        let mut access = Access::default();
+        C::access(&mut access);
+        access.assert_declared_in(&self.access.components, self.system);
+    }
+}
@@ -0,0 +1,240 @@
+extern crate cgmath;
+#[macro_use]
+extern crate frunk;
+extern crate game;
+
+use cgmath::{InnerSpace, Point2, Vector2};
+use game::{
+    collision::{slide, Aabb},
+    components::{CameraComponent, DoorComponent, LocationComponent, TransformComponent},
+    playtest::{Command, Playtest},
+    resources::InputState,
+    snapshot::ComponentRegistry,
+    spatial::SpatialIndex,
+    systems::{seed_transforms, MOVE_SPEED},
+    timestep::STEP_MILLIS,
+    Map, Tile, With, World,
+};
+use std::path::Path;
+
+/// The radius of the player, as in their prefab.
+const RADIUS: f32 = 0.15;
+
+/// Checks that pushing into a corner comes to rest touching both walls, without jittering.
+#[test]
+fn corners_are_stable() {
+    let boxes = walls("3 3\n000\n0S0\n000\n");
+    for &(dx, dz) in &[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+        let path = walk(&boxes, Point2::new(1.5, 1.5), Vector2::new(dx, dz), 500);
+        let end = path[path.len() - 1];
+        let corner = Point2::new(1.5 + dx * (1.5 - RADIUS), 1.5 + dz * (1.5 - RADIUS));
+        assert!(
+            (end - corner).magnitude() < 1e-4,
+            "{:?} != {:?}",
+            end,
+            corner
+        );
+        for pos in &path[path.len() - 100..] {
+            assert!((*pos - end).magnitude() < 1e-5, "{:?} != {:?}", pos, end);
+        }
+    }
+}
+
+/// Checks that walking along a one-tile corridor while pushing into one of its walls slides along
+/// it at full speed, without catching on the seams between the wall tiles.
+#[test]
+fn one_tile_corridors_slide() {
+    let boxes = walls("6 3\nWWWWWW\n000000\nWWWWWW\n");
+    let step = MOVE_SPEED * STEP_MILLIS as f32 / 1000.0;
+
+    // Straight along a wall, touching it.
+    let path = walk(
+        &boxes,
+        Point2::new(0.5, 1.0 + RADIUS),
+        Vector2::unit_x(),
+        300,
+    );
+    for (i, pos) in path.iter().enumerate() {
+        let x = (0.5 + step * i as f32).min(6.0 - RADIUS);
+        assert!((pos.x - x).abs() < 1e-3, "{:?} isn't at x = {}", pos, x);
+        assert!(
+            (pos.y - (1.0 + RADIUS)).abs() < 1e-4,
+            "{:?} left the wall",
+            pos
+        );
+    }
+
+    // At an angle into the walls, on either side, which only the move along them survives.
+    for &dz in &[-0.5, 0.5] {
+        let direction = Vector2::new(1.0, dz);
+        let step_x = step * direction.normalize().x;
+        let path = walk(&boxes, Point2::new(0.5, 1.5), direction, 600);
+        for pair in path.windows(2) {
+            let moved = pair[1].x - pair[0].x;
+            assert!(moved >= 0.0, "{:?} went backwards", pair);
+            assert!(moved < step_x + 1e-5, "{:?} was pushed along", pair);
+            assert!(!overlaps(&boxes, pair[1]), "{:?} is in a wall", pair[1]);
+        }
+        let end = path[path.len() - 1];
+        assert!(
+            (end.x - (6.0 - RADIUS)).abs() < 1e-4,
+            "{:?} didn't reach the end",
+            end
+        );
+    }
+}
+
+/// Checks that a doorway can be walked through from beside it, sliding along the wall and around
+/// the doorway's corner, and that walking straight into the corner stops dead.
+#[test]
+fn doorways_are_stable() {
+    let boxes = walls("3 3\n000\nW0W\n000\n");
+
+    let path = walk(&boxes, Point2::new(0.5, 0.5), Vector2::new(0.3, 1.0), 600);
+    for pos in &path {
+        assert!(!overlaps(&boxes, *pos), "{:?} is in a wall", pos);
+    }
+    let end = path[path.len() - 1];
+    assert!(end.y > 2.0, "{:?} didn't get through the doorway", end);
+
+    let path = walk(&boxes, Point2::new(1.0, 0.5), Vector2::unit_y(), 300);
+    let end = path[path.len() - 1];
+    assert!(
+        (end - Point2::new(1.0, 1.0 - RADIUS)).magnitude() < 1e-4,
+        "{:?}",
+        end
+    );
+    for pos in &path[path.len() - 100..] {
+        assert!((*pos - end).magnitude() < 1e-5, "{:?} != {:?}", pos, end);
+    }
+}
+
+/// Checks that a locked door stops the player flush against it, and that once it's unlocked and
+/// sinking into the floor, they walk through it.
+#[test]
+fn sinking_doors_block_until_unlocked() {
+    let mut playtest = play("5 1\nS0A0G\n");
+    playtest.run(Command::Turn(90.0)).unwrap();
+    walk_forward(&mut playtest, 200);
+    let x = camera(&playtest).xyz.x;
+    assert!((x - (2.0 - RADIUS)).abs() < 1e-3, "stopped at x = {}", x);
+
+    let mut playtest = play("5 1\nSaA0G\n");
+    playtest.run(Command::Turn(90.0)).unwrap();
+    walk_forward(&mut playtest, 250);
+    playtest.run(Command::ExpectUnlocked('A')).unwrap();
+    let x = camera(&playtest).xyz.x;
+    assert!(x > 3.0, "stopped at x = {}", x);
+
+    // The door is still sinking when the player walks through it.
+    let world = playtest.state().world().unwrap();
+    let (_, hlist_pat![_, door]) = world
+        .single::<Hlist![With<DoorComponent>, &LocationComponent]>()
+        .unwrap();
+    assert!(door.xyz.y > -0.5, "the door is at y = {}", door.xyz.y);
+}
+
+/// Checks that everything is in the spatial index as soon as a map is built, and once it's been
+/// saved and loaded again, rather than only after the first steps.
+#[test]
+fn walls_are_indexed_from_the_start() {
+    let maps = Path::new(env!("CARGO_MANIFEST_DIR")).join("maps");
+    let (_, world) = World::from_map("4 1\nWSWG\n".parse().unwrap(), maps).unwrap();
+    assert_indexed(&world);
+
+    let registry = ComponentRegistry::default();
+    let mut loaded = World::from_snapshot(world.snapshot(&registry).unwrap(), &registry).unwrap();
+    seed_transforms(&mut loaded);
+    assert_indexed(&loaded);
+}
+
+/// Checks that every entity with a location has a transform and is in the spatial index, and
+/// that the index finds the same things near the player as checking every entity does.
+fn assert_indexed(world: &World) {
+    let located = world.iter::<Hlist![With<LocationComponent>]>().count();
+    let transformed = world.iter::<Hlist![With<TransformComponent>]>().count();
+    let index = world.resource::<SpatialIndex>().unwrap();
+    assert_eq!((transformed, index.len()), (located, located));
+
+    let (_, hlist_pat![_, camera]) = world
+        .single::<Hlist![With<CameraComponent>, &LocationComponent]>()
+        .unwrap();
+    let mut near = world
+        .iter::<Hlist![&TransformComponent]>()
+        .filter(|(_, hlist_pat![transform])| {
+            (transform.position() - camera.xyz).magnitude() < 1.0 + transform.radius()
+        })
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    near.sort();
+    assert!(near.len() > 2);
+    assert_eq!(index.query_radius(camera.xyz, 1.0), near);
+}
+
+/// Returns boxes for the walls of a map, including its border.
+fn walls(map: &str) -> Vec<Aabb> {
+    let map: Map = map.parse().unwrap();
+    let (width, height) = (map.dims.0 as isize, map.dims.1 as isize);
+    let mut boxes = Vec::new();
+    for z in -1..=height {
+        for x in -1..=width {
+            let inside = x >= 0 && z >= 0 && x < width && z < height;
+            if !inside || map.tile(x as usize, z as usize) == Some(Tile::Wall) {
+                let center = Point2::new(x as f32 + 0.5, z as f32 + 0.5);
+                boxes.push(Aabb::around(center, 1.0));
+            }
+        }
+    }
+    boxes
+}
+
+/// Moves the player from a point for a number of steps at `MOVE_SPEED`, returning where they are
+/// after each.
+fn walk(
+    boxes: &[Aabb],
+    start: Point2<f32>,
+    direction: Vector2<f32>,
+    steps: usize,
+) -> Vec<Point2<f32>> {
+    let motion = direction.normalize() * MOVE_SPEED * STEP_MILLIS as f32 / 1000.0;
+    let mut path = vec![start];
+    for _ in 0..steps {
+        let pos = path[path.len() - 1];
+        path.push(slide(pos, motion, RADIUS, boxes));
+    }
+    path
+}
+
+/// Returns whether the player overlaps any of the boxes, beyond rounding errors.
+fn overlaps(boxes: &[Aabb], pos: Point2<f32>) -> bool {
+    boxes
+        .iter()
+        .any(|aabb| aabb.push_out(pos, RADIUS - 1e-4).is_some())
+}
+
+/// Loads a map and starts playing it.
+fn play(map: &str) -> Playtest {
+    let maps = Path::new(env!("CARGO_MANIFEST_DIR")).join("maps");
+    let (_, world) = World::from_map(map.parse().unwrap(), maps).unwrap();
+    Playtest::new(world).unwrap()
+}
+
+/// Walks the player forwards for a number of steps.
+fn walk_forward(playtest: &mut Playtest, steps: usize) {
+    for _ in 0..steps {
+        let input = InputState {
+            move_forward: 1.0,
+            ..InputState::default()
+        };
+        playtest.step(input).unwrap();
+    }
+}
+
+/// Returns the location of the player.
+fn camera(playtest: &Playtest) -> LocationComponent {
+    let world = playtest.state().world().unwrap();
+    let (_, hlist_pat![_, &loc]) = world
+        .single::<Hlist![With<CameraComponent>, &LocationComponent]>()
+        .unwrap();
+    loc
+}
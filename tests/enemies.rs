@@ -0,0 +1,141 @@
+extern crate cgmath;
+#[macro_use]
+extern crate frunk;
+extern crate game;
+
+use cgmath::Point3;
+use game::{
+    components::{CameraComponent, EnemyComponent, LocationComponent},
+    playtest::Playtest,
+    resources::InputState,
+    schedule::Schedule,
+    systems::{add_simulation_systems, CAUGHT_MILLIS},
+    timestep::STEP_MILLIS,
+    Enemy, Map, State, With, World,
+};
+use std::path::Path;
+
+/// Checks that once an enemy catches the player, neither of them moves until the game goes on.
+#[test]
+fn nothing_moves_while_caught() {
+    let world = load("5 1\nS000G\n", &[&[(3, 0)]]);
+    let mut playtest = Playtest::new(world).unwrap();
+    playtest
+        .run_script(&"expect_caught(1000)".parse().unwrap())
+        .unwrap();
+    assert!(playtest.state().is_lost());
+
+    let caught = positions(playtest.state());
+    let mut steps = 0;
+    while playtest.state().is_lost() {
+        assert_eq!(positions(playtest.state()), caught);
+        let input = InputState {
+            move_forward: 1.0,
+            ..InputState::default()
+        };
+        playtest.step(input).unwrap();
+        steps += 1;
+    }
+    assert!(
+        steps >= CAUGHT_MILLIS / STEP_MILLIS,
+        "only lost for {} steps",
+        steps
+    );
+}
+
+/// Checks that an enemy can't walk through a locked door to its next waypoint, but can once
+/// there's no door in the way.
+#[test]
+fn locked_doors_block_enemies() {
+    for &(map, blocked) in &[
+        ("SW000\n0W000\nGA00a\n", true),
+        ("SW000\n0W000\nG000a\n", false),
+    ] {
+        let world = load(&format!("5 3\n{}", map), &[&[(3, 1), (0, 1)]]);
+        let mut playtest = Playtest::new(world).unwrap();
+        let mut furthest = f32::INFINITY;
+        for _ in 0..3000 / STEP_MILLIS {
+            playtest.step(InputState::default()).unwrap();
+            furthest = furthest.min(positions(playtest.state()).0.x);
+        }
+        assert_eq!(
+            furthest >= 2.0,
+            blocked,
+            "the enemy got to x = {}",
+            furthest
+        );
+    }
+}
+
+/// Checks that an enemy which loses sight of the player goes to where it last saw them, then
+/// goes back to its patrol.
+#[test]
+fn enemies_go_back_to_their_patrol() {
+    let world = load("6 3\nS0000G\nWWWWWW\n000000\n", &[&[(5, 2), (4, 2)]]);
+    let mut schedule = add_simulation_systems(Schedule::builder()).build().unwrap();
+    let mut state = State::Playing(world);
+
+    // Show the player to the enemy for a step, then hide them on the other side of the wall.
+    move_player(&mut state, 2.5, 2.5);
+    step(&mut schedule, &mut state);
+    assert_eq!(enemy(&state).last_seen, Some((2, 2)));
+    move_player(&mut state, 0.5, 0.5);
+
+    let mut furthest = f32::INFINITY;
+    for _ in 0..5000 / STEP_MILLIS {
+        step(&mut schedule, &mut state);
+        furthest = furthest.min(positions(&state).0.x);
+    }
+    assert!(furthest < 3.0, "the enemy only got to x = {}", furthest);
+    assert_eq!(enemy(&state).last_seen, None);
+    let x = positions(&state).0.x;
+    assert!(x >= 4.5 - 1e-3, "the enemy is at x = {}, off its patrol", x);
+    assert!(!state.is_lost());
+}
+
+/// Loads a map, adding enemies with the given patrols.
+fn load(map: &str, patrols: &[&[(usize, usize)]]) -> World {
+    let mut map: Map = map.parse().unwrap();
+    for patrol in patrols {
+        map.enemies.push(Enemy {
+            patrol: patrol.to_vec(),
+        });
+    }
+    let maps = Path::new(env!("CARGO_MANIFEST_DIR")).join("maps");
+    let (_, world) = World::from_map(map, maps).unwrap();
+    world
+}
+
+/// Runs a single step of the simulation, without any input.
+fn step(schedule: &mut Schedule, state: &mut State) {
+    state.world_mut().unwrap().update_events();
+    schedule.run(state, STEP_MILLIS);
+}
+
+/// Puts the player in the middle of the floor at the given point.
+fn move_player(state: &mut State, x: f32, z: f32) {
+    let world = state.world_mut().unwrap();
+    let (camera, _) = world.single::<Hlist![With<CameraComponent>]>().unwrap();
+    let loc = world.get_mut::<LocationComponent>(camera).unwrap();
+    loc.xyz.x = x;
+    loc.xyz.z = z;
+}
+
+/// Returns the enemy.
+fn enemy(state: &State) -> EnemyComponent {
+    let world = state.world().unwrap();
+    let (_, hlist_pat![enemy]) = world.single::<Hlist![&EnemyComponent]>().unwrap();
+    enemy.clone()
+}
+
+/// Returns the positions of the enemy and the player.
+fn positions(state: &State) -> (Point3<f32>, Point3<f32>) {
+    let world = state.world().unwrap();
+    let (_, hlist_pat![_, &enemy]) = world
+        .single::<Hlist![With<EnemyComponent>, &LocationComponent]>()
+        .unwrap();
+    let (_, hlist_pat![_, &camera]) = world
+        .single::<Hlist![With<CameraComponent>, &LocationComponent]>()
+        .unwrap();
+    (enemy.xyz, camera.xyz)
+}
@@ -0,0 +1,94 @@
+extern crate game;
+
+use game::{
+    generate::{Algorithm, Generator, Rng},
+    Map, Tile,
+};
+
+/// Checks that writing any map the assignment's format can hold and parsing it back gives the
+/// same map, for many random maps.
+#[test]
+fn legacy_format_round_trips() {
+    let mut rng = Rng::new(0x5607_5607);
+    for _ in 0..1000 {
+        let map = random_map(&mut rng);
+        assert_eq!(map.legacy_losses(), Vec::<String>::new(), "\n{}", map);
+        check_round_trip(&map);
+    }
+}
+
+/// Checks the same for generated mazes, which have doors as well as walls.
+#[test]
+fn generated_maps_round_trip() {
+    for seed in 0..20 {
+        for &algorithm in &Algorithm::ALL {
+            let generator = Generator {
+                dims: (17, 11),
+                algorithm,
+                keys: 6,
+                seed,
+            };
+            let mut map = generator.generate().unwrap();
+            map.keys.sort_by_key(|&(x, y, _)| (y, x));
+            assert_eq!(map.legacy_losses(), Vec::<String>::new(), "\n{}", map);
+            check_round_trip(&map);
+        }
+    }
+}
+
+/// Checks that parts of a map which can't be written are reported.
+#[test]
+fn losses_are_reported() {
+    let mut map = "3 1\nS0G\n".parse::<Map>().unwrap();
+    assert!(map.legacy_losses().is_empty());
+
+    map.keys.push((0, 0, 'a'));
+    map.material_wall = Some("wall.mtl".into());
+    assert_eq!(
+        map.legacy_losses(),
+        vec![
+            "The wall material wall.mtl can't be set",
+            "The key 'a' at (0, 0) shares its tile with something else",
+        ]
+    );
+}
+
+fn check_round_trip(map: &Map) {
+    let written = map.to_string();
+    let parsed = written.parse::<Map>().unwrap();
+    assert_eq!(&parsed, map, "\n{}", written);
+}
+
+/// Returns a random map with a start, a goal, and keys on distinct empty tiles.
+fn random_map(rng: &mut Rng) -> Map {
+    let dims = (1 + rng.below(12), 2 + rng.below(11));
+    let mut tiles = (0..dims.0 * dims.1)
+        .map(|_| match rng.below(10) {
+            0..=4 => Tile::Empty,
+            5..=7 => Tile::Wall,
+            _ => Tile::Door((b'A' + rng.below(5) as u8) as char),
+        }).collect::<Vec<_>>();
+
+    // Shuffle the tiles' indices, then clear the first few for the start, goal, and keys.
+    let mut free = (0..tiles.len()).collect::<Vec<_>>();
+    rng.shuffle(&mut free);
+    let key_count = rng.below(free.len() - 1).min(6);
+    for &i in &free[..2 + key_count] {
+        tiles[i] = Tile::Empty;
+    }
+    let pos = |i: usize| (i % dims.0, i / dims.0);
+    let mut keys = free[2..2 + key_count]
+        .iter()
+        .map(|&i| (pos(i).0, pos(i).1, (b'a' + rng.below(5) as u8) as char))
+        .collect::<Vec<_>>();
+    keys.sort_by_key(|&(x, y, _)| (y, x));
+
+    Map {
+        dims,
+        tiles,
+        start: pos(free[0]),
+        goal: pos(free[1]),
+        keys,
+        ..Map::default()
+    }
+}
@@ -0,0 +1,105 @@
+extern crate game;
+
+use game::{
+    lint::lint,
+    playtest::{script_path, Playtest},
+    replay::checksum,
+    solve::{solve, Verdict},
+    util::read_file_and_unjson,
+    Map,
+};
+use std::{
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+/// Checks that every map in the `maps` directory is completed by its playtest script.
+#[test]
+fn every_map_can_be_completed() {
+    let mut failures = Vec::new();
+    for path in map_paths() {
+        match Playtest::run_files(&path, script_path(&path)) {
+            Ok(ref playtest) if playtest.state().is_done() => {}
+            Ok(_) => failures.push(format!(
+                "{}: the playtest finished without reaching the goal",
+                path.display()
+            )),
+            Err(err) => failures.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Checks that playing each map's script twice leaves the world in the same state both times,
+/// enemies and all.
+#[test]
+fn playtests_are_deterministic() {
+    let mut failures = Vec::new();
+    for path in map_paths() {
+        let mut checksums = Vec::new();
+        for _ in 0..2 {
+            let playtest = Playtest::run_files(&path, script_path(&path)).unwrap();
+            let world = playtest.state().world().unwrap();
+            checksums.push((checksum(world), playtest.ticks()));
+        }
+        if checksums[0] != checksums[1] {
+            failures.push(format!(
+                "{}: the playtests ended differently, {:?}",
+                path.display(),
+                checksums
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Checks that no map in the `maps` directory has any issues, even warnings.
+#[test]
+fn every_map_lints_cleanly() {
+    let mut failures = Vec::new();
+    for path in map_paths() {
+        match Map::load(&path) {
+            Ok(map) => failures.extend(
+                lint(&map)
+                    .into_iter()
+                    .map(|issue| format!("{}: {}", path.display(), issue)),
+            ),
+            Err(err) => failures.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Checks that the solver finds a way through every map in the `maps` directory.
+#[test]
+fn every_map_is_solvable() {
+    let mut failures = Vec::new();
+    for path in map_paths() {
+        match Map::load(&path).and_then(|map| solve(&map)) {
+            Ok(Verdict::Solvable(_)) => {}
+            Ok(Verdict::Unsolvable(why)) => failures.push(format!("{}: {}", path.display(), why)),
+            Err(err) => failures.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Returns the paths of the maps in the `maps` directory, i.e. the `.map` files and the `.json`
+/// files which are maps rather than, say, prefabs.
+fn map_paths() -> Vec<PathBuf> {
+    let maps_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("maps");
+    let mut paths = Vec::new();
+    for entry in read_dir(&maps_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let is_map = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("map") => true,
+            Some("json") => read_file_and_unjson::<_, Map>(&path).is_ok(),
+            _ => false,
+        };
+        if is_map {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+}
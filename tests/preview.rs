@@ -0,0 +1,80 @@
+extern crate game;
+
+use game::{preview::Preview, Map};
+
+/// A map with a wall, a door and its key, and the goal.
+const MAP: &str = "4 2\nSaAG\n0WW0\n";
+
+/// Points in the preview, in tiles from its top-left corner, and the colors they should be. The
+/// map's tiles start at (1, 1), after the border.
+const EXPECTED: &[((f32, f32), &str)] = &[
+    // The border, and the wall tiles.
+    ((0.5, 0.5), "#404040"),
+    ((2.5, 2.5), "#404040"),
+    ((3.5, 2.5), "#404040"),
+    // An empty tile.
+    ((1.5, 2.5), "#cccccc"),
+    // The door, on the floor.
+    ((3.5, 1.5), "#ff0000"),
+    ((3.05, 1.05), "#cccccc"),
+    // The key, in the inverse of its door's color.
+    ((2.5, 1.5), "#00ffff"),
+    ((2.1, 1.1), "#cccccc"),
+    // The goal's checkered square.
+    ((4.35, 1.35), "#ffffff"),
+    ((4.65, 1.35), "#000000"),
+    ((4.35, 1.65), "#000000"),
+    ((4.65, 1.65), "#ffffff"),
+    ((4.05, 1.05), "#cccccc"),
+];
+
+/// Checks that each kind of tile is drawn in the right color in PNGs.
+#[test]
+fn png_colors() {
+    let map: Map = MAP.parse().unwrap();
+    let scale = 20;
+    let png = Preview::new(&map, None).to_png(scale);
+    for &((x, y), color) in EXPECTED {
+        let pixel = png
+            .get_pixel((x * scale as f32) as u32, (y * scale as f32) as u32)
+            .data;
+        let actual = format!("#{:02x}{:02x}{:02x}", pixel[0], pixel[1], pixel[2]);
+        assert_eq!((actual.as_str(), pixel[3]), (color, 255), "at {:?}", (x, y));
+    }
+}
+
+/// Checks the same for SVGs, by finding the last shape drawn over each point.
+#[test]
+fn svg_colors() {
+    let map: Map = MAP.parse().unwrap();
+    let svg = Preview::new(&map, None).to_svg(20);
+    for &((x, y), color) in EXPECTED {
+        let actual = svg.lines().filter(|line| covers(line, x, y)).last();
+        let fill = actual.map(|line| attr(line, "fill"));
+        assert_eq!(fill, Some(color), "at {:?}", (x, y));
+    }
+}
+
+/// Returns whether an SVG element is a `<rect>` or `<circle>` which covers the point.
+fn covers(element: &str, x: f32, y: f32) -> bool {
+    let num = |name| attr(element, name).parse::<f32>().unwrap();
+    if element.trim_start().starts_with("<rect") {
+        let (left, top) = (num("x"), num("y"));
+        left <= x && x < left + num("width") && top <= y && y < top + num("height")
+    } else if element.trim_start().starts_with("<circle") {
+        (x - num("cx")).hypot(y - num("cy")) < num("r")
+    } else {
+        false
+    }
+}
+
+/// Returns the value of an attribute of an SVG element.
+fn attr<'a>(element: &'a str, name: &str) -> &'a str {
+    let start = element
+        .find(&format!(" {}=\"", name))
+        .unwrap_or_else(|| panic!("{} has no {} attribute", element, name))
+        + name.len()
+        + 3;
+    let len = element[start..].find('"').unwrap();
+    &element[start..start + len]
+}
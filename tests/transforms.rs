@@ -0,0 +1,71 @@
+extern crate cgmath;
+#[macro_use]
+extern crate frunk;
+extern crate game;
+
+use cgmath::Point3;
+use game::{
+    components::{LocationComponent, ParentComponent, TransformComponent},
+    schedule::Schedule,
+    systems::TransformSystem,
+    Changed, Entity, State, World,
+};
+
+/// Checks that moving an entity moves its descendants, that detaching one puts it back at its own
+/// location, and that nothing else is recomputed.
+#[test]
+fn only_moved_entities_are_updated() {
+    let mut world = World::default();
+    let root = world.new_entity(hlist![LocationComponent::pos(1.0, 0.0, 0.0)]);
+    let child = world.new_entity(hlist![LocationComponent::pos(0.0, 1.0, 0.0)]);
+    let grandchild = world.new_entity(hlist![LocationComponent::pos(0.0, 0.0, 1.0)]);
+    let other = world.new_entity(hlist![LocationComponent::pos(5.0, 0.0, 0.0)]);
+    world.set_parent(child, root).unwrap();
+    world.set_parent(grandchild, child).unwrap();
+
+    let mut schedule = Schedule::builder()
+        .parallel_system("transforms", TransformSystem::default())
+        .build()
+        .unwrap();
+    let mut state = State::Playing(world);
+    schedule.run(&mut state, 8);
+    schedule.run(&mut state, 8);
+    assert_eq!(position(&state, grandchild), Point3::new(1.0, 1.0, 1.0));
+    assert_eq!(position(&state, other), Point3::new(5.0, 0.0, 0.0));
+
+    {
+        let world = state.world_mut().unwrap();
+        world.get_mut::<LocationComponent>(root).unwrap().xyz.x = 2.0;
+        world.start_system::<Observer>();
+        world.finish_system::<Observer>();
+    }
+    schedule.run(&mut state, 8);
+    assert_eq!(position(&state, grandchild), Point3::new(2.0, 1.0, 1.0));
+    {
+        let world = state.world_mut().unwrap();
+        world.start_system::<Observer>();
+        let changed = world
+            .iter::<Hlist![Changed<TransformComponent>]>()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        world.finish_system::<Observer>();
+        assert_eq!(changed, vec![root, child, grandchild]);
+
+        world.remove::<ParentComponent>(child);
+    }
+    schedule.run(&mut state, 8);
+    assert_eq!(position(&state, child), Point3::new(0.0, 1.0, 0.0));
+    assert_eq!(position(&state, grandchild), Point3::new(0.0, 1.0, 1.0));
+}
+
+/// A system which only looks for changes.
+struct Observer;
+
+/// Returns the position of an entity in world space.
+fn position(state: &State, entity: Entity) -> Point3<f32> {
+    let world = state.world().unwrap();
+    world
+        .get_one::<TransformComponent>(entity)
+        .unwrap()
+        .position()
+}